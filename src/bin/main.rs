@@ -1,32 +1,45 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
 use colored::Colorize;
 use futures::future;
 use futures_util::{SinkExt, StreamExt};
+use marketplace::binance::data_stream::MarketStream;
+use marketplace::binance::download::DownloadParams;
+use marketplace::binance::import::{import_archives, SyntheticBook};
 use marketplace::binance::Binance;
 use marketplace::*;
+use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
 use state::State;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
-use strategy::{scalping::ScalpingStrategy, Strategy};
+use strategy::Strategy;
 use ticker::Ticker;
-use tokio::fs::OpenOptions;
-use tokio::io::AsyncWriteExt;
 use tokio::select;
 use tokio::sync::RwLock;
+use tokio::task::JoinHandle;
 use tokio_tungstenite::connect_async;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+use trading_bot::credentials::CredentialStore;
+use trading_bot::marketplace::candle::{start_candle_aggregator, CandleInterval, CandleSource};
+use trading_bot::marketplace::replay::check::{check_data, CheckParams};
+use trading_bot::marketplace::replay::source::TimeRange;
 use trading_bot::marketplace::replay::ReplayMarketplace;
 use trading_bot::marketplace::simulation::{SimulationMarketplace, SimulationSource};
+use trading_bot::marketplace::store::{start_candle_store, CandleStore};
+use trading_bot::marketplace::synthetic::{generate, Jumps, PriceProcess, Regime, Scenario};
 use trading_bot::marketplace::MarketplaceDataStream;
 use trading_bot::order::OrderStatus;
-use trading_bot::strategy::{StrategyAction, StrategyEvent};
+use trading_bot::portfolio::Asset;
+use trading_bot::recorder::{start_recorder, RecordedEvent, RecorderConfig};
+use trading_bot::strategy::registry::{AnyStrategy, StrategyConfigs};
+use trading_bot::strategy::{StrategyAction, StrategyCommand, StrategyEvent};
+use trading_bot::utils::parse_time;
 use tungstenite::Message;
 
-use trading_bot::strategy::scalping::ScalpingParams;
 use trading_bot::tui::app::App;
 use trading_bot::*;
 
@@ -34,8 +47,13 @@ use trading_bot::*;
 struct Args {
     #[arg(long)]
     replay_path: Option<PathBuf>,
-    #[arg(long)]
-    store_path: Option<PathBuf>,
+    // closed candles kept for the strategies warm-up
+    #[arg(long, env = "CANDLE_STORE_PATH", default_value = "data/candles")]
+    store_path: PathBuf,
+    #[arg(long, env = "CREDENTIALS_PATH")]
+    credentials_path: Option<PathBuf>,
+    #[arg(long, env = "CREDENTIALS_ACCOUNT", default_value = "default")]
+    account: String,
 
     #[command(subcommand)]
     command: Option<Commands>,
@@ -53,8 +71,34 @@ enum Commands {
         replay_path: Option<PathBuf>,
         #[arg(long)]
         real: bool,
+        // build the higher interval candles from "candles" (1m klines) or "trades",
+        // which streams trades instead of klines
+        #[arg(long, default_value = "candles")]
+        candle_source: CandleSource,
+        // kline, depth, trade, aggTrade, bookTicker
+        #[arg(long, value_delimiter = ',', default_value = "kline,depth")]
+        streams: Vec<MarketStream>,
+        // events recorded in --replay-path : trade, aggTrade, candle, book, bookTicker, order, portfolio, balance, stream
+        #[arg(
+            long,
+            value_delimiter = ',',
+            default_value = "trade,aggTrade,candle,book,bookTicker"
+        )]
+        record_events: Vec<RecordedEvent>,
+        // record at most one book per ticker every N ms
+        #[arg(long)]
+        record_book_interval: Option<u64>,
+        // gzip the recordings of the previous days
+        #[arg(long)]
+        record_compress: bool,
         #[arg(long, default_value = "USDC")]
         quote: String,
+        // strategy of the tickers missing from --strategy-config
+        #[arg(long, default_value = "scalping")]
+        strategy: String,
+        // json list of {"symbol": "BTCUSDC", "strategy": "scalping", "params": {..}}
+        #[arg(long)]
+        strategy_config: Option<PathBuf>,
     },
     Replay {
         #[arg(long, value_delimiter = ',', default_value = "BTCUSDC")]
@@ -63,12 +107,27 @@ enum Commands {
         server_address: String,
         #[arg(long)]
         no_server: bool,
-        #[arg(long)]
-        replay_path: PathBuf,
+        // recordings (files or directories) merged by time
+        #[arg(long, value_delimiter = ',', required = true)]
+        replay_path: Vec<PathBuf>,
+        // date (2025-01-31), RFC 3339 datetime or timestamp in ms
+        #[arg(long, value_parser = parse_time)]
+        from: Option<u64>,
+        #[arg(long, value_parser = parse_time)]
+        to: Option<u64>,
         #[arg(long, default_value = "USDC")]
         quote: String,
         #[arg(long, default_value = "500")]
         interval: u64,
+        // play at N times the recorded pace instead of a fixed interval between events
+        #[arg(long)]
+        speed: Option<f64>,
+        #[arg(long, default_value = "candles")]
+        candle_source: CandleSource,
+        #[arg(long, default_value = "scalping")]
+        strategy: String,
+        #[arg(long)]
+        strategy_config: Option<PathBuf>,
     },
     Tui {
         #[arg(long, value_delimiter = ',', default_value = "BTCUSDC")]
@@ -79,6 +138,126 @@ enum Commands {
         quote: String,
     },
     Test,
+    // Download 1m klines and aggregated trades into a replay dataset
+    Download {
+        #[arg(long, value_delimiter = ',', default_value = "BTCUSDC")]
+        symbol: Vec<String>,
+        // date (2025-01-31), RFC 3339 datetime or timestamp in ms
+        #[arg(long, value_parser = parse_time)]
+        from: u64,
+        #[arg(long, value_parser = parse_time)]
+        to: u64,
+        #[arg(long)]
+        replay_path: PathBuf,
+        #[arg(long)]
+        no_trades: bool,
+        // add top of book events built from the trades, needed to fill orders in replays
+        #[arg(long)]
+        synthetic_book: bool,
+        #[arg(long, default_value = "1000")]
+        book_interval: u64,
+        #[arg(long, default_value = "0.0001")]
+        book_spread: Decimal,
+    },
+    // Convert Binance public data archives (kline, trades and aggTrades csv) into a replay dataset
+    Import {
+        // csv files or directories
+        #[arg(long, value_delimiter = ',', required = true)]
+        input: Vec<PathBuf>,
+        #[arg(long)]
+        replay_path: PathBuf,
+        // add top of book events built from the trades
+        #[arg(long)]
+        synthetic_book: bool,
+        #[arg(long, default_value = "1000")]
+        book_interval: u64,
+        #[arg(long, default_value = "0.0001")]
+        book_spread: Decimal,
+    },
+    // Report gaps, ordering violations, duplicates and invalid books of a replay dataset
+    CheckData {
+        // recordings (files or directories)
+        #[arg(long, value_delimiter = ',', required = true)]
+        replay_path: Vec<PathBuf>,
+        // longest time without an event of a stream before it is reported, in ms
+        #[arg(long, default_value = "60000")]
+        max_gap: u64,
+        // write a cleaned copy (sorted, without duplicates nor invalid books) in this directory
+        #[arg(long)]
+        clean_path: Option<PathBuf>,
+        #[arg(long, default_value = "10000")]
+        reorder_window: u64,
+    },
+    // Generate a synthetic replay dataset (trades, 1m candles and books, warm-up candles)
+    Generate {
+        #[arg(long)]
+        replay_path: PathBuf,
+        // json scenario, the other options are ignored (see synthetic::Scenario)
+        #[arg(long)]
+        scenario: Option<PathBuf>,
+        #[arg(long, value_delimiter = ',', default_value = "BTCUSDC")]
+        symbol: Vec<String>,
+        // date (2025-01-31), RFC 3339 datetime or timestamp in ms
+        #[arg(long, value_parser = parse_time, default_value = "2025-01-01")]
+        from: u64,
+        #[arg(long, value_parser = parse_time, default_value = "2025-01-02")]
+        to: u64,
+        // gbm, mean-reverting or regimes (range, up, down and crash)
+        #[arg(long, default_value = "gbm")]
+        process: String,
+        #[arg(long, default_value = "100000")]
+        price: f64,
+        // per day, on the log price
+        #[arg(long, default_value = "0")]
+        drift: f64,
+        #[arg(long, default_value = "0.03")]
+        volatility: f64,
+        // pull toward --price per day, for mean-reverting
+        #[arg(long, default_value = "5")]
+        reversion: f64,
+        // jumps per day, with a mean log size (-0.1 for a 10% flash crash)
+        #[arg(long, default_value = "0")]
+        jump_rate: f64,
+        #[arg(long, default_value = "-0.1")]
+        jump_size: f64,
+        // recovery time of a jump in ms, 0 keeps the new price
+        #[arg(long, default_value = "0")]
+        jump_recovery: u64,
+        #[arg(long, default_value = "0.0001")]
+        spread: f64,
+        #[arg(long, default_value = "5")]
+        depth: usize,
+        // ms between two books
+        #[arg(long, default_value = "1000")]
+        step: u64,
+        // ms of candles before --from, the history the replayed strategies start from
+        #[arg(long, default_value = "172800000")]
+        warmup: u64,
+        #[arg(long, default_value = "0")]
+        seed: u64,
+    },
+    Credentials {
+        #[command(subcommand)]
+        command: CredentialsCommands,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum CredentialsCommands {
+    Add {
+        account: String,
+        #[arg(long)]
+        api_key: Option<String>,
+        #[arg(long)]
+        secure_api_key: Option<String>,
+        // Ed25519 private key PEM file, its content is stored
+        #[arg(long)]
+        private_key: Option<PathBuf>,
+    },
+    List,
+    Remove {
+        account: String,
+    },
 }
 
 #[tokio::main]
@@ -98,34 +277,108 @@ async fn main() {
 
     let args = Args::parse();
 
-    match args.command {
-        Some(Commands::AccountInfo) => {
-            let _ = run_account_info().await;
+    if let Some(Commands::Credentials { command }) = args.command {
+        if let Err(err) = run_credentials(args.credentials_path, command) {
+            error!("{err}");
         }
-        Some(Commands::Start {
-            quote,
-            symbol,
-            replay_path,
-            server_address,
-            real,
-        }) => {
+        return;
+    }
+
+    let candle_store = CandleStore::new(args.store_path);
+    let marketplace = match &args.command {
+        Some(Commands::Tui { .. })
+        | Some(Commands::Download { .. })
+        | Some(Commands::Import { .. })
+        | Some(Commands::CheckData { .. })
+        | Some(Commands::Generate { .. })
+        | None => None,
+        _ => match load_marketplace(args.credentials_path.as_ref(), &args.account) {
+            Ok(marketplace) => Some(marketplace.with_candle_store(candle_store.clone())),
+            Err(err) => {
+                error!("Could not load credentials : {err}");
+                return;
+            }
+        },
+    };
+
+    match (args.command, marketplace) {
+        (Some(Commands::AccountInfo), Some(marketplace)) => {
+            let _ = run_account_info(marketplace).await;
+        }
+        (
+            Some(Commands::Start {
+                quote,
+                symbol,
+                replay_path,
+                server_address,
+                real,
+                candle_source,
+                streams,
+                record_events,
+                record_book_interval,
+                record_compress,
+                strategy,
+                strategy_config,
+            }),
+            Some(marketplace),
+        ) => {
             let tickers: Vec<Ticker> = symbol.iter().flat_map(Ticker::try_from).collect();
-            let _ = run_start(quote, tickers, replay_path, server_address, real).await;
+            let Some(strategies) = strategy_configs(strategy, strategy_config) else {
+                return;
+            };
+            let _ = run_start(
+                marketplace.with_market_streams(candle_streams(streams, candle_source)),
+                quote,
+                tickers,
+                replay_path.map(|data_path| RecorderConfig {
+                    data_path,
+                    events: record_events,
+                    book_interval: record_book_interval,
+                    compress: record_compress,
+                }),
+                server_address,
+                real,
+                candle_source,
+                strategies,
+            )
+            .await;
         }
-        Some(Commands::Replay {
-            interval,
-            quote,
-            symbol,
-            replay_path,
-            server_address,
-            no_server,
-        }) => {
+        (
+            Some(Commands::Replay {
+                interval,
+                speed,
+                quote,
+                symbol,
+                replay_path,
+                from,
+                to,
+                server_address,
+                no_server,
+                candle_source,
+                strategy,
+                strategy_config,
+            }),
+            Some(marketplace),
+        ) => {
             let tickers: Vec<Ticker> = symbol.iter().flat_map(Ticker::try_from).collect();
+            let Some(strategies) = strategy_configs(strategy, strategy_config) else {
+                return;
+            };
+            // replays trade smaller targets, once per ticker
+            let strategies = strategies.with_defaults(
+                "scalping",
+                serde_json::json!({"target_profit": 0.3, "reentry_delay": 60, "session_count": 1}),
+            );
             let _ = run_replay(
+                marketplace,
                 interval,
+                candle_source,
                 quote,
                 tickers,
                 replay_path,
+                TimeRange { from, to },
+                speed,
+                strategies,
                 if no_server {
                     None
                 } else {
@@ -134,23 +387,253 @@ async fn main() {
             )
             .await;
         }
-        Some(Commands::Tui {
-            symbol,
-            quote,
-            server_address,
-        }) => {
+        (
+            Some(Commands::Tui {
+                symbol,
+                quote,
+                server_address,
+            }),
+            _,
+        ) => {
             let tickers: Vec<Ticker> = symbol.iter().flat_map(Ticker::try_from).collect();
             let _ = run_tui(quote, server_address, tickers).await;
         }
-        Some(Commands::Test) => {
-            run_test().await;
+        (
+            Some(Commands::Download {
+                symbol,
+                from,
+                to,
+                replay_path,
+                no_trades,
+                synthetic_book,
+                book_interval,
+                book_spread,
+            }),
+            _,
+        ) => {
+            let params = DownloadParams {
+                tickers: symbol.iter().flat_map(Ticker::try_from).collect(),
+                from,
+                to,
+                trades: !no_trades,
+                book: (synthetic_book && !no_trades).then_some(SyntheticBook {
+                    interval: book_interval,
+                    spread: book_spread,
+                }),
+            };
+            if let Err(err) = Binance::new().download(&params, &replay_path).await {
+                error!("Download failed : {err}");
+            }
+        }
+        (
+            Some(Commands::Import {
+                input,
+                replay_path,
+                synthetic_book,
+                book_interval,
+                book_spread,
+            }),
+            _,
+        ) => {
+            let book = synthetic_book.then_some(SyntheticBook {
+                interval: book_interval,
+                spread: book_spread,
+            });
+            match tokio::task::spawn_blocking(move || import_archives(&input, &replay_path, book))
+                .await
+            {
+                Ok(Ok(count)) => info!("Imported {} events", count),
+                Ok(Err(err)) => error!("Import failed : {err:?}"),
+                Err(err) => error!("Import failed : {err}"),
+            }
+        }
+        (
+            Some(Commands::CheckData {
+                replay_path,
+                max_gap,
+                clean_path,
+                reorder_window,
+            }),
+            _,
+        ) => {
+            let params = CheckParams {
+                max_gap,
+                clean_path,
+                reorder_window,
+            };
+            match tokio::task::spawn_blocking(move || check_data(&replay_path, &params)).await {
+                Ok(Ok(report)) => println!("{report}"),
+                Ok(Err(err)) => error!("Check failed : {err:?}"),
+                Err(err) => error!("Check failed : {err}"),
+            }
+        }
+        (
+            Some(Commands::Generate {
+                replay_path,
+                scenario,
+                symbol,
+                from,
+                to,
+                process,
+                price,
+                drift,
+                volatility,
+                reversion,
+                jump_rate,
+                jump_size,
+                jump_recovery,
+                spread,
+                depth,
+                step,
+                warmup,
+                seed,
+            }),
+            _,
+        ) => {
+            let scenario = match scenario {
+                Some(path) => match std::fs::read_to_string(&path)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|content| Ok(serde_json::from_str::<Scenario>(&content)?))
+                {
+                    Ok(scenario) => scenario,
+                    Err(err) => {
+                        error!("Could not read scenario {} : {err}", path.display());
+                        return;
+                    }
+                },
+                None => {
+                    let process = match process.as_str() {
+                        "gbm" => PriceProcess::Gbm { drift, volatility },
+                        "mean-reverting" => PriceProcess::MeanReverting {
+                            mean: price,
+                            reversion,
+                            volatility,
+                        },
+                        "regimes" => PriceProcess::Regimes {
+                            regimes: Regime::presets(),
+                        },
+                        other => {
+                            error!("Unknown process {other}");
+                            return;
+                        }
+                    };
+                    Scenario {
+                        tickers: symbol.iter().flat_map(Ticker::try_from).collect(),
+                        from,
+                        to,
+                        step,
+                        warmup,
+                        start_price: price,
+                        process,
+                        jumps: (jump_rate > 0.0).then_some(Jumps {
+                            rate: jump_rate,
+                            size: jump_size,
+                            size_volatility: jump_size.abs() / 4.0,
+                            recovery: jump_recovery,
+                        }),
+                        spread,
+                        depth,
+                        seed,
+                        ..Default::default()
+                    }
+                }
+            };
+            match tokio::task::spawn_blocking(move || generate(&scenario, &replay_path)).await {
+                Ok(Ok(count)) => info!("Generated {} events", count),
+                Ok(Err(err)) => error!("Generation failed : {err:?}"),
+                Err(err) => error!("Generation failed : {err}"),
+            }
+        }
+        (Some(Commands::Test), Some(marketplace)) => {
+            run_test(marketplace).await;
         }
         _ => {}
     }
 }
 
-async fn run_account_info() -> Result<()> {
-    let marketplace = Binance::new();
+// Strategies of the --strategy default and --strategy-config file, None when the file is invalid.
+fn strategy_configs(strategy: String, config_path: Option<PathBuf>) -> Option<StrategyConfigs> {
+    let configs = StrategyConfigs::new(strategy);
+    match config_path {
+        Some(path) => match configs.with_file(&path) {
+            Ok(configs) => Some(configs),
+            Err(err) => {
+                error!("{err:?}");
+                None
+            }
+        },
+        None => Some(configs),
+    }
+}
+
+// Binance client with secrets from the encrypted credentials file when configured,
+// from the environment otherwise.
+fn load_marketplace(credentials_path: Option<&PathBuf>, account: &str) -> Result<Binance> {
+    match credentials_path {
+        Some(path) => {
+            let passphrase = credentials::read_passphrase()?;
+            let store = CredentialStore::open(path, &passphrase)?;
+            let credentials = store
+                .get(account)
+                .with_context(|| format!("Account {account} not found in credentials file"))?;
+            info!("Using credentials of account {}", account.blue());
+            Ok(Binance::with_credentials(credentials.clone()))
+        }
+        None => Ok(Binance::new()),
+    }
+}
+
+fn run_credentials(credentials_path: Option<PathBuf>, command: CredentialsCommands) -> Result<()> {
+    let path = credentials_path.context("Missing --credentials-path or CREDENTIALS_PATH")?;
+    let passphrase = credentials::read_passphrase()?;
+    let mut store = CredentialStore::open(&path, &passphrase)?;
+
+    match command {
+        CredentialsCommands::Add {
+            account,
+            api_key,
+            secure_api_key,
+            private_key,
+        } => {
+            let mut credentials = store.get(&account).cloned().unwrap_or_default();
+            if let Some(api_key) = api_key {
+                credentials.api_key = Some(api_key);
+                credentials.api_secret = Some(rpassword::prompt_password("Api secret: ")?);
+            }
+            if let Some(secure_api_key) = secure_api_key {
+                credentials.secure_api_key = Some(secure_api_key);
+            }
+            if let Some(private_key) = private_key {
+                credentials.private_key = Some(std::fs::read_to_string(private_key)?);
+            }
+            store.insert(&account, credentials);
+            store.save()?;
+            println!("Saved account {}", account.green());
+        }
+        CredentialsCommands::List => {
+            for (account, credentials) in store.accounts() {
+                println!(
+                    "{} hmac={} ed25519={}",
+                    account.blue(),
+                    credentials.hmac().is_ok(),
+                    credentials.ed25519().is_ok()
+                );
+            }
+        }
+        CredentialsCommands::Remove { account } => {
+            if store.remove(&account).is_some() {
+                store.save()?;
+                println!("Removed account {}", account.red());
+            } else {
+                println!("Account {} not found", account);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn run_account_info(marketplace: Binance) -> Result<()> {
     let account_overview = marketplace.get_account_overview(true).await;
     info!("{:?}", account_overview);
 
@@ -222,28 +705,44 @@ async fn run_tui(quote: String, server_address: String, tickers: Vec<Ticker>) ->
     Ok(())
 }
 
-async fn run_test() {
-    let mut binance = Binance::new();
-
+async fn run_test(mut binance: Binance) {
     let (tx_app, _) = tokio::sync::broadcast::channel::<AppEvent>(1000);
     let res = binance.start_account_stream(tx_app).await;
 
     println!("{:?}", res);
 }
 
+// Trades build the 1m candles, the klines would send them a second time
+fn candle_streams(streams: Vec<MarketStream>, candle_source: CandleSource) -> Vec<MarketStream> {
+    if candle_source == CandleSource::Candles {
+        return streams;
+    }
+    let mut streams: Vec<MarketStream> = streams
+        .into_iter()
+        .filter(|stream| *stream != MarketStream::Kline)
+        .collect();
+    if !streams.contains(&MarketStream::Trade) {
+        streams.push(MarketStream::Trade);
+    }
+    streams
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn run_start(
+    mut marketplace: Binance,
     quote: String,
     tickers: Vec<Ticker>,
-    replay_path: Option<PathBuf>,
+    recorder: Option<RecorderConfig>,
     server_address: String,
     real: bool,
+    candle_source: CandleSource,
+    configs: StrategyConfigs,
 ) -> Result<()> {
     let state: Arc<RwLock<state::State>> = Arc::from(RwLock::from(state::State::new()));
 
     let (tx_app, _) = tokio::sync::broadcast::channel::<AppEvent>(1000);
-    let (tx_cmd, _) = tokio::sync::mpsc::channel::<AppCommandEvent>(16);
+    let (tx_cmd, rx_cmd) = tokio::sync::mpsc::channel::<AppCommandEvent>(16);
 
-    let mut marketplace = Binance::new();
     marketplace.init(&tickers).await?;
 
     let mut simulation =
@@ -296,41 +795,53 @@ async fn run_start(
         });
     }
 
+    let mut strategies = HashMap::new();
+    let mut intervals = BTreeSet::new();
     for ticker in tickers.iter() {
-        let mut strategy = ScalpingStrategy::new(
+        let strategy = AnyStrategy::build(
+            &configs.get(ticker),
             state.clone(),
             marketplace.clone(),
             ticker.clone(),
-            ScalpingParams {
-                target_profit: dec!(1),
-                quote_amount: dec!(100),
-                entry_delay: Duration::from_secs(3600 * 24),
-                reentry_delay: Duration::from_secs(60 * 15),
-                session_count: 2,
-                session_profit_lifetime: Duration::from_secs(3600),
-            },
-        );
-        if strategy.init(None).await.is_err() {
-            panic!("Failed strategy initialization for {ticker}");
+        )?;
+        intervals.extend(strategy.intervals());
+        let strategy = spawn_strategy(strategy, tx_app.clone()).await?;
+        strategies.insert(ticker.clone(), strategy);
+    }
+
+    let (tx_intervals, rx_intervals) = tokio::sync::mpsc::channel(16);
+    tokio::task::spawn(start_candle_aggregator(
+        candle_source,
+        intervals.into_iter().collect(),
+        tx_app.clone(),
+        rx_intervals,
+    ));
+
+    tokio::task::spawn({
+        let state = state.clone();
+        let marketplace = marketplace.clone();
+        let tx_app = tx_app.clone();
+        async move {
+            process_commands(
+                state,
+                marketplace,
+                configs,
+                strategies,
+                rx_cmd,
+                tx_app,
+                tx_intervals,
+            )
+            .await;
         }
+    });
 
-        tokio::task::spawn({
-            let tx_app = tx_app.clone();
-            async move {
-                info!("{}", "Starting strategy".green());
-                let _ = strategy.start(tx_app).await;
-                info!("{}", "Ended strategy".red());
-            }
-        });
+    if let Some(recorder) = recorder {
+        tokio::task::spawn(start_recorder(recorder, tx_app.clone()));
     }
 
-    if let Some(replay_path) = replay_path {
-        tokio::task::spawn({
-            let tx_app = tx_app.clone();
-            async move {
-                log_marketplace_events(replay_path, tx_app).await;
-            }
-        });
+    // only live candles are stored, replays may play synthetic data
+    if let Some(candle_store) = marketplace.candle_store() {
+        tokio::task::spawn(start_candle_store(candle_store.clone(), tx_app.clone()));
     }
 
     tokio::task::spawn({
@@ -376,11 +887,209 @@ async fn run_start(
     Ok(())
 }
 
+// Task of a running strategy, and its command channel
+struct RunningStrategy {
+    task: JoinHandle<()>,
+    tx_cmd: tokio::sync::mpsc::Sender<StrategyCommand>,
+}
+
+async fn spawn_strategy(
+    mut strategy: AnyStrategy<Binance>,
+    tx_app: tokio::sync::broadcast::Sender<AppEvent>,
+) -> Result<RunningStrategy> {
+    let ticker = strategy.ticker().clone();
+    strategy
+        .init(None)
+        .await
+        .with_context(|| format!("Failed strategy initialization for {ticker}"))?;
+
+    let (tx_cmd, rx_cmd) = tokio::sync::mpsc::channel(16);
+    let task = tokio::task::spawn(async move {
+        info!(
+            "{} {} {}",
+            "Starting strategy".green(),
+            strategy.name(),
+            ticker
+        );
+        strategy.start(tx_app, rx_cmd).await;
+        info!("{} {}", "Ended strategy".red(), ticker);
+    });
+    Ok(RunningStrategy { task, tx_cmd })
+}
+
+// Runtime commands of a live bot
+async fn process_commands(
+    state: Arc<RwLock<State>>,
+    marketplace: Binance,
+    configs: StrategyConfigs,
+    mut strategies: HashMap<Ticker, RunningStrategy>,
+    mut rx_cmd: tokio::sync::mpsc::Receiver<AppCommandEvent>,
+    tx_app: tokio::sync::broadcast::Sender<AppEvent>,
+    tx_intervals: tokio::sync::mpsc::Sender<Vec<CandleInterval>>,
+) {
+    let draining: Arc<RwLock<HashSet<Ticker>>> = Arc::default();
+
+    while let Some(cmd) = rx_cmd.recv().await {
+        info!("Received command {:?}", cmd);
+        match cmd {
+            AppCommandEvent::Pause | AppCommandEvent::Replay(..) => {
+                error!("{:?} is only available during a replay", cmd);
+            }
+            AppCommandEvent::Strategy { ticker, command } => match strategies.get(&ticker) {
+                Some(strategy) => {
+                    if strategy.tx_cmd.send(command).await.is_err() {
+                        error!("Strategy of {} has ended", ticker);
+                    }
+                }
+                None => error!("Ticker {} is not running", ticker),
+            },
+            AppCommandEvent::AddTicker(ticker) => {
+                if strategies.contains_key(&ticker) || draining.read().await.contains(&ticker) {
+                    error!("Ticker {} is already running or draining", ticker);
+                    continue;
+                }
+                if let Err(err) = marketplace.add_ticker_info(&ticker).await {
+                    error!("Could not load exchange info for {} : {}", ticker, err);
+                    continue;
+                }
+                let strategy = AnyStrategy::build(
+                    &configs.get(&ticker),
+                    state.clone(),
+                    marketplace.clone(),
+                    ticker.clone(),
+                );
+                let strategy = match strategy {
+                    Ok(strategy) => {
+                        let intervals = strategy.intervals();
+                        spawn_strategy(strategy, tx_app.clone())
+                            .await
+                            .map(|strategy| (strategy, intervals))
+                    }
+                    Err(err) => Err(err),
+                };
+                match strategy {
+                    Ok((strategy, intervals)) => {
+                        // the candles of its intervals may not be built yet
+                        let _ = tx_intervals.send(intervals).await;
+                        strategies.insert(ticker.clone(), strategy);
+                        marketplace.subscribe(&ticker).await;
+                        info!("Added ticker {}", ticker);
+                    }
+                    Err(err) => error!("{:?}", err),
+                }
+            }
+            AppCommandEvent::RemoveTicker(ticker) => {
+                let Some(strategy) = strategies.remove(&ticker) else {
+                    error!("Ticker {} is not running", ticker);
+                    continue;
+                };
+                // no new orders from now on
+                strategy.task.abort();
+                draining.write().await.insert(ticker.clone());
+
+                tokio::task::spawn({
+                    let state = state.clone();
+                    let marketplace = marketplace.clone();
+                    let draining = draining.clone();
+                    let tx_app = tx_app.clone();
+                    async move {
+                        drain_ticker(state, &ticker, tx_app).await;
+                        marketplace.unsubscribe(&ticker).await;
+                        draining.write().await.remove(&ticker);
+                        info!("Removed ticker {}", ticker);
+                    }
+                });
+            }
+        }
+    }
+}
+
+// Cancel the open orders of a ticker and wait for them to be cancelled or executed.
+// The market data is still needed meanwhile (simulation matching, order prices).
+// Drafts never placed after a minute are marked as rejected.
+async fn drain_ticker(
+    state: Arc<RwLock<State>>,
+    ticker: &Ticker,
+    tx_app: tokio::sync::broadcast::Sender<AppEvent>,
+) {
+    let started = std::time::Instant::now();
+    let mut cancelled = HashSet::new();
+    loop {
+        let (pending, active): (usize, Vec<String>) = {
+            let state = state.read().await;
+            let pending: Vec<_> = state
+                .orders
+                .iter()
+                .filter(|order| {
+                    &order.ticker == ticker
+                        && matches!(
+                            order.status,
+                            OrderStatus::Draft
+                                | OrderStatus::Sent
+                                | OrderStatus::Active
+                                | OrderStatus::PendingCancel
+                        )
+                })
+                .collect();
+            (
+                pending.len(),
+                pending
+                    .iter()
+                    .filter(|order| order.status == OrderStatus::Active)
+                    .map(|order| order.id.clone())
+                    .collect(),
+            )
+        };
+        if pending == 0 {
+            return;
+        }
+
+        for order_id in active {
+            if cancelled.insert(order_id.clone()) {
+                let _ = tx_app.send(AppEvent::Strategy(StrategyEvent::Action(Box::new(
+                    StrategyAction::Cancel {
+                        order_id,
+                        reason: "Removing ticker".to_string(),
+                        details: None,
+                    },
+                ))));
+            }
+        }
+
+        if started.elapsed() > Duration::from_secs(60) {
+            let mut state = state.write().await;
+            let drafts: Vec<String> = state
+                .orders
+                .iter_mut()
+                .filter(|order| &order.ticker == ticker && order.status == OrderStatus::Draft)
+                .map(|order| {
+                    order.status = OrderStatus::Rejected;
+                    order.reject_reason = Some("Not placed before the ticker removal".to_string());
+                    order.id.clone()
+                })
+                .collect();
+            for id in drafts {
+                warn!("Draining {} : draft {} marked as rejected", ticker, id);
+                state.detach_unfilled(&id);
+            }
+        }
+
+        info!("Draining {} : {} pending orders", ticker, pending);
+        tokio::time::sleep(Duration::from_secs(5)).await;
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn run_replay(
+    mut marketplace: Binance,
     interval: u64,
+    candle_source: CandleSource,
     quote: String,
     tickers: Vec<Ticker>,
-    replay_path: PathBuf,
+    replay_paths: Vec<PathBuf>,
+    range: TimeRange,
+    speed: Option<f64>,
+    configs: StrategyConfigs,
     server_address: Option<String>,
 ) -> Result<()> {
     let state: Arc<RwLock<state::State>> = Arc::from(RwLock::from(state::State::new()));
@@ -388,54 +1097,64 @@ async fn run_replay(
     let (tx_app, _) = tokio::sync::broadcast::channel::<AppEvent>(10000);
     let (tx_cmd, mut rx_cmd) = tokio::sync::mpsc::channel::<AppCommandEvent>(16);
 
-    let mut marketplace = Binance::new();
     marketplace.init(&tickers).await?;
 
-    let replay = ReplayMarketplace::new(replay_path, marketplace.clone(), interval);
+    let replay = ReplayMarketplace::new(replay_paths, marketplace.clone(), interval)
+        .with_time_range(range)
+        .with_candle_source(candle_source)
+        // commands can only come from the server
+        .with_hold_end(server_address.is_some())
+        .with_speed(speed);
 
     let mut simulation = SimulationMarketplace::new(SimulationSource::Book, marketplace.clone());
     simulation
         .update_asset_amount(&quote, dec!(1000), Some(dec!(1)))
         .await;
 
-    {
-        let mut state = state.write().await;
-        state.portfolio.assets = simulation.get_account_assets().await?;
-    }
+    let initial_assets = simulation.get_account_assets().await?;
+    state.write().await.portfolio.assets = initial_assets.clone();
 
     let start_time = replay.get_start_time().await;
     if start_time.is_none() {
         panic!("Could not find start time from replay file");
     }
 
+    let mut intervals = BTreeSet::new();
+
+    let mut strategies = HashMap::new();
     for ticker in tickers.iter() {
-        let mut strategy = ScalpingStrategy::new(
+        let mut strategy = AnyStrategy::build(
+            &configs.get(ticker),
             state.clone(),
             replay.clone(),
             ticker.clone(),
-            ScalpingParams {
-                target_profit: dec!(0.3),
-                quote_amount: dec!(100),
-                entry_delay: Duration::from_secs(3600 * 24),
-                reentry_delay: Duration::from_secs(60),
-                session_count: 1,
-                session_profit_lifetime: Duration::from_secs(3600),
-            },
-        );
+        )?;
+        intervals.extend(strategy.intervals());
         if strategy.init(start_time).await.is_err() {
             panic!("Could not init strategy");
         }
 
+        let (tx_strategy, rx_strategy) = tokio::sync::mpsc::channel(16);
+        strategies.insert(ticker.clone(), tx_strategy);
         tokio::task::spawn({
             let tx_app = tx_app.clone();
             async move {
-                info!("{}", "Starting strategy".green());
-                let _ = strategy.start(tx_app).await;
+                info!("{} {}", "Starting strategy".green(), strategy.name());
+                strategy.start(tx_app, rx_strategy).await;
                 info!("{}", "Ended strategy".red());
             }
         });
     }
 
+    // tickers can not be added during a replay
+    let (_, rx_intervals) = tokio::sync::mpsc::channel(1);
+    tokio::task::spawn(start_candle_aggregator(
+        candle_source,
+        intervals.into_iter().collect(),
+        tx_app.clone(),
+        rx_intervals,
+    ));
+
     tokio::spawn({
         let tx_app = tx_app.clone();
         let mut simulation = simulation.clone();
@@ -444,6 +1163,13 @@ async fn run_replay(
         }
     });
 
+    tokio::task::spawn(reset_on_backward_seek(
+        state.clone(),
+        simulation.clone(),
+        initial_assets,
+        tx_app.clone(),
+    ));
+
     tokio::task::spawn({
         let tx_app = tx_app.clone();
         let state = state.clone();
@@ -464,6 +1190,20 @@ async fn run_replay(
                         AppCommandEvent::Pause => {
                             replay.toggle_pause().await;
                         }
+                        AppCommandEvent::Replay(cmd) => {
+                            replay.command(cmd).await;
+                        }
+                        AppCommandEvent::Strategy { ticker, command } => {
+                            match strategies.get(&ticker) {
+                                Some(tx_strategy) => {
+                                    let _ = tx_strategy.send(command).await;
+                                }
+                                None => error!("Ticker {} is not running", ticker),
+                            }
+                        }
+                        AppCommandEvent::AddTicker(..) | AppCommandEvent::RemoveTicker(..) => {
+                            error!("Tickers can not be changed during a replay");
+                        }
                     }
                 }
             }
@@ -518,6 +1258,41 @@ async fn run_replay(
     Ok(())
 }
 
+// The orders are played again after a backward seek, from the initial portfolio
+async fn reset_on_backward_seek(
+    state: Arc<RwLock<State>>,
+    simulation: SimulationMarketplace<Binance>,
+    assets: HashMap<String, Asset>,
+    tx_app: tokio::sync::broadcast::Sender<AppEvent>,
+) {
+    let mut rx_app = tx_app.subscribe();
+    loop {
+        match rx_app.recv().await {
+            Ok(AppEvent::MarketPlace(MarketplaceEvent::StreamStatus(
+                MarketplaceStreamStatus {
+                    status: StreamStatus::Seeked { from, to },
+                    ..
+                },
+            ))) if to < from => {
+                info!("Replay seeked back, resetting the orders and the portfolio");
+                simulation.reset(assets.clone()).await;
+                let mut state = state.write().await;
+                state.orders.clear();
+                state.portfolio.assets = assets.clone();
+                state.portfolio.update_value();
+                let _ = tx_app.send(AppEvent::State(state::StateEvent::Orders(
+                    state.orders.clone(),
+                )));
+                let _ = tx_app.send(AppEvent::State(state::StateEvent::Portfolio(
+                    state.portfolio.clone(),
+                )));
+            }
+            Ok(_) | Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(_) => return,
+        }
+    }
+}
+
 async fn process_app_event<T: MarketplaceTradeApi>(
     state: Arc<RwLock<State>>,
     mut marketplace: T,
@@ -537,49 +1312,90 @@ async fn process_app_event<T: MarketplaceTradeApi>(
             }
 
             match event {
-                AppEvent::Strategy(StrategyEvent::Action(StrategyAction::None)) => {
-                    debug!("{}", "No strategy".purple());
-                }
-                AppEvent::Strategy(StrategyEvent::Action(StrategyAction::Ignore {
-                    ticker,
-                    reason,
-                    details,
-                })) => {
-                    debug!(
-                        "{} {} {} {:?}",
-                        "Ignore".purple(),
+                AppEvent::Strategy(StrategyEvent::Action(action)) => match *action {
+                    StrategyAction::None => {
+                        debug!("{}", "No strategy".purple());
+                    }
+                    StrategyAction::Ignore {
                         ticker,
-                        reason.as_str().yellow(),
-                        details
-                    )
-                }
-                AppEvent::Strategy(StrategyEvent::Action(StrategyAction::PlaceOrder { order })) => {
-                    info!("{} {:?}", "Add order".blue(), order);
-                    let added_order = {
-                        let mut state = state.write().await;
-                        state.add_order(order.clone())
-                    };
-                    match added_order {
-                        Ok(order) => match marketplace.place_order(&order).await {
-                            Ok(market_order) => {
-                                let mut state = state.write().await;
-                                if let Some(order) = state.find_by_id(&order.id) {
-                                    *order = market_order;
+                        reason,
+                        details,
+                    } => {
+                        debug!(
+                            "{} {} {} {:?}",
+                            "Ignore".purple(),
+                            ticker,
+                            reason.as_str().yellow(),
+                            details
+                        )
+                    }
+                    StrategyAction::PlaceOrder { order } => {
+                        info!("{} {:?}", "Add order".blue(), order);
+                        let added_order = {
+                            let mut state = state.write().await;
+                            state.add_order(order.clone())
+                        };
+                        match added_order {
+                            Ok(order) => match marketplace.place_order(&order).await {
+                                Ok(market_order) => {
+                                    let mut state = state.write().await;
+                                    if let Some(order) = state.find_by_id(&order.id) {
+                                        *order = market_order;
+                                    }
+                                    state.detach_unfilled(&order.id);
                                 }
-                            }
+                                Err(err) => {
+                                    error!("Failed posting order : {err}");
+                                    let mut state = state.write().await;
+                                    if let Some(order) = state.find_by_id(&order.id) {
+                                        order.status = OrderStatus::Rejected;
+                                    }
+                                    state.detach_unfilled(&order.id);
+                                }
+                            },
                             Err(err) => {
-                                error!("Failed posting order : {err}");
-                                let mut state = state.write().await;
-                                if let Some(order) = state.find_by_id(&order.id) {
-                                    order.status = OrderStatus::Rejected;
+                                error!("Failed creating order : {err}");
+                            }
+                        }
+                    }
+                    StrategyAction::Cancel {
+                        order_id,
+                        reason,
+                        details,
+                    } => {
+                        info!(
+                            "{} {} : {} {}",
+                            "Cancel order".blue(),
+                            order_id,
+                            reason,
+                            details.unwrap_or_default()
+                        );
+                        let order = {
+                            let mut state = state.write().await;
+                            match state.find_by_id(&order_id) {
+                                Some(order) if order.status == OrderStatus::Active => {
+                                    order.status = OrderStatus::PendingCancel;
+                                    Some(order.clone())
+                                }
+                                _ => None,
+                            }
+                        };
+                        let Some(order) = order else {
+                            continue;
+                        };
+                        if let Err(err) = marketplace.cancel_order(&order).await {
+                            error!("Failed cancelling order : {err}");
+                            // filled meanwhile, or unknown : the order updates tell
+                            let mut state = state.write().await;
+                            if let Some(order) = state.find_by_id(&order_id) {
+                                if order.status == OrderStatus::PendingCancel {
+                                    order.status = OrderStatus::Active;
                                 }
                             }
-                        },
-                        Err(err) => {
-                            error!("Failed creating order : {err}");
                         }
                     }
-                }
+                    StrategyAction::Break { .. } => {}
+                },
                 AppEvent::MarketPlace(MarketplaceEvent::PortfolioUpdate(update)) => {
                     info!("{} : {:?}", "Portfolio update".blue(), update);
                     let mut state = state.write().await;
@@ -590,6 +1406,13 @@ async fn process_app_event<T: MarketplaceTradeApi>(
                         state.portfolio.clone(),
                     )));
                 }
+                // the balances come with the PortfolioUpdate (outboundAccountPosition) sent along
+                AppEvent::MarketPlace(MarketplaceEvent::BalanceUpdate(update)) => {
+                    info!("{} : {:?}", "Balance update".blue(), update);
+                }
+                AppEvent::MarketPlace(MarketplaceEvent::OrderListUpdate(update)) => {
+                    info!("{} : {:?}", "Order list update".blue(), update);
+                }
                 AppEvent::MarketPlace(MarketplaceEvent::OrderUpdate(update)) => {
                     info!("{} : {:?}", "Order update".blue(), update);
                     let orders = {
@@ -604,43 +1427,3 @@ async fn process_app_event<T: MarketplaceTradeApi>(
         }
     }
 }
-
-async fn log_marketplace_events(
-    replay_path: PathBuf,
-    tx_app: tokio::sync::broadcast::Sender<AppEvent>,
-) {
-    let mut rx = tx_app.subscribe();
-
-    let mut path = replay_path.clone();
-    path.push("events.jsonl");
-
-    tokio::fs::create_dir_all(path.parent().unwrap())
-        .await
-        .expect("Failed to create log directories");
-
-    let mut file = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(path)
-        .await
-        .expect("Failed to open log file");
-
-    loop {
-        let event = rx.recv().await;
-        if let Ok(AppEvent::MarketPlace(event)) = event {
-            match serde_json::ser::to_string(&event) {
-                Ok(json) => {
-                    file.write_all(json.as_bytes())
-                        .await
-                        .expect("Failed to write to store file");
-                    file.write_all(b"\n")
-                        .await
-                        .expect("Failed to write to store file");
-                }
-                Err(err) => {
-                    error!("Failed to save event : {}", err);
-                }
-            }
-        }
-    }
-}
@@ -0,0 +1,221 @@
+use std::collections::BTreeMap;
+use std::env::var;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use argon2::Argon2;
+use base64::{prelude::BASE64_STANDARD, Engine as _};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use serde::{Deserialize, Serialize};
+
+pub const PASSPHRASE_ENV: &str = "CREDENTIALS_PASSPHRASE";
+
+const STORE_VERSION: u8 = 1;
+
+// Secrets of one marketplace account.
+// HMAC keys are used for the REST api, the Ed25519 key for the websocket api.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Credentials {
+    pub api_key: Option<String>,
+    pub api_secret: Option<String>,
+    pub secure_api_key: Option<String>,
+    // PEM encoded Ed25519 private key
+    pub private_key: Option<String>,
+}
+
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("api_key", &self.api_key)
+            .field("api_secret", &self.api_secret.as_ref().map(|_| "***"))
+            .field("secure_api_key", &self.secure_api_key)
+            .field("private_key", &self.private_key.as_ref().map(|_| "***"))
+            .finish()
+    }
+}
+
+impl Credentials {
+    // Legacy configuration from the environment (or .env).
+    pub fn from_env() -> Self {
+        Self {
+            api_key: var("BINANCE_API_KEY").ok(),
+            api_secret: var("BINANCE_API_SECRET").ok(),
+            secure_api_key: var("BINANCE_SECURE_API_KEY").ok(),
+            private_key: var("BINANCE_PRIVATE_KEY")
+                .ok()
+                .and_then(|path| fs::read_to_string(path).ok()),
+        }
+    }
+
+    pub fn hmac(&self) -> Result<(&str, &str)> {
+        match (&self.api_key, &self.api_secret) {
+            (Some(key), Some(secret)) => Ok((key.as_str(), secret.as_str())),
+            _ => Err(anyhow!("Missing HMAC api key or secret")),
+        }
+    }
+
+    pub fn ed25519(&self) -> Result<(&str, &str)> {
+        match (&self.secure_api_key, &self.private_key) {
+            (Some(key), Some(pem)) => Ok((key.as_str(), pem.as_str())),
+            _ => Err(anyhow!("Missing Ed25519 api key or private key")),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct EncryptedFile {
+    version: u8,
+    salt: String,
+    nonce: String,
+    data: String,
+}
+
+// Credentials of several named accounts/environments,
+// stored in a file encrypted with a key derived from a passphrase (Argon2id + ChaCha20-Poly1305).
+pub struct CredentialStore {
+    path: PathBuf,
+    salt: [u8; 16],
+    key: Key,
+    accounts: BTreeMap<String, Credentials>,
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Key> {
+    let mut key = Key::default();
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|err| anyhow!("Key derivation failed : {err}"))?;
+    Ok(key)
+}
+
+impl CredentialStore {
+    // Open and decrypt the store, or start an empty one if the file does not exist yet.
+    pub fn open(path: &Path, passphrase: &str) -> Result<Self> {
+        if !path.exists() {
+            let mut salt = [0_u8; 16];
+            OsRng.fill_bytes(&mut salt);
+            return Ok(Self {
+                path: path.to_path_buf(),
+                key: derive_key(passphrase, &salt)?,
+                salt,
+                accounts: BTreeMap::new(),
+            });
+        }
+
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Could not read credentials file {}", path.display()))?;
+        let file: EncryptedFile = serde_json::from_str(&content)?;
+        if file.version != STORE_VERSION {
+            anyhow::bail!("Unsupported credentials file version {}", file.version);
+        }
+
+        let salt: [u8; 16] = BASE64_STANDARD
+            .decode(file.salt)?
+            .try_into()
+            .map_err(|_| anyhow!("Invalid credentials salt"))?;
+        let nonce = BASE64_STANDARD.decode(file.nonce)?;
+        if nonce.len() != 12 {
+            anyhow::bail!("Invalid credentials nonce");
+        }
+        let data = BASE64_STANDARD.decode(file.data)?;
+
+        let key = derive_key(passphrase, &salt)?;
+        let plain = ChaCha20Poly1305::new(&key)
+            .decrypt(Nonce::from_slice(&nonce), data.as_slice())
+            .map_err(|_| anyhow!("Could not decrypt credentials : wrong passphrase?"))?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            salt,
+            key,
+            accounts: serde_json::from_slice(&plain)?,
+        })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let plain = serde_json::to_vec(&self.accounts)?;
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let data = ChaCha20Poly1305::new(&self.key)
+            .encrypt(&nonce, plain.as_slice())
+            .map_err(|_| anyhow!("Could not encrypt credentials"))?;
+
+        let file = EncryptedFile {
+            version: STORE_VERSION,
+            salt: BASE64_STANDARD.encode(self.salt),
+            nonce: BASE64_STANDARD.encode(nonce),
+            data: BASE64_STANDARD.encode(data),
+        };
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, serde_json::to_string_pretty(&file)?)?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&self.path, fs::Permissions::from_mode(0o600))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn get(&self, account: &str) -> Option<&Credentials> {
+        self.accounts.get(account)
+    }
+
+    pub fn insert(&mut self, account: &str, credentials: Credentials) {
+        self.accounts.insert(account.to_string(), credentials);
+    }
+
+    pub fn remove(&mut self, account: &str) -> Option<Credentials> {
+        self.accounts.remove(account)
+    }
+
+    pub fn accounts(&self) -> impl Iterator<Item = (&String, &Credentials)> {
+        self.accounts.iter()
+    }
+}
+
+// Passphrase from the environment, or prompted on the terminal.
+pub fn read_passphrase() -> Result<String> {
+    match var(PASSPHRASE_ENV) {
+        Ok(passphrase) => Ok(passphrase),
+        Err(_) => Ok(rpassword::prompt_password("Credentials passphrase: ")?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_store_roundtrip() {
+        let path = std::env::temp_dir().join(format!("credentials-{}.json", uuid::Uuid::new_v4()));
+
+        let mut store = CredentialStore::open(&path, "secret").unwrap();
+        store.insert(
+            "testnet",
+            Credentials {
+                api_key: Some("key".to_string()),
+                api_secret: Some("api secret".to_string()),
+                ..Default::default()
+            },
+        );
+        store.save().unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(!content.contains("api secret"));
+
+        let store = CredentialStore::open(&path, "secret").unwrap();
+        let credentials = store.get("testnet").unwrap();
+        assert_eq!(credentials.hmac().unwrap(), ("key", "api secret"));
+        assert!(credentials.ed25519().is_err());
+
+        assert!(CredentialStore::open(&path, "wrong").is_err());
+
+        let _ = fs::remove_file(path);
+    }
+}
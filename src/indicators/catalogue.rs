@@ -0,0 +1,476 @@
+use rust_decimal::prelude::*;
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
+use yata::core::{
+    IndicatorConfig, IndicatorInstance, Method, PeriodType, Source, ValueType, OHLCV,
+};
+use yata::helpers::MA;
+use yata::indicators::{
+    AverageDirectionalIndex, BollingerBands, KeltnerChannel, RelativeStrengthIndex,
+    StochasticOscillator, MACD,
+};
+use yata::methods::EMA;
+
+use crate::marketplace::MarketplaceCandle;
+
+impl OHLCV for MarketplaceCandle {
+    fn open(&self) -> ValueType {
+        self.open_price.to_f64().unwrap_or_default()
+    }
+
+    fn high(&self) -> ValueType {
+        self.high_price.to_f64().unwrap_or_default()
+    }
+
+    fn low(&self) -> ValueType {
+        self.low_price.to_f64().unwrap_or_default()
+    }
+
+    fn close(&self) -> ValueType {
+        self.close_price.to_f64().unwrap_or_default()
+    }
+
+    fn volume(&self) -> ValueType {
+        self.volume.to_f64().unwrap_or_default()
+    }
+}
+
+// Indicators of the catalogue, in the configs as {"name": "rsi", "period": 14}
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "name", rename_all = "snake_case")]
+pub enum NamedIndicator {
+    Ema {
+        period: PeriodType,
+    },
+    // wilder smoothing of the gains and losses
+    Rsi {
+        period: PeriodType,
+    },
+    Macd {
+        fast: PeriodType,
+        slow: PeriodType,
+        signal: PeriodType,
+    },
+    Bollinger {
+        period: PeriodType,
+        std_devs: f64,
+    },
+    // %k smoothed over `smooth` candles, %d over `signal`
+    Stochastic {
+        period: PeriodType,
+        smooth: PeriodType,
+        signal: PeriodType,
+    },
+    // on balance volume
+    Obv,
+    Adx {
+        period: PeriodType,
+    },
+    // ema -/+ multiplier x atr
+    Keltner {
+        period: PeriodType,
+        multiplier: f64,
+    },
+}
+
+// Values of the last candle. Oscillators (rsi, stochastic, adx) range from 0 to 100.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IndicatorValue {
+    Ema(Decimal),
+    Rsi(Decimal),
+    Macd {
+        macd: Decimal,
+        signal: Decimal,
+        histogram: Decimal,
+    },
+    Bollinger {
+        upper: Decimal,
+        middle: Decimal,
+        lower: Decimal,
+    },
+    Stochastic {
+        k: Decimal,
+        d: Decimal,
+    },
+    Obv(Decimal),
+    Adx {
+        adx: Decimal,
+        plus_di: Decimal,
+        minus_di: Decimal,
+    },
+    Keltner {
+        upper: Decimal,
+        middle: Decimal,
+        lower: Decimal,
+    },
+}
+
+// Outputs of the indicators, in the configs as "signal" or "plus_di".
+// "value" is the main output of each indicator.
+// "position" of the bands: 0 at the lower band, 1 at the upper band.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, strum_macros::Display,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum IndicatorOutput {
+    #[default]
+    Value,
+    Macd,
+    Signal,
+    Histogram,
+    Upper,
+    Middle,
+    Lower,
+    Position,
+    K,
+    D,
+    Adx,
+    PlusDi,
+    MinusDi,
+}
+
+fn to_decimal(value: ValueType) -> Option<Decimal> {
+    if value.is_finite() {
+        Decimal::from_f64_retain(value)
+    } else {
+        None
+    }
+}
+
+impl IndicatorValue {
+    // None for the outputs the indicator does not have
+    pub fn get(&self, output: IndicatorOutput, price: Decimal) -> Option<Decimal> {
+        let position = |upper: Decimal, lower: Decimal| {
+            if upper > lower {
+                Some((price - lower) / (upper - lower))
+            } else {
+                None
+            }
+        };
+        match (self, output) {
+            (
+                IndicatorValue::Ema(value)
+                | IndicatorValue::Rsi(value)
+                | IndicatorValue::Obv(value),
+                IndicatorOutput::Value,
+            ) => Some(*value),
+            (IndicatorValue::Macd { macd, .. }, IndicatorOutput::Macd | IndicatorOutput::Value) => {
+                Some(*macd)
+            }
+            (IndicatorValue::Macd { signal, .. }, IndicatorOutput::Signal) => Some(*signal),
+            (IndicatorValue::Macd { histogram, .. }, IndicatorOutput::Histogram) => {
+                Some(*histogram)
+            }
+            (
+                IndicatorValue::Bollinger { upper, .. } | IndicatorValue::Keltner { upper, .. },
+                IndicatorOutput::Upper,
+            ) => Some(*upper),
+            (
+                IndicatorValue::Bollinger { middle, .. } | IndicatorValue::Keltner { middle, .. },
+                IndicatorOutput::Middle | IndicatorOutput::Value,
+            ) => Some(*middle),
+            (
+                IndicatorValue::Bollinger { lower, .. } | IndicatorValue::Keltner { lower, .. },
+                IndicatorOutput::Lower,
+            ) => Some(*lower),
+            (
+                IndicatorValue::Bollinger { upper, lower, .. }
+                | IndicatorValue::Keltner { upper, lower, .. },
+                IndicatorOutput::Position,
+            ) => position(*upper, *lower),
+            (IndicatorValue::Stochastic { k, .. }, IndicatorOutput::K | IndicatorOutput::Value) => {
+                Some(*k)
+            }
+            (IndicatorValue::Stochastic { d, .. }, IndicatorOutput::D) => Some(*d),
+            (IndicatorValue::Adx { adx, .. }, IndicatorOutput::Adx | IndicatorOutput::Value) => {
+                Some(*adx)
+            }
+            (IndicatorValue::Adx { plus_di, .. }, IndicatorOutput::PlusDi) => Some(*plus_di),
+            (IndicatorValue::Adx { minus_di, .. }, IndicatorOutput::MinusDi) => Some(*minus_di),
+            _ => None,
+        }
+    }
+}
+
+// Last values of a yata indicator over the candles, oldest first
+fn last_values<C: IndicatorConfig>(
+    config: C,
+    candles: &[&MarketplaceCandle],
+) -> Option<Vec<ValueType>> {
+    let (first, rest) = candles.split_first()?;
+    let mut instance = config.init(*first).ok()?;
+    let mut result = instance.next(*first);
+    for candle in rest {
+        result = instance.next(*candle);
+    }
+    Some(result.values().to_vec())
+}
+
+impl NamedIndicator {
+    pub fn outputs(&self) -> &'static [IndicatorOutput] {
+        use IndicatorOutput::*;
+        match self {
+            NamedIndicator::Ema { .. } | NamedIndicator::Rsi { .. } | NamedIndicator::Obv => {
+                &[Value]
+            }
+            NamedIndicator::Macd { .. } => &[Value, Macd, Signal, Histogram],
+            NamedIndicator::Bollinger { .. } | NamedIndicator::Keltner { .. } => {
+                &[Value, Upper, Middle, Lower, Position]
+            }
+            NamedIndicator::Stochastic { .. } => &[Value, K, D],
+            NamedIndicator::Adx { .. } => &[Value, Adx, PlusDi, MinusDi],
+        }
+    }
+
+    // Candles needed before the first value
+    pub fn warm_up(&self) -> usize {
+        let warm_up = match self {
+            NamedIndicator::Ema { period }
+            | NamedIndicator::Bollinger { period, .. }
+            | NamedIndicator::Keltner { period, .. } => *period as usize,
+            NamedIndicator::Rsi { period } => *period as usize + 1,
+            NamedIndicator::Macd { slow, signal, .. } => *slow as usize + *signal as usize,
+            NamedIndicator::Stochastic {
+                period,
+                smooth,
+                signal,
+            } => *period as usize + *smooth as usize + *signal as usize,
+            NamedIndicator::Obv => 2,
+            NamedIndicator::Adx { period } => 2 * *period as usize,
+        };
+        warm_up.max(1)
+    }
+
+    // Value of the latest candle, candles latest first like the strategy history.
+    // None until enough candles or with invalid params.
+    pub fn compute<'a>(
+        &self,
+        candles: impl IntoIterator<Item = &'a MarketplaceCandle>,
+    ) -> Option<IndicatorValue> {
+        let mut candles: Vec<&MarketplaceCandle> = candles.into_iter().collect();
+        if candles.len() < self.warm_up() {
+            return None;
+        }
+        candles.reverse();
+        let hundred = dec!(100);
+
+        let value = match self {
+            NamedIndicator::Ema { period } => {
+                let mut ema = EMA::new(*period, &candles[0].close()).ok()?;
+                let mut value = candles[0].close();
+                for candle in candles.iter().skip(1) {
+                    value = ema.next(&candle.close());
+                }
+                IndicatorValue::Ema(to_decimal(value)?)
+            }
+            NamedIndicator::Rsi { period } => {
+                let values = last_values(
+                    RelativeStrengthIndex {
+                        ma: MA::RMA(*period),
+                        zone: 0.3,
+                        source: Source::Close,
+                    },
+                    &candles,
+                )?;
+                IndicatorValue::Rsi(to_decimal(values[0])? * hundred)
+            }
+            NamedIndicator::Macd { fast, slow, signal } => {
+                let values = last_values(
+                    MACD {
+                        ma1: MA::EMA(*fast),
+                        ma2: MA::EMA(*slow),
+                        signal: MA::EMA(*signal),
+                        source: Source::Close,
+                    },
+                    &candles,
+                )?;
+                let (macd, signal) = (to_decimal(values[0])?, to_decimal(values[1])?);
+                IndicatorValue::Macd {
+                    macd,
+                    signal,
+                    histogram: macd - signal,
+                }
+            }
+            NamedIndicator::Bollinger { period, std_devs } => {
+                let values = last_values(
+                    BollingerBands {
+                        avg_size: *period,
+                        sigma: *std_devs,
+                        source: Source::Close,
+                    },
+                    &candles,
+                )?;
+                IndicatorValue::Bollinger {
+                    upper: to_decimal(values[0])?,
+                    middle: to_decimal(values[1])?,
+                    lower: to_decimal(values[2])?,
+                }
+            }
+            NamedIndicator::Stochastic {
+                period,
+                smooth,
+                signal,
+            } => {
+                let values = last_values(
+                    StochasticOscillator {
+                        period: *period,
+                        ma: MA::SMA(*smooth),
+                        signal: MA::SMA(*signal),
+                        zone: 0.2,
+                    },
+                    &candles,
+                )?;
+                IndicatorValue::Stochastic {
+                    k: to_decimal(values[0])? * hundred,
+                    d: to_decimal(values[1])? * hundred,
+                }
+            }
+            NamedIndicator::Obv => {
+                let obv = candles.windows(2).fold(dec!(0), |obv, candles| {
+                    match candles[1].close_price.cmp(&candles[0].close_price) {
+                        std::cmp::Ordering::Greater => obv + candles[1].volume,
+                        std::cmp::Ordering::Less => obv - candles[1].volume,
+                        std::cmp::Ordering::Equal => obv,
+                    }
+                });
+                IndicatorValue::Obv(obv)
+            }
+            NamedIndicator::Adx { period } => {
+                let values = last_values(
+                    AverageDirectionalIndex {
+                        method1: MA::RMA(*period),
+                        method2: MA::RMA(*period),
+                        period1: 1,
+                        zone: 0.2,
+                    },
+                    &candles,
+                )?;
+                IndicatorValue::Adx {
+                    adx: to_decimal(values[0])? * hundred,
+                    plus_di: to_decimal(values[1])? * hundred,
+                    minus_di: to_decimal(values[2])? * hundred,
+                }
+            }
+            NamedIndicator::Keltner { period, multiplier } => {
+                let values = last_values(
+                    KeltnerChannel {
+                        ma: MA::EMA(*period),
+                        sigma: *multiplier,
+                        source: Source::Close,
+                    },
+                    &candles,
+                )?;
+                let (upper, lower) = (to_decimal(values[1])?, to_decimal(values[2])?);
+                IndicatorValue::Keltner {
+                    upper,
+                    middle: (upper + lower) / dec!(2),
+                    lower,
+                }
+            }
+        };
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::marketplace::candle::CandleInterval;
+    use crate::ticker::Ticker;
+
+    #[test]
+    fn test_named_indicators() {
+        // rising closes, latest first
+        let candles: Vec<MarketplaceCandle> = (0..60)
+            .map(|i| {
+                let close = Decimal::from(100 + i) + Decimal::from(i % 3);
+                MarketplaceCandle {
+                    ticker: Ticker::new("BTC", "USDC"),
+                    interval: CandleInterval::M1,
+                    open_price: close - dec!(1),
+                    close_price: close,
+                    high_price: close + dec!(1),
+                    low_price: close - dec!(2),
+                    trade_count: 0,
+                    start_time: i * 60_000,
+                    close_time: i * 60_000 + 59_999,
+                    volume: dec!(2),
+                    closed: true,
+                }
+            })
+            .rev()
+            .collect();
+        let price = candles[0].close_price;
+
+        let rsi = NamedIndicator::Rsi { period: 14 };
+        assert_eq!(rsi.compute(&candles[..10]), None);
+        let rsi = rsi
+            .compute(&candles)
+            .unwrap()
+            .get(IndicatorOutput::Value, price)
+            .unwrap();
+        assert!(rsi > dec!(50) && rsi <= dec!(100));
+
+        let ema = NamedIndicator::Ema { period: 10 }
+            .compute(&candles)
+            .unwrap();
+        assert!(ema.get(IndicatorOutput::Value, price).unwrap() < price);
+
+        let macd = NamedIndicator::Macd {
+            fast: 12,
+            slow: 26,
+            signal: 9,
+        }
+        .compute(&candles)
+        .unwrap();
+        assert!(macd.get(IndicatorOutput::Macd, price).unwrap() > dec!(0));
+
+        let bands = NamedIndicator::Bollinger {
+            period: 20,
+            std_devs: 2.0,
+        }
+        .compute(&candles)
+        .unwrap();
+        let position = bands.get(IndicatorOutput::Position, price).unwrap();
+        assert!(position > dec!(0.5) && position < dec!(1.5));
+        assert_eq!(bands.get(IndicatorOutput::Histogram, price), None);
+
+        let stochastic = NamedIndicator::Stochastic {
+            period: 14,
+            smooth: 3,
+            signal: 3,
+        }
+        .compute(&candles)
+        .unwrap();
+        assert!(stochastic.get(IndicatorOutput::K, price).unwrap() > dec!(50));
+
+        let adx = NamedIndicator::Adx { period: 14 }
+            .compute(&candles)
+            .unwrap();
+        assert!(
+            adx.get(IndicatorOutput::PlusDi, price).unwrap()
+                > adx.get(IndicatorOutput::MinusDi, price).unwrap()
+        );
+
+        let keltner = NamedIndicator::Keltner {
+            period: 20,
+            multiplier: 2.0,
+        }
+        .compute(&candles)
+        .unwrap();
+        assert!(
+            keltner.get(IndicatorOutput::Upper, price).unwrap()
+                > keltner.get(IndicatorOutput::Lower, price).unwrap()
+        );
+
+        // 40 closes up and 19 down, volume 2
+        let obv = NamedIndicator::Obv.compute(&candles).unwrap();
+        assert_eq!(obv.get(IndicatorOutput::Value, price), Some(dec!(42)));
+
+        let config: NamedIndicator =
+            serde_json::from_str(r#"{"name": "macd", "fast": 12, "slow": 26, "signal": 9}"#)
+                .unwrap();
+        assert_eq!(config.warm_up(), 35);
+    }
+}
@@ -0,0 +1,424 @@
+pub mod catalogue;
+
+use std::collections::{HashMap, VecDeque};
+
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
+
+use crate::marketplace::candle::CandleInterval;
+use crate::marketplace::MarketplaceCandle;
+use crate::ticker::Ticker;
+
+// Indicators of the engine, in the configs as {"indicator": "wsma", "period": 14}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "indicator", rename_all = "snake_case")]
+pub enum IndicatorKind {
+    // close prices
+    Sma { period: usize },
+    // Wilder smoothing of the closes
+    Wsma { period: usize },
+    // average of the true ranges
+    Atr { period: usize },
+}
+
+// Simple moving average, O(1) by bar
+#[derive(Clone, Debug)]
+pub struct Sma {
+    period: usize,
+    window: VecDeque<Decimal>,
+    sum: Decimal,
+}
+
+impl Sma {
+    pub fn new(period: usize) -> Self {
+        Self {
+            period: period.max(1),
+            window: VecDeque::with_capacity(period.max(1)),
+            sum: dec!(0),
+        }
+    }
+
+    pub fn next(&mut self, value: Decimal) -> Option<Decimal> {
+        self.sum += value;
+        self.window.push_back(value);
+        if self.window.len() > self.period {
+            self.sum -= self.window.pop_front().unwrap_or_default();
+        }
+        self.value()
+    }
+
+    pub fn peek(&self, value: Decimal) -> Option<Decimal> {
+        if self.window.len() + 1 < self.period {
+            return None;
+        }
+        let dropped = if self.window.len() == self.period {
+            self.window.front().copied().unwrap_or_default()
+        } else {
+            dec!(0)
+        };
+        Some((self.sum - dropped + value) / Decimal::from(self.period))
+    }
+
+    pub fn value(&self) -> Option<Decimal> {
+        if self.window.len() == self.period {
+            Some(self.sum / Decimal::from(self.period))
+        } else {
+            None
+        }
+    }
+}
+
+// Wilder smoothing of the closes, seeded with the average of the first period ones. O(1) by bar.
+#[derive(Clone, Debug)]
+pub struct Wsma {
+    period: usize,
+    seed: Sma,
+    value: Option<Decimal>,
+}
+
+impl Wsma {
+    pub fn new(period: usize) -> Self {
+        Self {
+            period: period.max(1),
+            seed: Sma::new(period),
+            value: None,
+        }
+    }
+
+    fn smooth(&self, previous: Decimal, value: Decimal) -> Decimal {
+        previous + (value - previous) / Decimal::from(self.period)
+    }
+
+    pub fn next(&mut self, value: Decimal) -> Option<Decimal> {
+        self.value = match self.value {
+            Some(previous) => Some(self.smooth(previous, value)),
+            None => self.seed.next(value),
+        };
+        self.value
+    }
+
+    pub fn peek(&self, value: Decimal) -> Option<Decimal> {
+        match self.value {
+            Some(previous) => Some(self.smooth(previous, value)),
+            None => self.seed.peek(value),
+        }
+    }
+
+    pub fn value(&self) -> Option<Decimal> {
+        self.value
+    }
+}
+
+// Average true range, the first bar only gives the previous close
+#[derive(Clone, Debug)]
+pub struct Atr {
+    ranges: Sma,
+    prev_close: Option<Decimal>,
+}
+
+impl Atr {
+    pub fn new(period: usize) -> Self {
+        Self {
+            ranges: Sma::new(period),
+            prev_close: None,
+        }
+    }
+
+    fn true_range(&self, candle: &MarketplaceCandle) -> Option<Decimal> {
+        let prev_close = self.prev_close?;
+        Some(
+            (candle.high_price - candle.low_price)
+                .max((candle.high_price - prev_close).abs())
+                .max((candle.low_price - prev_close).abs()),
+        )
+    }
+
+    pub fn next(&mut self, candle: &MarketplaceCandle) -> Option<Decimal> {
+        if let Some(range) = self.true_range(candle) {
+            self.ranges.next(range);
+        }
+        self.prev_close = Some(candle.close_price);
+        self.value()
+    }
+
+    pub fn peek(&self, candle: &MarketplaceCandle) -> Option<Decimal> {
+        self.ranges.peek(self.true_range(candle)?)
+    }
+
+    pub fn value(&self) -> Option<Decimal> {
+        self.ranges.value()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Indicator {
+    Sma(Sma),
+    Wsma(Wsma),
+    Atr(Atr),
+}
+
+impl Indicator {
+    pub fn new(kind: IndicatorKind) -> Self {
+        match kind {
+            IndicatorKind::Sma { period } => Self::Sma(Sma::new(period)),
+            IndicatorKind::Wsma { period } => Self::Wsma(Wsma::new(period)),
+            IndicatorKind::Atr { period } => Self::Atr(Atr::new(period)),
+        }
+    }
+
+    // Closed candle, None until the indicator is warmed up
+    pub fn next(&mut self, candle: &MarketplaceCandle) -> Option<Decimal> {
+        match self {
+            Self::Sma(sma) => sma.next(candle.close_price),
+            Self::Wsma(wsma) => wsma.next(candle.close_price),
+            Self::Atr(atr) => atr.next(candle),
+        }
+    }
+
+    // Value if the forming candle closed now, the state is unchanged
+    pub fn peek(&self, candle: &MarketplaceCandle) -> Option<Decimal> {
+        match self {
+            Self::Sma(sma) => sma.peek(candle.close_price),
+            Self::Wsma(wsma) => wsma.peek(candle.close_price),
+            Self::Atr(atr) => atr.peek(candle),
+        }
+    }
+
+    pub fn value(&self) -> Option<Decimal> {
+        match self {
+            Self::Sma(sma) => sma.value(),
+            Self::Wsma(wsma) => wsma.value(),
+            Self::Atr(atr) => atr.value(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct Series {
+    indicators: HashMap<IndicatorKind, Indicator>,
+    last_closed: Option<u64>,
+    forming: Option<MarketplaceCandle>,
+}
+
+impl Series {
+    fn close(&mut self, candle: &MarketplaceCandle) {
+        for indicator in self.indicators.values_mut() {
+            indicator.next(candle);
+        }
+        self.last_closed = Some(candle.start_time);
+    }
+}
+
+// Indicators by ticker and interval, updated once per closed candle.
+// A candle is closed by its flag or by the start of the next one, the forming one is only peeked.
+#[derive(Clone, Debug, Default)]
+pub struct IndicatorEngine {
+    series: HashMap<(Ticker, CandleInterval), Series>,
+}
+
+impl IndicatorEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The indicator starts warming up with the next closed candles
+    pub fn add(&mut self, ticker: &Ticker, interval: CandleInterval, kind: IndicatorKind) {
+        self.series
+            .entry((ticker.clone(), interval))
+            .or_default()
+            .indicators
+            .entry(kind)
+            .or_insert_with(|| Indicator::new(kind));
+    }
+
+    pub fn with_indicators(
+        mut self,
+        ticker: &Ticker,
+        interval: CandleInterval,
+        kinds: &[IndicatorKind],
+    ) -> Self {
+        for kind in kinds {
+            self.add(ticker, interval, *kind);
+        }
+        self
+    }
+
+    // Candles of an untracked ticker or interval are ignored, older ones too
+    pub fn update(&mut self, candle: &MarketplaceCandle) {
+        let Some(series) = self
+            .series
+            .get_mut(&(candle.ticker.clone(), candle.interval))
+        else {
+            return;
+        };
+        if series
+            .last_closed
+            .is_some_and(|last_closed| candle.start_time <= last_closed)
+        {
+            return;
+        }
+        if let Some(forming) = series
+            .forming
+            .take_if(|forming| forming.start_time < candle.start_time)
+        {
+            series.close(&forming);
+        }
+        if candle.closed {
+            series.forming = None;
+            series.close(candle);
+        } else {
+            series.forming = Some(candle.clone());
+        }
+    }
+
+    // Back to the warm-up state, after a seek
+    pub fn reset(&mut self, ticker: &Ticker) {
+        for ((series_ticker, _), series) in self.series.iter_mut() {
+            if series_ticker == ticker {
+                *series = Series {
+                    indicators: series
+                        .indicators
+                        .keys()
+                        .map(|kind| (*kind, Indicator::new(*kind)))
+                        .collect(),
+                    ..Default::default()
+                };
+            }
+        }
+    }
+
+    // Value of the last closed candle
+    pub fn value(
+        &self,
+        ticker: &Ticker,
+        interval: CandleInterval,
+        kind: IndicatorKind,
+    ) -> Option<Decimal> {
+        self.series
+            .get(&(ticker.clone(), interval))?
+            .indicators
+            .get(&kind)?
+            .value()
+    }
+
+    // Value including the forming candle
+    pub fn peek(
+        &self,
+        ticker: &Ticker,
+        interval: CandleInterval,
+        kind: IndicatorKind,
+    ) -> Option<Decimal> {
+        let series = self.series.get(&(ticker.clone(), interval))?;
+        let indicator = series.indicators.get(&kind)?;
+        match &series.forming {
+            Some(forming) => indicator.peek(forming),
+            None => indicator.value(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{atr, sma};
+
+    fn candle(start_time: u64, close: Decimal, closed: bool) -> MarketplaceCandle {
+        MarketplaceCandle {
+            ticker: Ticker::new("BTC", "USDC"),
+            interval: CandleInterval::M1,
+            open_price: close,
+            close_price: close,
+            high_price: close + dec!(1),
+            low_price: close - dec!(2),
+            trade_count: 0,
+            start_time,
+            close_time: start_time + 59_999,
+            volume: dec!(1),
+            closed,
+        }
+    }
+
+    #[test]
+    fn test_indicator_engine() {
+        let ticker = Ticker::new("BTC", "USDC");
+        let sma_3 = IndicatorKind::Sma { period: 3 };
+        let atr_3 = IndicatorKind::Atr { period: 3 };
+        let wsma_3 = IndicatorKind::Wsma { period: 3 };
+        let mut engine = IndicatorEngine::new().with_indicators(
+            &ticker,
+            CandleInterval::M1,
+            &[sma_3, atr_3, wsma_3],
+        );
+
+        let closes = [dec!(10), dec!(12), dec!(11), dec!(15), dec!(13)];
+        let candles: Vec<MarketplaceCandle> = closes
+            .iter()
+            .enumerate()
+            .map(|(i, close)| candle(i as u64 * 60_000, *close, true))
+            .collect();
+        for candle in candles.iter().take(2) {
+            engine.update(candle);
+        }
+        assert_eq!(engine.value(&ticker, CandleInterval::M1, sma_3), None);
+        // the forming candle is peeked, closed by the next one
+        engine.update(&candle(120_000, dec!(14), false));
+        assert_eq!(engine.value(&ticker, CandleInterval::M1, sma_3), None);
+        assert_eq!(
+            engine.peek(&ticker, CandleInterval::M1, sma_3),
+            Some(dec!(12))
+        );
+        engine.update(&candles[2]);
+        engine.update(&candles[2]);
+        engine.update(&candle(180_000, dec!(15), false));
+        assert_eq!(
+            engine.value(&ticker, CandleInterval::M1, sma_3),
+            Some(dec!(11))
+        );
+        // seeded with the average of the first closes, 11 + (15 - 11) / 3 if the forming one closed
+        assert_eq!(
+            engine.value(&ticker, CandleInterval::M1, wsma_3),
+            Some(dec!(11))
+        );
+        assert_eq!(
+            engine
+                .peek(&ticker, CandleInterval::M1, wsma_3)
+                .map(|wsma| wsma.round_dp(4)),
+            Some(dec!(12.3333))
+        );
+        engine.update(&candles[3]);
+        engine.update(&candles[4]);
+
+        // same values as the windowed functions, latest first
+        let recent: Vec<Decimal> = closes.iter().rev().copied().collect();
+        assert_eq!(
+            engine.value(&ticker, CandleInterval::M1, sma_3),
+            sma(&recent, 3)
+        );
+        let ranges: Vec<(Decimal, Decimal, Decimal)> = candles
+            .windows(2)
+            .rev()
+            .map(|candles| {
+                (
+                    candles[1].high_price,
+                    candles[1].low_price,
+                    candles[0].close_price,
+                )
+            })
+            .collect();
+        assert_eq!(
+            engine.value(&ticker, CandleInterval::M1, atr_3),
+            atr(&ranges, 3)
+        );
+        // 11 + (15 - 11) / 3, then + (13 - 12.3333) / 3
+        assert_eq!(
+            engine
+                .value(&ticker, CandleInterval::M1, wsma_3)
+                .map(|wsma| wsma.round_dp(4)),
+            Some(dec!(12.5556))
+        );
+
+        engine.reset(&ticker);
+        assert_eq!(engine.value(&ticker, CandleInterval::M1, sma_3), None);
+    }
+}
@@ -1,11 +1,16 @@
+use marketplace::replay::ReplayCommand;
 use marketplace::MarketplaceEvent;
 use serde::{Deserialize, Serialize};
 use state::StateEvent;
-use strategy::StrategyEvent;
+use strategy::{StrategyCommand, StrategyEvent};
+use ticker::Ticker;
 
+pub mod credentials;
+pub mod indicators;
 pub mod marketplace;
 pub mod order;
 pub mod portfolio;
+pub mod recorder;
 pub mod server;
 pub mod state;
 pub mod strategy;
@@ -23,4 +28,13 @@ pub enum AppEvent {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum AppCommandEvent {
     Pause,
+    AddTicker(Ticker),
+    // stops the strategy then unsubscribes once the ticker orders are settled
+    RemoveTicker(Ticker),
+    Replay(ReplayCommand),
+    // pause, resume or stop the strategy of a ticker
+    Strategy {
+        ticker: Ticker,
+        command: StrategyCommand,
+    },
 }
@@ -1,5 +1,3 @@
-use std::env;
-
 use anyhow::Result;
 use chrono::prelude::*;
 use hex::encode;
@@ -42,8 +40,7 @@ impl Binance {
     pub async fn get_account_overview(&self, refresh: bool) -> Result<AccountOverview> {
         let mut overview = self.account_overview.write().await;
         if refresh || overview.is_none() {
-            let api_key = env::var("BINANCE_API_KEY")?;
-            let api_secret = env::var("BINANCE_API_SECRET")?;
+            let (api_key, api_secret) = self.credentials.hmac()?;
 
             let timestamp = Utc::now().timestamp_millis();
             let params = format!("timestamp={}&omitZeroBalances=true", timestamp);
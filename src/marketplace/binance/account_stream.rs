@@ -1,28 +1,28 @@
-use std::{env, time::Duration};
+use std::time::Duration;
 
+use anyhow::anyhow;
 use base64::{prelude::BASE64_STANDARD, Engine as _};
 use chrono::Utc;
-use ed25519_dalek::{
-    ed25519::signature::SignerMut,
-    pkcs8::DecodePrivateKey, SigningKey,
-};
+use ed25519_dalek::{ed25519::signature::SignerMut, pkcs8::DecodePrivateKey, SigningKey};
 use futures::SinkExt;
 use futures_util::StreamExt;
 use rust_decimal::Decimal;
 use serde::Deserialize;
 use serde_json::{json, Value};
-use tokio::{fs, select, sync::broadcast::Sender, time::interval};
+use tokio::{select, sync::broadcast::Sender, time::interval};
 use tokio_tungstenite::connect_async;
 use tracing::{debug, error, info};
 use tungstenite::Message;
 
 use crate::{
+    credentials::Credentials,
     marketplace::{
-        binance::WS_ENDPOINT, MarketplaceAccountStream, MarketplaceEvent, MarketplaceOrderUpdate,
-        MarketplacePortfolioUpdate,
+        binance::WS_ENDPOINT, MarketplaceAccountStream, MarketplaceBalanceUpdate, MarketplaceEvent,
+        MarketplaceOrderListUpdate, MarketplaceOrderUpdate, MarketplacePortfolioUpdate,
     },
     order::{OrderStatus, OrderTrade},
     portfolio::Asset,
+    ticker::Ticker,
     AppEvent,
 };
 
@@ -101,7 +101,7 @@ struct OrderUpdate {
     pub transaction_time: u64,
     #[serde(rename = "t")]
     pub trade_id: i64,
-    
+
     #[serde(rename = "O")]
     pub creation_time: u64,
     #[serde(rename = "W")]
@@ -135,23 +135,141 @@ impl TryFrom<&OrderUpdate> for MarketplaceOrderUpdate {
                 trade_time: value.transaction_time,
                 price: value.trade_price,
                 amount: value.trade_amount,
+                fee: value.commission_amount,
+                fee_asset: value.commission_asset.clone(),
             })
         }
         Ok(update)
     }
 }
 
+#[derive(Deserialize, Clone, Debug)]
+struct BalanceUpdateStream {
+    #[serde(rename = "E")]
+    pub event_time: u64,
+    #[serde(rename = "a")]
+    pub asset: String,
+    #[serde(rename = "d")]
+    #[serde(with = "rust_decimal::serde::str")]
+    pub delta: Decimal,
+    #[serde(rename = "T")]
+    pub clear_time: u64,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+struct ListStatusOrder {
+    #[serde(rename = "i")]
+    pub id: u64,
+    #[serde(rename = "c")]
+    pub client_id: String,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+struct ListStatusStream {
+    #[serde(rename = "E")]
+    pub event_time: u64,
+    #[serde(rename = "s")]
+    pub symbol: String,
+    #[serde(rename = "g")]
+    pub list_id: i64,
+    #[serde(rename = "c")]
+    pub contingency_type: String,
+    #[serde(rename = "l")]
+    pub status: String,
+    #[serde(rename = "L")]
+    pub order_status: String,
+    #[serde(rename = "r")]
+    pub reject_reason: String,
+    #[serde(rename = "C")]
+    pub client_list_id: String,
+    #[serde(rename = "O")]
+    pub orders: Vec<ListStatusOrder>,
+}
+
+// Convert a user data stream event (shared by the websocket api and the listen key streams).
+pub(crate) fn parse_user_data_event(event: &Value) -> anyhow::Result<Option<MarketplaceEvent>> {
+    let Some(Value::String(e)) = event.get("e") else {
+        return Ok(None);
+    };
+
+    let event = match e.as_str() {
+        "executionReport" => {
+            let update = serde_json::from_value::<OrderUpdate>(event.clone())?;
+            MarketplaceEvent::OrderUpdate(
+                MarketplaceOrderUpdate::try_from(&update).map_err(|err| anyhow!(err))?,
+            )
+        }
+        "outboundAccountPosition" => {
+            let update = serde_json::from_value::<AccountUpdateStream>(event.clone())?;
+            MarketplaceEvent::PortfolioUpdate(MarketplacePortfolioUpdate {
+                time: update.event_time,
+                assets: update
+                    .balances
+                    .iter()
+                    .map(|b| Asset {
+                        symbol: b.symbol.clone(),
+                        amount: b.free,
+                        locked: b.locked,
+                        value: None,
+                    })
+                    .collect(),
+            })
+        }
+        "balanceUpdate" | "externalLockUpdate" => {
+            let update = serde_json::from_value::<BalanceUpdateStream>(event.clone())?;
+            MarketplaceEvent::BalanceUpdate(MarketplaceBalanceUpdate {
+                time: update.event_time,
+                asset: update.asset,
+                delta: update.delta,
+                locked: e == "externalLockUpdate",
+                clear_time: update.clear_time,
+            })
+        }
+        "listStatus" => {
+            let update = serde_json::from_value::<ListStatusStream>(event.clone())?;
+            MarketplaceEvent::OrderListUpdate(MarketplaceOrderListUpdate {
+                time: update.event_time,
+                ticker: Ticker::try_from(&update.symbol).map_err(|err| anyhow!(err))?,
+                list_id: update.list_id,
+                client_list_id: update.client_list_id,
+                contingency_type: update.contingency_type,
+                status: update.status,
+                order_status: update.order_status,
+                reject_reason: if update.reject_reason == "NONE" {
+                    None
+                } else {
+                    Some(update.reject_reason)
+                },
+                orders: update
+                    .orders
+                    .into_iter()
+                    .map(|order| (order.id.to_string(), order.client_id))
+                    .collect(),
+            })
+        }
+        "eventStreamTerminated" | "listenKeyExpired" => {
+            error!("User data stream terminated : {}", event);
+            return Ok(None);
+        }
+        other => {
+            debug!("Ignored user data event {other}");
+            return Ok(None);
+        }
+    };
+
+    Ok(Some(event))
+}
+
 async fn logon(
     ws: &mut tokio_tungstenite::WebSocketStream<
         tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
     >,
     id: u64,
+    credentials: &Credentials,
 ) -> anyhow::Result<()> {
-    let api_key = env::var("BINANCE_SECURE_API_KEY")?;
-    let private_key = env::var("BINANCE_PRIVATE_KEY")?;
-    let private_key = fs::read_to_string(private_key).await?;
+    let (api_key, private_key) = credentials.ed25519()?;
 
-    let mut signing_key = SigningKey::from_pkcs8_pem(private_key.as_str())?;
+    let mut signing_key = SigningKey::from_pkcs8_pem(private_key)?;
     let timestamp = Utc::now().timestamp_millis();
     let payload = format!("apiKey={}&timestamp={}", api_key, timestamp);
 
@@ -191,7 +309,21 @@ async fn subscribe(
 }
 
 impl MarketplaceAccountStream for Binance {
+    // The websocket api needs an Ed25519 key, HMAC only accounts fall back to a listen key stream.
     async fn start_account_stream(&mut self, tx_app: Sender<AppEvent>) -> anyhow::Result<()> {
+        if self.credentials.ed25519().is_ok() {
+            self.start_ws_api_account_stream(tx_app).await
+        } else if self.credentials.hmac().is_ok() {
+            info!("No Ed25519 key configured, using a listen key user data stream");
+            self.start_listen_key_account_stream(tx_app).await
+        } else {
+            Err(anyhow!("No credentials for the user data stream"))
+        }
+    }
+}
+
+impl Binance {
+    async fn start_ws_api_account_stream(&self, tx_app: Sender<AppEvent>) -> anyhow::Result<()> {
         let request = (*WS_ENDPOINT).to_string();
 
         loop {
@@ -224,7 +356,7 @@ impl MarketplaceAccountStream for Binance {
             let mut subscribe_request_id = 0;
 
             let mut last_logon_attempt = Utc::now().timestamp_millis() as u64;
-            logon(&mut ws_stream, req_id).await?;
+            logon(&mut ws_stream, req_id, &self.credentials).await?;
             req_id += 1;
 
             loop {
@@ -272,7 +404,7 @@ impl MarketplaceAccountStream for Binance {
                                             if Duration::from_millis(now.saturating_sub(last_logon_attempt)) > Duration::from_secs(60) {
                                                 logon_request_id = req_id;
                                                 last_logon_attempt = now;
-                                                logon(&mut ws_stream, req_id).await?;
+                                                logon(&mut ws_stream, req_id, &self.credentials).await?;
                                                 req_id += 1;
                                             } else {
                                                 info!("Too early to login");
@@ -281,45 +413,13 @@ impl MarketplaceAccountStream for Binance {
                                     }
 
                                 } else if let Some(event) = parsed.get("event") {
-                                    if let Some(Value::String(e)) = event.get("e") {
-                                        match e.as_str() {
-                                            "executionReport" => {
-                                                match serde_json::from_value::<OrderUpdate>(event.clone()) {
-                                                    Ok(update) => {
-                                                        match MarketplaceOrderUpdate::try_from(&update) {
-                                                            Ok(update) => {
-                                                               let _ = tx_app.send(AppEvent::MarketPlace(MarketplaceEvent::OrderUpdate(update)));
-                                                            }
-                                                            Err(err) => {
-                                                                error!("{err}");
-                                                            }
-                                                        }
-                                                    }
-                                                    Err(err) => {
-                                                        error!("{err}");
-                                                    }
-                                                }
-                                            },
-                                            "outboundAccountPosition" => {
-                                                match serde_json::from_value::<AccountUpdateStream>(event.clone()) {
-                                                    Ok(update) => {
-                                                        let _ = tx_app.send(AppEvent::MarketPlace(
-                                                            MarketplaceEvent::PortfolioUpdate(MarketplacePortfolioUpdate {
-                                                                time: update.event_time,
-                                                                assets: update.balances.iter().map(|b| Asset {
-                                                                    symbol: b.symbol.clone(),
-                                                                    amount: b.free,
-                                                                    locked: b.locked,
-                                                                    value: None
-                                                                }).collect()
-                                                            })));
-                                                    }
-                                                    Err(err) => {
-                                                        error!("{err}");
-                                                    }
-                                                }
-                                            }
-                                            _ => {}
+                                    match parse_user_data_event(event) {
+                                        Ok(Some(event)) => {
+                                            let _ = tx_app.send(AppEvent::MarketPlace(event));
+                                        }
+                                        Ok(None) => {}
+                                        Err(err) => {
+                                            error!("User data event parsing error : {err}");
                                         }
                                     }
                                 }
@@ -350,3 +450,58 @@ impl MarketplaceAccountStream for Binance {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_parse_user_data_events() {
+        let event = json!({
+            "e": "executionReport", "E": 1499405658658_u64, "s": "ETHBTC", "c": "mUvoqJxFIILMdfAW5iGSOW",
+            "S": "BUY", "o": "LIMIT", "f": "GTC", "q": "1.00000000", "p": "0.10264410", "P": "0.00000000",
+            "F": "0.00000000", "g": -1, "C": "", "x": "TRADE", "X": "FILLED", "r": "NONE", "i": 4293153,
+            "l": "1.00000000", "z": "1.00000000", "L": "0.10264410", "n": "0.00100000", "N": "ETH",
+            "T": 1499405658657_u64, "t": 12, "I": 8641984, "w": false, "m": false, "M": false,
+            "O": 1499405658657_u64, "Z": "0.10264410", "Y": "0.10264410", "Q": "0.00000000",
+            "W": 1499405658657_u64, "V": "NONE"
+        });
+        match parse_user_data_event(&event) {
+            Ok(Some(MarketplaceEvent::OrderUpdate(update))) => {
+                let trade = update.trade.unwrap();
+                assert_eq!(trade.fee, dec!(0.001));
+                assert_eq!(trade.fee_asset, Some("ETH".to_string()));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let event = json!({
+            "e": "balanceUpdate", "E": 1573200697110_u64, "a": "BTC", "d": "100.00000000", "T": 1573200697068_u64
+        });
+        match parse_user_data_event(&event) {
+            Ok(Some(MarketplaceEvent::BalanceUpdate(update))) => {
+                assert_eq!(update.delta, dec!(100));
+                assert!(!update.locked);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let event = json!({
+            "e": "listStatus", "E": 1564035303637_u64, "s": "ETHBTC", "g": 2, "c": "OCO",
+            "l": "EXEC_STARTED", "L": "EXECUTING", "r": "NONE", "C": "F4QN4G8DlFATFlIUQ0cjdD",
+            "T": 1564035303625_u64,
+            "O": [
+                {"s": "ETHBTC", "i": 17, "c": "AJYsMjErWJesZvqlJCTUgL"},
+                {"s": "ETHBTC", "i": 18, "c": "bfYPSQdLoqAJeNrOr9adzq"}
+            ]
+        });
+        match parse_user_data_event(&event) {
+            Ok(Some(MarketplaceEvent::OrderListUpdate(update))) => {
+                assert_eq!(update.orders.len(), 2);
+                assert_eq!(update.reject_reason, None);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
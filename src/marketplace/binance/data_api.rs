@@ -9,8 +9,10 @@ use serde::Serialize;
 use serde_json::Value;
 use tracing::info;
 
-use crate::marketplace::binance::{Binance, ENDPOINT, PUBLIC_ENDPOINT};
-use crate::marketplace::MarketplaceCandle;
+use crate::marketplace::binance::data_stream::aggressor_side;
+use crate::marketplace::binance::{Binance, ENDPOINT};
+use crate::marketplace::candle::CandleInterval;
+use crate::marketplace::{MarketplaceBook, MarketplaceCandle, MarketplaceTrade};
 use crate::ticker::Ticker;
 
 #[derive(Deserialize, Debug, Clone, Default)]
@@ -27,9 +29,10 @@ pub struct Candle {
 }
 
 impl Candle {
-    pub fn into_candle_event(self, ticker: Ticker) -> MarketplaceCandle {
+    pub fn into_candle_event(self, ticker: Ticker, interval: CandleInterval) -> MarketplaceCandle {
         MarketplaceCandle {
             ticker,
+            interval,
             open_price: self.open_price,
             close_price: self.close_price,
             high_price: self.high_price,
@@ -84,8 +87,12 @@ impl TryFrom<Value> for Candle {
                 .and_then(|v| {
                     Decimal::from_str(v).map_err(|e| anyhow!("Invalid decimal: {}", e))
                 })?,
-            trade_count: 0,
-            volume: dec!(0),
+            trade_count: value[8].as_u64().unwrap_or(0),
+            // quote asset volume, like the kline stream
+            volume: value[7]
+                .as_str()
+                .and_then(|v| Decimal::from_str(v).ok())
+                .unwrap_or(dec!(0)),
         };
         Ok(candle)
     }
@@ -145,6 +152,59 @@ pub struct Depth {
     asks: Vec<(Decimal, Decimal)>,
 }
 
+impl Depth {
+    pub fn into_book_event(self, ticker: Ticker, time: u64) -> MarketplaceBook {
+        MarketplaceBook {
+            ticker,
+            first_update_id: 0,
+            final_update_id: self.last_update_id,
+            time,
+            asks: self.asks,
+            bids: self.bids,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct AggTrade {
+    #[serde(rename = "a")]
+    pub agg_trade_id: u64,
+
+    #[serde(rename = "p")]
+    #[serde(with = "rust_decimal::serde::str")]
+    pub price: Decimal,
+
+    #[serde(rename = "q")]
+    #[serde(with = "rust_decimal::serde::str")]
+    pub quantity: Decimal,
+
+    #[serde(rename = "f")]
+    pub first_trade_id: u64,
+
+    #[serde(rename = "l")]
+    pub last_trade_id: u64,
+
+    #[serde(rename = "T")]
+    pub trade_time: u64,
+
+    #[serde(rename = "m")]
+    pub buyer_maker: bool,
+}
+
+impl AggTrade {
+    // Played as a trade, like the aggTrades archives
+    pub fn into_trade_event(self, ticker: Ticker) -> MarketplaceTrade {
+        MarketplaceTrade {
+            ticker,
+            trade_id: self.agg_trade_id,
+            trade_time: self.trade_time,
+            price: self.price,
+            quantity: self.quantity,
+            aggressor_side: Some(aggressor_side(self.buyer_maker)),
+        }
+    }
+}
+
 impl Binance {
     pub async fn get_candles(
         &self,
@@ -164,7 +224,7 @@ impl Binance {
             params.push(("endTime", to.to_string()));
         }
         let url = Url::parse_with_params(
-            format!("{}/api/v3/klines", *PUBLIC_ENDPOINT).as_str(),
+            format!("{}/api/v3/klines", self.public_endpoint()).as_str(),
             &params,
         )
         .unwrap();
@@ -178,8 +238,103 @@ impl Binance {
             .collect::<Vec<Candle>>())
     }
 
-    pub async fn get_depth(&self, ticker: &Ticker) -> Result<Depth> {
-        let url = format!("{}/api/v3/depth?symbol={}", *ENDPOINT, ticker);
+    // Candles between from and to, paging through the 1000 candles limit of a single call.
+    pub async fn get_candles_range(
+        &self,
+        symbol: &str,
+        interval: &str,
+        from: u64,
+        to: u64,
+    ) -> Result<Vec<Candle>> {
+        let mut candles: Vec<Candle> = Vec::new();
+        let mut start = from;
+        while start <= to {
+            let page = self
+                .get_candles(symbol, interval, Some(start), Some(to))
+                .await?;
+            match page.last() {
+                Some(last) if last.start_time >= start => {
+                    start = last.start_time + 1;
+                    candles.extend(page);
+                }
+                _ => break,
+            }
+        }
+        Ok(candles)
+    }
+
+    // Aggregated trades between from and to (at most one hour apart), 1000 per call.
+    pub async fn get_agg_trades(&self, symbol: &str, from: u64, to: u64) -> Result<Vec<AggTrade>> {
+        let params = [
+            ("symbol", symbol.to_string()),
+            ("startTime", from.to_string()),
+            ("endTime", to.to_string()),
+            ("limit", "1000".to_string()),
+        ];
+        let url = Url::parse_with_params(
+            format!("{}/api/v3/aggTrades", self.public_endpoint()).as_str(),
+            &params,
+        )?;
+
+        info!("{}", url);
+
+        let r = self.client.get(url).send().await?.error_for_status()?;
+        Ok(r.json().await?)
+    }
+
+    // Aggregated trades from an id, the time range can not be combined with it.
+    pub async fn get_agg_trades_from_id(
+        &self,
+        symbol: &str,
+        from_id: u64,
+    ) -> Result<Vec<AggTrade>> {
+        let params = [
+            ("symbol", symbol.to_string()),
+            ("fromId", from_id.to_string()),
+            ("limit", "1000".to_string()),
+        ];
+        let url = Url::parse_with_params(
+            format!("{}/api/v3/aggTrades", self.public_endpoint()).as_str(),
+            &params,
+        )?;
+
+        info!("{}", url);
+
+        let r = self.client.get(url).send().await?.error_for_status()?;
+        Ok(r.json().await?)
+    }
+
+    // Aggregated trades between from and to, paging through the 1000 trades limit of a single call.
+    pub async fn get_agg_trades_range(
+        &self,
+        symbol: &str,
+        from: u64,
+        to: u64,
+    ) -> Result<Vec<AggTrade>> {
+        let mut trades: Vec<AggTrade> = Vec::new();
+        let mut page = self.get_agg_trades(symbol, from, to).await?;
+        loop {
+            let full_page = page.len() >= 1000;
+            let Some(last) = page.last() else {
+                break;
+            };
+            // the next page starts after the last trade, many trades can share its time
+            let next_id = last.agg_trade_id + 1;
+            let past_end = last.trade_time > to;
+            trades.extend(page.into_iter().filter(|trade| trade.trade_time <= to));
+            if !full_page || past_end {
+                break;
+            }
+            page = self.get_agg_trades_from_id(symbol, next_id).await?;
+        }
+        Ok(trades)
+    }
+
+    pub async fn get_depth(&self, ticker: &Ticker, limit: u16) -> Result<Depth> {
+        let url = format!(
+            "{}/api/v3/depth?symbol={}&limit={}",
+            *ENDPOINT, ticker, limit
+        );
         info!("{}", url);
         let r = self.client.get(url).send().await?;
         let depth: Depth = r.json().await?;
@@ -212,7 +367,9 @@ mod tests {
         let res = Candle::try_from(json);
         assert!(res.is_ok());
         let candle = res.unwrap();
-        assert_eq!(candle.low_price, dec!(0.01575800))
+        assert_eq!(candle.low_price, dec!(0.01575800));
+        assert_eq!(candle.volume, dec!(2434.19055334));
+        assert_eq!(candle.trade_count, 308);
     }
 
     #[test]
@@ -1,12 +1,17 @@
+use std::str::FromStr;
 use std::time::Duration;
 
+use anyhow::anyhow;
+
 use chrono::Utc;
 use futures::SinkExt;
 use futures_util::StreamExt;
 use rust_decimal::Decimal;
 use serde::Deserialize;
+use serde_json::json;
 use serde_json::Value;
 use tokio::sync::broadcast::Sender;
+use tokio::sync::mpsc;
 use tokio_tungstenite::connect_async;
 use tracing::debug;
 use tracing::error;
@@ -14,10 +19,16 @@ use tracing::info;
 use tungstenite::Message;
 
 use crate::marketplace::binance::STREAM_ENDPOINT;
+use crate::marketplace::candle::CandleInterval;
+use crate::marketplace::MarketplaceAggTrade;
 use crate::marketplace::MarketplaceBook;
+use crate::marketplace::MarketplaceBookTicker;
 use crate::marketplace::MarketplaceCandle;
 use crate::marketplace::MarketplaceEvent;
+use crate::marketplace::MarketplaceStreamStatus;
 use crate::marketplace::MarketplaceTrade;
+use crate::marketplace::StreamStatus;
+use crate::order::OrderSide;
 use crate::ticker::Ticker;
 use crate::AppEvent;
 
@@ -130,7 +141,145 @@ pub struct TradeStream {
     pub quantity: Decimal,
 
     #[serde(rename = "m")]
-    pub maker_maker: bool,
+    pub buyer_maker: bool,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[allow(dead_code)]
+pub struct AggTradeStream {
+    #[serde(rename = "E")]
+    pub event_time: u64,
+
+    #[serde(rename = "s")]
+    pub symbol: String,
+
+    #[serde(rename = "a")]
+    pub agg_trade_id: u64,
+
+    #[serde(rename = "f")]
+    pub first_trade_id: u64,
+
+    #[serde(rename = "l")]
+    pub last_trade_id: u64,
+
+    #[serde(rename = "T")]
+    pub trade_time: u64,
+
+    #[serde(rename = "p")]
+    #[serde(with = "rust_decimal::serde::str")]
+    pub price: Decimal,
+
+    #[serde(rename = "q")]
+    #[serde(with = "rust_decimal::serde::str")]
+    pub quantity: Decimal,
+
+    #[serde(rename = "m")]
+    pub buyer_maker: bool,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct BookTickerStream {
+    #[serde(rename = "u")]
+    pub update_id: u64,
+
+    #[serde(rename = "s")]
+    pub symbol: String,
+
+    #[serde(rename = "b")]
+    #[serde(with = "rust_decimal::serde::str")]
+    pub bid_price: Decimal,
+
+    #[serde(rename = "B")]
+    #[serde(with = "rust_decimal::serde::str")]
+    pub bid_quantity: Decimal,
+
+    #[serde(rename = "a")]
+    #[serde(with = "rust_decimal::serde::str")]
+    pub ask_price: Decimal,
+
+    #[serde(rename = "A")]
+    #[serde(with = "rust_decimal::serde::str")]
+    pub ask_quantity: Decimal,
+}
+
+// Market data streams subscribed for each ticker
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MarketStream {
+    Kline,
+    Depth,
+    Trade,
+    AggTrade,
+    BookTicker,
+}
+
+impl MarketStream {
+    pub const DEFAULT: [MarketStream; 2] = [MarketStream::Kline, MarketStream::Depth];
+
+    fn name(&self, ticker: &Ticker) -> String {
+        let symbol = format!(
+            "{}{}",
+            ticker.base.to_lowercase(),
+            ticker.quote.to_lowercase()
+        );
+        match self {
+            MarketStream::Kline => format!("{symbol}@kline_1m"),
+            MarketStream::Depth => format!("{symbol}@depth5"),
+            MarketStream::Trade => format!("{symbol}@trade"),
+            MarketStream::AggTrade => format!("{symbol}@aggTrade"),
+            MarketStream::BookTicker => format!("{symbol}@bookTicker"),
+        }
+    }
+}
+
+impl FromStr for MarketStream {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "kline" => Ok(MarketStream::Kline),
+            "depth" => Ok(MarketStream::Depth),
+            "trade" => Ok(MarketStream::Trade),
+            "aggTrade" => Ok(MarketStream::AggTrade),
+            "bookTicker" => Ok(MarketStream::BookTicker),
+            other => Err(format!("Unknown market stream {other}")),
+        }
+    }
+}
+
+// The aggressor is the taker
+pub(crate) fn aggressor_side(buyer_maker: bool) -> OrderSide {
+    if buyer_maker {
+        OrderSide::Sell
+    } else {
+        OrderSide::Buy
+    }
+}
+
+fn parse_agg_trade(value: &Value) -> anyhow::Result<MarketplaceEvent> {
+    let trade = serde_json::from_value::<AggTradeStream>(value.clone())?;
+    Ok(MarketplaceEvent::AggTrade(MarketplaceAggTrade {
+        ticker: Ticker::try_from(&trade.symbol).map_err(|err| anyhow!(err))?,
+        agg_trade_id: trade.agg_trade_id,
+        first_trade_id: trade.first_trade_id,
+        last_trade_id: trade.last_trade_id,
+        trade_time: trade.trade_time,
+        price: trade.price,
+        quantity: trade.quantity,
+        aggressor_side: aggressor_side(trade.buyer_maker),
+    }))
+}
+
+fn parse_book_ticker(value: &Value) -> anyhow::Result<MarketplaceEvent> {
+    let ticker = serde_json::from_value::<BookTickerStream>(value.clone())?;
+    Ok(MarketplaceEvent::BookTicker(MarketplaceBookTicker {
+        ticker: Ticker::try_from(&ticker.symbol).map_err(|err| anyhow!(err))?,
+        time: Utc::now().timestamp_millis() as u64,
+        update_id: ticker.update_id,
+        bid_price: ticker.bid_price,
+        bid_quantity: ticker.bid_quantity,
+        ask_price: ticker.ask_price,
+        ask_quantity: ticker.ask_quantity,
+    }))
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -186,46 +335,29 @@ pub struct DepthUpdateStream {
 
 impl Binance {
     pub async fn connect_stream(&self, tickers: &[Ticker], tx: Sender<AppEvent>) {
-        let trade_params = tickers
-            .iter()
-            .map(|s| format!("{}{}@trade", s.base.to_lowercase(), s.quote.to_lowercase()))
-            .collect::<Vec<String>>()
-            .join("/");
+        let (tx_control, mut rx_control) = mpsc::unbounded_channel::<String>();
+        *self.stream_tickers.write().await = tickers.to_vec();
+        *self.stream_control.write().await = Some(tx_control);
 
-        let candle_params = tickers
-            .iter()
-            .map(|s| {
-                format!(
-                    "{}{}@kline_1m",
-                    s.base.to_lowercase(),
-                    s.quote.to_lowercase()
-                )
-            })
-            .collect::<Vec<String>>()
-            .join("/");
-
-        let depth_params = tickers
-            .iter()
-            .map(|s| {
-                format!(
-                    "{}{}@depth{}",
-                    s.base.to_lowercase(),
-                    s.quote.to_lowercase(),
-                    5
-                )
-            })
-            .collect::<Vec<String>>()
-            .join("/");
-
-        let request = format!(
-            "{}/stream?streams={}/{}",
-            *STREAM_ENDPOINT, candle_params, depth_params
-        );
+        let mut last_message_time: Option<u64> = None;
+        let mut disconnected_at: Option<u64> = None;
 
         loop {
             let mut ws_stream;
             let response;
 
+            // subscriptions changed at runtime are kept across reconnections
+            let tickers = self.stream_tickers.read().await.clone();
+            let request = format!(
+                "{}/stream?streams={}",
+                *STREAM_ENDPOINT,
+                tickers
+                    .iter()
+                    .flat_map(|ticker| self.stream_names(ticker))
+                    .collect::<Vec<String>>()
+                    .join("/")
+            );
+
             loop {
                 info!("Connecting to market data stream {request}");
                 match connect_async(request.clone()).await {
@@ -246,11 +378,29 @@ impl Binance {
                 debug!("\t{header}");
             }
 
+            send_stream_status(&tx, StreamStatus::Connected);
+            if let Some(since) = disconnected_at.take() {
+                self.backfill_stream(&tickers, since, &tx).await;
+            }
+
             loop {
-                match tokio::time::timeout(Duration::from_secs(60), ws_stream.next()).await {
+                let message = tokio::select! {
+                    message = tokio::time::timeout(Duration::from_secs(60), ws_stream.next()) => message,
+                    Some(frame) = rx_control.recv() => {
+                        debug!("Sending stream frame {frame}");
+                        if let Err(err) = ws_stream.send(Message::Text(frame.into())).await {
+                            error!("Failed to send stream frame: {}", err);
+                            break;
+                        }
+                        continue;
+                    }
+                };
+
+                match message {
                     Ok(Some(message)) => match message {
                         Ok(Message::Text(message)) => {
                             debug!("{:?}", message);
+                            last_message_time = Some(Utc::now().timestamp_millis() as u64);
                             match serde_json::de::from_slice::<Value>(message.as_ref()) {
                                 Ok(value) => match (value.get("data"), value.get("stream")) {
                                     (Some(value), Some(Value::String(stream))) => {
@@ -274,6 +424,10 @@ impl Binance {
                                                                                     .trade_id,
                                                                                 trade_time: trade
                                                                                     .trade_time,
+                                                                                aggressor_side:
+                                                                                    Some(aggressor_side(
+                                                                                        trade.buyer_maker,
+                                                                                    )),
                                                                             },
                                                                         ),
                                                                     ));
@@ -300,6 +454,8 @@ impl Binance {
                                                                         MarketplaceEvent::Candle(
                                                                             MarketplaceCandle {
                                                                                 ticker,
+                                                                                interval:
+                                                                                    CandleInterval::M1,
                                                                                 high_price: candle
                                                                                     .data
                                                                                     .high_price,
@@ -341,6 +497,28 @@ impl Binance {
                                                     }
                                                 }
                                             }
+                                            Some(Value::String(e)) if e == "aggTrade" => {
+                                                match parse_agg_trade(value) {
+                                                    Ok(event) => {
+                                                        let _ =
+                                                            tx.send(AppEvent::MarketPlace(event));
+                                                    }
+                                                    Err(err) => {
+                                                        error!("Stream parsing error : {}", err);
+                                                    }
+                                                }
+                                            }
+                                            None if stream.ends_with("@bookTicker") => {
+                                                match parse_book_ticker(value) {
+                                                    Ok(event) => {
+                                                        let _ =
+                                                            tx.send(AppEvent::MarketPlace(event));
+                                                    }
+                                                    Err(err) => {
+                                                        error!("Stream parsing error : {}", err);
+                                                    }
+                                                }
+                                            }
                                             Some(Value::String(e))
                                                 if e == "depthUpdate" =>
                                             {
@@ -407,12 +585,15 @@ impl Binance {
                                             }
                                         }
                                     }
+                                    _ if value.get("id").is_some() => {
+                                        info!("Stream response {}", value);
+                                    }
                                     _ => {
                                         error!("Unknown json");
                                     }
                                 },
                                 Err(err) => {
-                                    error!("Invalid json");
+                                    error!("Invalid json: {}", err);
                                 }
                             }
                         }
@@ -441,10 +622,130 @@ impl Binance {
                 }
             }
 
+            let since = *disconnected_at
+                .get_or_insert(last_message_time.unwrap_or(Utc::now().timestamp_millis() as u64));
+            send_stream_status(&tx, StreamStatus::Reconnecting { since });
+
             info!("Reconnecting");
             tokio::time::sleep(Duration::from_secs(3)).await;
         }
     }
+
+    // Add the streams of a ticker to the live connection.
+    pub async fn subscribe(&self, ticker: &Ticker) {
+        {
+            let mut tickers = self.stream_tickers.write().await;
+            if tickers.contains(ticker) {
+                return;
+            }
+            tickers.push(ticker.clone());
+        }
+        self.send_stream_frame("SUBSCRIBE", self.stream_names(ticker))
+            .await;
+    }
+
+    pub async fn unsubscribe(&self, ticker: &Ticker) {
+        self.stream_tickers
+            .write()
+            .await
+            .retain(|stream_ticker| stream_ticker != ticker);
+        self.send_stream_frame("UNSUBSCRIBE", self.stream_names(ticker))
+            .await;
+    }
+
+    fn stream_names(&self, ticker: &Ticker) -> Vec<String> {
+        self.market_streams
+            .iter()
+            .map(|stream| stream.name(ticker))
+            .collect()
+    }
+
+    async fn send_stream_frame(&self, method: &str, params: Vec<String>) {
+        let frame = json!({
+            "method": method,
+            "params": params,
+            "id": Utc::now().timestamp_millis(),
+        });
+        // without a running stream, the next connection will use the updated tickers
+        if let Some(tx_control) = self.stream_control.read().await.as_ref() {
+            let _ = tx_control.send(frame.to_string());
+        }
+    }
+
+    // Reload the 1m candles missed since the disconnection and resync the books.
+    // A ticker whose candles could not be reloaded stays not ready until the next reconnection.
+    async fn backfill_stream(&self, tickers: &[Ticker], since: u64, tx: &Sender<AppEvent>) {
+        let now = Utc::now().timestamp_millis() as u64;
+        // start from the candle which was in progress at the disconnection
+        let from = since - since % 60_000;
+
+        for ticker in tickers {
+            let mut candles = None;
+            for attempt in 1..=3 {
+                match self
+                    .get_candles_range(&ticker.to_string(), CandleInterval::M1.as_str(), from, now)
+                    .await
+                {
+                    Ok(result) => {
+                        candles = Some(result);
+                        break;
+                    }
+                    Err(err) => {
+                        error!(
+                            "Failed to backfill candles for {} (attempt {}) : {}",
+                            ticker, attempt, err
+                        );
+                        tokio::time::sleep(Duration::from_secs(5)).await;
+                    }
+                }
+            }
+            let Some(candles) = candles else {
+                continue;
+            };
+
+            info!("Backfilled {} candles for {}", candles.len(), ticker);
+            for candle in candles {
+                let mut candle = candle.into_candle_event(ticker.clone(), CandleInterval::M1);
+                candle.closed = candle.close_time < now;
+                // without klines, the trades build the candle in progress
+                if !candle.closed && !self.market_streams.contains(&MarketStream::Kline) {
+                    continue;
+                }
+                let _ = tx.send(AppEvent::MarketPlace(MarketplaceEvent::Candle(candle)));
+            }
+
+            // the strategies take the books again from this status
+            send_stream_status(
+                tx,
+                StreamStatus::Backfilled {
+                    ticker: ticker.clone(),
+                    from: since,
+                    to: now,
+                },
+            );
+
+            match self.get_depth(ticker, 5).await {
+                Ok(depth) => {
+                    let _ = tx.send(AppEvent::MarketPlace(MarketplaceEvent::Book(
+                        depth.into_book_event(ticker.clone(), now),
+                    )));
+                }
+                Err(err) => {
+                    error!("Failed to resync book for {} : {}", ticker, err);
+                }
+            }
+        }
+    }
+}
+
+fn send_stream_status(tx: &Sender<AppEvent>, status: StreamStatus) {
+    info!("Market data stream status {:?}", status);
+    let _ = tx.send(AppEvent::MarketPlace(MarketplaceEvent::StreamStatus(
+        MarketplaceStreamStatus {
+            time: Utc::now().timestamp_millis() as u64,
+            status,
+        },
+    )));
 }
 
 fn extract_ticker_from_stream(stream: &str) -> Option<Ticker> {
@@ -455,3 +756,38 @@ fn extract_ticker_from_stream(stream: &str) -> Option<Ticker> {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_parse_agg_trade_and_book_ticker() {
+        let value = json!({
+            "e": "aggTrade", "E": 1672515782136_u64, "s": "BNBBTC", "a": 12345, "p": "0.001",
+            "q": "100", "f": 100, "l": 105, "T": 1672515782136_u64, "m": true, "M": true
+        });
+        match parse_agg_trade(&value).unwrap() {
+            MarketplaceEvent::AggTrade(trade) => {
+                assert_eq!(trade.ticker, Ticker::new("BNB", "BTC"));
+                assert_eq!(trade.aggressor_side, OrderSide::Sell);
+                assert_eq!(trade.last_trade_id, 105);
+                assert_eq!(trade.quantity, dec!(100));
+            }
+            other => panic!("Unexpected event {:?}", other),
+        }
+
+        let value = json!({
+            "u": 400900217, "s": "BNBUSDT", "b": "25.35190000", "B": "31.21000000",
+            "a": "25.36520000", "A": "40.66000000"
+        });
+        match parse_book_ticker(&value).unwrap() {
+            MarketplaceEvent::BookTicker(ticker) => {
+                assert_eq!(ticker.bid_price, dec!(25.3519));
+                assert_eq!(ticker.ask_quantity, dec!(40.66));
+            }
+            other => panic!("Unexpected event {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,360 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tracing::info;
+
+use crate::marketplace::candle::CandleInterval;
+use crate::marketplace::MarketplaceEvent;
+use crate::ticker::Ticker;
+
+use super::import::{synthetic_book, SyntheticBook};
+use super::Binance;
+
+// The aggTrades api does not accept a wider time range.
+const WINDOW: u64 = 3_600_000;
+
+pub struct DownloadParams {
+    pub tickers: Vec<Ticker>,
+    pub from: u64,
+    pub to: u64,
+    pub trades: bool,
+    // top of book events built from the trades, the replays match the orders on the books
+    pub book: Option<SyntheticBook>,
+}
+
+// Progress of a download, saved after each window so it can be resumed.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct DownloadState {
+    symbols: Vec<String>,
+    from: u64,
+    to: u64,
+    trades: bool,
+    #[serde(default)]
+    book: Option<SyntheticBook>,
+    // start of the next window to download
+    next_time: u64,
+    // size of events.jsonl once the previous windows were written
+    offset: u64,
+}
+
+impl Binance {
+    // Download 1m klines (and aggregated trades) into a replay dataset (data_path/events.jsonl).
+    // Events are written window by window ordered by time, an interrupted download restarts from the last complete window.
+    pub async fn download(&self, params: &DownloadParams, data_path: &Path) -> Result<()> {
+        fs::create_dir_all(data_path).await?;
+        let events_path = data_path.join("events.jsonl");
+        let state_path = data_path.join("download.json");
+
+        let symbols: Vec<String> = params.tickers.iter().map(|t| t.to_string()).collect();
+        let mut state = match fs::read_to_string(&state_path).await {
+            Ok(content) => {
+                let state: DownloadState = serde_json::from_str(&content)?;
+                if state.symbols != symbols
+                    || state.from != params.from
+                    || state.to != params.to
+                    || state.trades != params.trades
+                    || state.book != params.book
+                {
+                    anyhow::bail!(
+                        "{} belongs to another download, remove it or use another directory",
+                        state_path.display()
+                    );
+                }
+                info!("Resuming download at {}", state.next_time);
+                state
+            }
+            Err(_) => DownloadState {
+                symbols,
+                from: params.from,
+                to: params.to,
+                trades: params.trades,
+                book: params.book.clone(),
+                next_time: params.from,
+                offset: 0,
+            },
+        };
+
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(&events_path)
+            .await
+            .with_context(|| format!("Could not open {}", events_path.display()))?;
+        // drop what was written after the last saved window
+        file.set_len(state.offset).await?;
+        let mut file = OpenOptions::new().append(true).open(&events_path).await?;
+        let mut last_books: HashMap<Ticker, u64> = HashMap::new();
+
+        while state.next_time < params.to {
+            let window_end = (state.next_time + WINDOW).min(params.to) - 1;
+            let mut events = Vec::new();
+
+            for ticker in &params.tickers {
+                let symbol = ticker.to_string();
+                let candles = self
+                    .get_candles_range(
+                        &symbol,
+                        CandleInterval::M1.as_str(),
+                        state.next_time,
+                        window_end,
+                    )
+                    .await?;
+                events.extend(candles.into_iter().map(|candle| {
+                    MarketplaceEvent::Candle(
+                        candle.into_candle_event(ticker.clone(), CandleInterval::M1),
+                    )
+                }));
+
+                if params.trades {
+                    let trades = self
+                        .get_agg_trades_range(&symbol, state.next_time, window_end)
+                        .await?;
+                    for trade in trades {
+                        let trade = trade.into_trade_event(ticker.clone());
+                        let book = params.book.as_ref().filter(|book| {
+                            last_books
+                                .get(ticker)
+                                .is_none_or(|time| trade.trade_time >= time + book.interval)
+                        });
+                        let book = book.map(|book| synthetic_book(&trade, book.spread));
+                        events.push(MarketplaceEvent::Trade(trade));
+                        if let Some(book) = book {
+                            last_books.insert(ticker.clone(), book.time);
+                            events.push(MarketplaceEvent::Book(book));
+                        }
+                    }
+                }
+            }
+
+            // stable: candles stay before the trades of the same time
+            events.sort_by_key(|event| event.get_time());
+
+            let mut content = String::new();
+            for event in &events {
+                content.push_str(&serde_json::to_string(event)?);
+                content.push('\n');
+            }
+            file.write_all(content.as_bytes()).await?;
+            file.flush().await?;
+
+            state.offset += content.len() as u64;
+            state.next_time = window_end + 1;
+            save_state(&state_path, &state).await?;
+
+            info!(
+                "Downloaded {} events up to {} ({}%)",
+                events.len(),
+                window_end,
+                (state.next_time - params.from) * 100 / (params.to - params.from).max(1)
+            );
+        }
+
+        Ok(())
+    }
+}
+
+async fn save_state(path: &PathBuf, state: &DownloadState) -> Result<()> {
+    // write then rename, the state must never be half written
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string(state)?).await?;
+    fs::rename(&tmp, path).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::marketplace::replay::ReplayMarketplace;
+    use crate::marketplace::simulation::{SimulationMarketplace, SimulationSource};
+    use crate::marketplace::{MarketplaceDataStream, MarketplaceMatching, MarketplaceTradeApi};
+    use crate::order::{Order, OrderStatus, OrderType};
+    use axum::extract::Query;
+    use axum::routing::get;
+    use axum::{Json, Router};
+    use rust_decimal_macros::dec;
+    use serde_json::{json, Value};
+
+    // 1m klines and one aggregated trade every 30s
+    async fn mock_klines(Query(params): Query<HashMap<String, String>>) -> Json<Value> {
+        let from: u64 = params["startTime"].parse().unwrap();
+        let to: u64 = params["endTime"].parse().unwrap();
+        let candles: Vec<Value> = (from.div_ceil(60_000) * 60_000..=to)
+            .step_by(60_000)
+            .take(1000)
+            .map(|t| {
+                json!([
+                    t,
+                    "1.0",
+                    "2.0",
+                    "0.5",
+                    "1.5",
+                    "10",
+                    t + 59_999,
+                    "15",
+                    3,
+                    "5",
+                    "7",
+                    "0"
+                ])
+            })
+            .collect();
+        Json(json!(candles))
+    }
+
+    async fn mock_agg_trades(Query(params): Query<HashMap<String, String>>) -> Json<Value> {
+        let (from, to) = match params.get("fromId") {
+            Some(id) => (id.parse::<u64>().unwrap() * 30_000, u64::MAX),
+            None => (
+                params["startTime"].parse().unwrap(),
+                params["endTime"].parse().unwrap(),
+            ),
+        };
+        let trades: Vec<Value> = (from.div_ceil(30_000) * 30_000..=to)
+            .step_by(30_000)
+            .take(1000)
+            .map(|t| {
+                json!({"a": t / 30_000, "p": "1.2", "q": "3", "f": t, "l": t, "T": t, "m": t % 60_000 == 0})
+            })
+            .collect();
+        Json(json!(trades))
+    }
+
+    // 2500 trades in the same millisecond
+    async fn mock_burst_trades(Query(params): Query<HashMap<String, String>>) -> Json<Value> {
+        let from_id: u64 = params.get("fromId").map_or(0, |id| id.parse().unwrap());
+        let trades: Vec<Value> = (from_id..2500)
+            .take(1000)
+            .map(|id| json!({"a": id, "p": "1.2", "q": "3", "f": id, "l": id, "T": 1_000, "m": false}))
+            .collect();
+        Json(json!(trades))
+    }
+
+    #[tokio::test]
+    async fn test_agg_trades_paging() {
+        let app = Router::new().route("/api/v3/aggTrades", get(mock_burst_trades));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let binance = Binance::new().with_public_endpoint(format!("http://{address}"));
+        let trades = binance
+            .get_agg_trades_range("BTCUSDC", 0, WINDOW)
+            .await
+            .unwrap();
+        assert_eq!(trades.len(), 2500);
+        assert!(trades
+            .windows(2)
+            .all(|trades| trades[0].agg_trade_id + 1 == trades[1].agg_trade_id));
+    }
+
+    #[tokio::test]
+    async fn test_download_and_resume() {
+        let app = Router::new()
+            .route("/api/v3/klines", get(mock_klines))
+            .route("/api/v3/aggTrades", get(mock_agg_trades));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let data_path = std::env::temp_dir().join(format!("download-{}", uuid::Uuid::new_v4()));
+        let params = DownloadParams {
+            tickers: vec![Ticker::new("BTC", "USDC")],
+            from: 0,
+            to: 2 * WINDOW,
+            trades: true,
+            book: None,
+        };
+
+        let binance = Binance::new().with_public_endpoint(format!("http://{address}"));
+        binance.download(&params, &data_path).await.unwrap();
+
+        let events_path = data_path.join("events.jsonl");
+        let content = std::fs::read_to_string(&events_path).unwrap();
+        let events: Vec<MarketplaceEvent> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        // 120 candles and 240 trades
+        assert_eq!(events.len(), 360);
+        assert!(events
+            .windows(2)
+            .all(|events| events[0].get_time() <= events[1].get_time()));
+
+        // an interrupted write after the last saved window is dropped on resume
+        std::fs::write(&events_path, format!("{content}{{\"C\":")).unwrap();
+        binance.download(&params, &data_path).await.unwrap();
+        assert_eq!(std::fs::read_to_string(&events_path).unwrap(), content);
+
+        let other = DownloadParams {
+            to: WINDOW,
+            ..params
+        };
+        assert!(binance.download(&other, &data_path).await.is_err());
+
+        let _ = std::fs::remove_dir_all(data_path);
+    }
+
+    #[tokio::test]
+    async fn test_replay_download() {
+        let app = Router::new()
+            .route("/api/v3/klines", get(mock_klines))
+            .route("/api/v3/aggTrades", get(mock_agg_trades));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let ticker = Ticker::new("BTC", "USDC");
+        let data_path = std::env::temp_dir().join(format!("download-{}", uuid::Uuid::new_v4()));
+        let params = DownloadParams {
+            tickers: vec![ticker.clone()],
+            from: 0,
+            to: WINDOW,
+            trades: true,
+            book: Some(SyntheticBook {
+                interval: 60_000,
+                spread: dec!(0.0001),
+            }),
+        };
+        let binance = Binance::new().with_public_endpoint(format!("http://{address}"));
+        binance.download(&params, &data_path).await.unwrap();
+
+        // a limit buy over the traded price, filled on the replayed books
+        let (tx, _) = tokio::sync::broadcast::channel(10_000);
+        let mut simulation = SimulationMarketplace::new(SimulationSource::Book, Binance::new());
+        simulation
+            .update_asset_amount("USDC", dec!(100), Some(dec!(1)))
+            .await;
+        tokio::spawn({
+            let mut simulation = simulation.clone();
+            let tx = tx.clone();
+            async move { simulation.start_matching(tx).await }
+        });
+        let mut order = Order::new_buy(ticker.clone(), dec!(1), dec!(2), dec!(2), 0, None);
+        order.order_type = OrderType::Limit;
+        let order = simulation.place_order(&order).await.unwrap();
+        assert_eq!(order.status, OrderStatus::Active);
+
+        let mut replay = ReplayMarketplace::new(vec![data_path.clone()], (), 0);
+        replay
+            .start_data_stream(&vec![ticker.clone()], tx)
+            .await
+            .unwrap();
+
+        let mut status = OrderStatus::Active;
+        for _ in 0..5 {
+            let orders = simulation.get_orders(&[ticker.clone()]).await.unwrap();
+            status = orders[0].status.clone();
+            if status == OrderStatus::Executed {
+                break;
+            }
+        }
+        assert_eq!(status, OrderStatus::Executed);
+
+        let _ = std::fs::remove_dir_all(data_path);
+    }
+}
@@ -0,0 +1,311 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Lines, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context, Result};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::marketplace::binance::data_stream::aggressor_side;
+use crate::marketplace::candle::CandleInterval;
+use crate::marketplace::{MarketplaceBook, MarketplaceCandle, MarketplaceEvent, MarketplaceTrade};
+use crate::order::OrderSide;
+use crate::ticker::Ticker;
+
+// Top of book built from the trades, for the book simulation source.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SyntheticBook {
+    // minimum time between two books of a ticker
+    pub interval: u64,
+    // relative spread between the best bid and ask
+    pub spread: Decimal,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum ArchiveKind {
+    Klines(CandleInterval),
+    Trades,
+    AggTrades,
+}
+
+// One csv of data.binance.vision, named like BTCUSDT-1m-2024-01.csv, BTCUSDT-trades-2024-01.csv or BTCUSDT-aggTrades-2024-01.csv
+struct Archive {
+    path: PathBuf,
+    ticker: Ticker,
+    kind: ArchiveKind,
+    lines: Lines<BufReader<File>>,
+}
+
+impl Archive {
+    fn open(path: &Path) -> Result<Self> {
+        let name = path
+            .file_stem()
+            .and_then(|name| name.to_str())
+            .context("Invalid archive file name")?;
+        let mut parts = name.split('-');
+        let symbol = parts.next().context("Missing symbol in archive name")?;
+        let kind = match parts.next() {
+            Some("trades") => ArchiveKind::Trades,
+            Some("aggTrades") => ArchiveKind::AggTrades,
+            Some(interval) => {
+                ArchiveKind::Klines(CandleInterval::from_str(interval).map_err(|err| anyhow!(err))?)
+            }
+            None => anyhow::bail!("Missing data type in archive name {name}"),
+        };
+        let file =
+            File::open(path).with_context(|| format!("Could not open {}", path.display()))?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            ticker: Ticker::try_from(symbol).map_err(|err| anyhow!(err))?,
+            kind,
+            lines: BufReader::new(file).lines(),
+        })
+    }
+
+    fn next_event(&mut self) -> Result<Option<MarketplaceEvent>> {
+        for line in self.lines.by_ref() {
+            let line = line?;
+            let fields: Vec<&str> = line.trim().split(',').collect();
+            // header or empty line
+            if fields[0].parse::<u64>().is_err() {
+                continue;
+            }
+            return parse_fields(self.kind, &self.ticker, &fields)
+                .with_context(|| format!("Invalid line in {} : {}", self.path.display(), line))
+                .map(Some);
+        }
+        Ok(None)
+    }
+}
+
+// Timestamps are in microseconds in the spot archives since 2025.
+fn to_millis(time: u64) -> u64 {
+    if time >= 100_000_000_000_000 {
+        time / 1000
+    } else {
+        time
+    }
+}
+
+fn field<T: FromStr>(fields: &[&str], index: usize) -> Result<T> {
+    fields
+        .get(index)
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| anyhow!("Invalid field {index}"))
+}
+
+fn parse_fields(kind: ArchiveKind, ticker: &Ticker, fields: &[&str]) -> Result<MarketplaceEvent> {
+    let event = match kind {
+        // open_time, open, high, low, close, volume, close_time, quote_volume, count, ...
+        ArchiveKind::Klines(interval) => MarketplaceEvent::Candle(MarketplaceCandle {
+            ticker: ticker.clone(),
+            interval,
+            start_time: to_millis(field(fields, 0)?),
+            open_price: field(fields, 1)?,
+            high_price: field(fields, 2)?,
+            low_price: field(fields, 3)?,
+            close_price: field(fields, 4)?,
+            close_time: to_millis(field(fields, 6)?),
+            volume: field(fields, 7)?,
+            trade_count: field(fields, 8)?,
+            closed: true,
+        }),
+        // id, price, qty, quote_qty, time, is_buyer_maker, is_best_match
+        ArchiveKind::Trades => MarketplaceEvent::Trade(MarketplaceTrade {
+            ticker: ticker.clone(),
+            trade_id: field(fields, 0)?,
+            price: field(fields, 1)?,
+            quantity: field(fields, 2)?,
+            trade_time: to_millis(field(fields, 4)?),
+            aggressor_side: Some(aggressor_side(parse_bool(fields, 5)?)),
+        }),
+        // agg_trade_id, price, qty, first_trade_id, last_trade_id, transact_time, is_buyer_maker, is_best_match
+        ArchiveKind::AggTrades => MarketplaceEvent::Trade(MarketplaceTrade {
+            ticker: ticker.clone(),
+            trade_id: field(fields, 0)?,
+            price: field(fields, 1)?,
+            quantity: field(fields, 2)?,
+            trade_time: to_millis(field(fields, 5)?),
+            aggressor_side: Some(aggressor_side(parse_bool(fields, 6)?)),
+        }),
+    };
+    Ok(event)
+}
+
+fn parse_bool(fields: &[&str], index: usize) -> Result<bool> {
+    match fields.get(index).map(|value| value.to_lowercase()) {
+        Some(value) if value == "true" => Ok(true),
+        Some(value) if value == "false" => Ok(false),
+        _ => Err(anyhow!("Invalid field {index}")),
+    }
+}
+
+pub(crate) fn synthetic_book(trade: &MarketplaceTrade, spread: Decimal) -> MarketplaceBook {
+    // the aggressor took the liquidity on the other side of the book
+    let (bid, ask) = match trade.aggressor_side {
+        Some(OrderSide::Sell) => (trade.price, trade.price * (dec!(1) + spread)),
+        _ => (trade.price * (dec!(1) - spread), trade.price),
+    };
+    MarketplaceBook {
+        ticker: trade.ticker.clone(),
+        first_update_id: 0,
+        final_update_id: trade.trade_id,
+        time: trade.trade_time,
+        bids: vec![(bid, trade.quantity)],
+        asks: vec![(ask, trade.quantity)],
+    }
+}
+
+// Csv files of the given paths, directories included.
+fn archive_paths(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let mut entries: Vec<PathBuf> = fs::read_dir(path)?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().is_some_and(|ext| ext == "csv"))
+                .collect();
+            entries.sort();
+            files.append(&mut entries);
+        } else {
+            files.push(path.clone());
+        }
+    }
+    Ok(files)
+}
+
+// Convert Binance public data archives into a replay dataset (data_path/events.jsonl),
+// merging the files by time. Each archive must be time ordered, as published.
+pub fn import_archives(
+    paths: &[PathBuf],
+    data_path: &Path,
+    book: Option<SyntheticBook>,
+) -> Result<usize> {
+    let mut archives = archive_paths(paths)?
+        .iter()
+        .map(|path| Archive::open(path))
+        .collect::<Result<Vec<Archive>>>()?;
+
+    // next event of each archive, ordered by time then archive
+    let mut heads: Vec<Option<MarketplaceEvent>> = Vec::new();
+    let mut queue = BinaryHeap::new();
+    for (index, archive) in archives.iter_mut().enumerate() {
+        let event = archive.next_event()?;
+        if let Some(time) = event.as_ref().and_then(|event| event.get_time()) {
+            queue.push(Reverse((time, index)));
+        }
+        heads.push(event);
+    }
+
+    fs::create_dir_all(data_path)?;
+    let mut writer = BufWriter::new(File::create(data_path.join("events.jsonl"))?);
+    let mut last_books: HashMap<Ticker, u64> = HashMap::new();
+    let mut count = 0;
+
+    while let Some(Reverse((_, index))) = queue.pop() {
+        let Some(event) = heads[index].take() else {
+            continue;
+        };
+
+        let mut events = vec![event];
+        if let (Some(book), MarketplaceEvent::Trade(trade)) = (&book, &events[0]) {
+            let last_book = last_books.get(&trade.ticker);
+            if last_book.is_none_or(|time| trade.trade_time >= time + book.interval) {
+                last_books.insert(trade.ticker.clone(), trade.trade_time);
+                events.push(MarketplaceEvent::Book(synthetic_book(trade, book.spread)));
+            }
+        }
+        for event in events {
+            serde_json::to_writer(&mut writer, &event)?;
+            writer.write_all(b"\n")?;
+            count += 1;
+        }
+
+        let next = archives[index].next_event()?;
+        if let Some(time) = next.as_ref().and_then(|event| event.get_time()) {
+            queue.push(Reverse((time, index)));
+        }
+        heads[index] = next;
+
+        if count % 1_000_000 == 0 {
+            info!("Imported {} events", count);
+        }
+    }
+    writer.flush()?;
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_import_archives() {
+        let dir = std::env::temp_dir().join(format!("import-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("BTCUSDT-1m-2025-01.csv"),
+            "1735689600000000,93576.00,93610.93,93537.50,93610.93,8.21827,1735689659999999,769145.48,1976,5.1,477716.09,0\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("BTCUSDT-trades-2025-01.csv"),
+            "id,price,qty,quote_qty,time,is_buyer_maker,is_best_match\n\
+             1,93576.00,0.1,9357.6,1735689600100000,true,true\n\
+             2,93577.00,0.2,18715.4,1735689600200000,false,true\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("ETHUSDT-aggTrades-2025-01.csv"),
+            "10,3300.5,1.5,20,21,1735689600150,False,True\n",
+        )
+        .unwrap();
+
+        let output = dir.join("replay");
+        let count = import_archives(
+            std::slice::from_ref(&dir),
+            &output,
+            Some(SyntheticBook {
+                interval: 1000,
+                spread: dec!(0.0001),
+            }),
+        )
+        .unwrap();
+
+        let events: Vec<MarketplaceEvent> = fs::read_to_string(output.join("events.jsonl"))
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(count, events.len());
+        // 3 trades, 1 candle, and a book for the first trade of each ticker
+        assert_eq!(events.len(), 6);
+        assert!(events
+            .windows(2)
+            .all(|events| events[0].get_time() <= events[1].get_time()));
+
+        match &events[1] {
+            MarketplaceEvent::Book(book) => {
+                assert_eq!(book.time, 1735689600100);
+                assert_eq!(book.bids[0].0, dec!(93576.00));
+            }
+            other => panic!("Unexpected event {:?}", other),
+        }
+        match events.last().unwrap() {
+            MarketplaceEvent::Candle(candle) => {
+                assert_eq!(candle.close_time, 1735689659999);
+                assert_eq!(candle.trade_count, 1976);
+            }
+            other => panic!("Unexpected event {:?}", other),
+        }
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,168 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use futures::SinkExt;
+use futures_util::StreamExt;
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::{select, sync::broadcast::Sender, time::interval};
+use tokio_tungstenite::connect_async;
+use tracing::{debug, error, info};
+use tungstenite::Message;
+
+use crate::AppEvent;
+
+use super::{account_stream::parse_user_data_event, Binance, ENDPOINT, STREAM_ENDPOINT};
+
+// A listen key expires 60 minutes after its creation or last keepalive.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30 * 60);
+// Binance closes stream connections after 24h, reconnect a bit earlier.
+const CONNECTION_LIFETIME: Duration = Duration::from_secs(23 * 3600 + 50 * 60);
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ListenKeyResponse {
+    listen_key: String,
+}
+
+impl Binance {
+    pub async fn create_listen_key(&self) -> Result<String> {
+        let (api_key, _) = self.credentials.hmac()?;
+
+        let url = format!("{}/api/v3/userDataStream", *ENDPOINT);
+        info!("{}", url);
+
+        let res = self
+            .client
+            .post(url)
+            .header("X-MBX-APIKEY", api_key)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        let res: ListenKeyResponse = res.json().await?;
+        Ok(res.listen_key)
+    }
+
+    pub async fn keepalive_listen_key(&self, listen_key: &str) -> Result<()> {
+        let (api_key, _) = self.credentials.hmac()?;
+
+        let url = format!(
+            "{}/api/v3/userDataStream?listenKey={}",
+            *ENDPOINT, listen_key
+        );
+        debug!("{}", url);
+
+        self.client
+            .put(url)
+            .header("X-MBX-APIKEY", api_key)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+
+    pub(super) async fn start_listen_key_account_stream(
+        &self,
+        tx_app: Sender<AppEvent>,
+    ) -> Result<()> {
+        loop {
+            let listen_key = match self.create_listen_key().await {
+                Ok(listen_key) => listen_key,
+                Err(err) => {
+                    error!("Failed to create listen key : {err}");
+                    tokio::time::sleep(Duration::from_secs(10)).await;
+                    continue;
+                }
+            };
+
+            let request = format!("{}/ws/{}", *STREAM_ENDPOINT, listen_key);
+
+            let mut ws_stream = loop {
+                info!("Connecting to listen key user stream");
+                match connect_async(request.clone()).await {
+                    Ok(res) => break res.0,
+                    Err(err) => {
+                        info!("Failed to connect to stream: {err}");
+                        tokio::time::sleep(Duration::from_secs(10)).await;
+                    }
+                }
+            };
+
+            info!("Connected to listen key user stream");
+
+            let mut keepalive = interval(KEEPALIVE_INTERVAL);
+            // the first tick completes immediately, the key has just been created
+            keepalive.tick().await;
+
+            let expiry = tokio::time::sleep(CONNECTION_LIFETIME);
+            tokio::pin!(expiry);
+
+            loop {
+                select! {
+                    _ = keepalive.tick() => {
+                        if let Err(err) = self.keepalive_listen_key(&listen_key).await {
+                            error!("Listen key keepalive failed : {err}");
+                            break;
+                        }
+                    }
+                    _ = &mut expiry => {
+                        info!("Listen key stream reached its lifetime");
+                        break;
+                    }
+                    message = ws_stream.next() => {
+                        match message {
+                            Some(Ok(Message::Text(text))) => {
+                                debug!("{:?}", text);
+                                let event: Value = match serde_json::from_str(&text) {
+                                    Ok(event) => event,
+                                    Err(err) => {
+                                        error!("Invalid json : {err}");
+                                        continue;
+                                    }
+                                };
+                                if event.get("e") == Some(&Value::String("listenKeyExpired".to_string())) {
+                                    error!("Listen key expired");
+                                    break;
+                                }
+                                match parse_user_data_event(&event) {
+                                    Ok(Some(event)) => {
+                                        let _ = tx_app.send(AppEvent::MarketPlace(event));
+                                    }
+                                    Ok(None) => {}
+                                    Err(err) => {
+                                        error!("User data event parsing error : {err}");
+                                    }
+                                }
+                            }
+                            Some(Ok(Message::Ping(data))) => {
+                                debug!("Received ping: {:?}", data);
+                                if ws_stream.send(Message::Pong(data)).await.is_err() {
+                                    break;
+                                }
+                            }
+                            Some(Ok(Message::Close(frame))) => {
+                                error!("Stream closed: {:?}", frame);
+                                break;
+                            }
+                            Some(Ok(_)) => {}
+                            Some(Err(err)) => {
+                                error!("Stream error: {}", err);
+                                break;
+                            }
+                            None => {
+                                error!("Empty stream");
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+
+            let _ = ws_stream.close(None).await;
+            info!("Reconnecting listen key user stream");
+            tokio::time::sleep(Duration::from_secs(3)).await;
+        }
+    }
+}
@@ -2,23 +2,28 @@ use std::collections::HashMap;
 use std::env::var;
 use std::sync::{Arc, LazyLock};
 
-use crate::marketplace::binance::utils::ceil_to_step;
-use crate::order::Order;
+use crate::credentials::Credentials;
+use crate::marketplace::binance::utils::{ceil_to_step, floor_to_step};
+use crate::order::{Order, OrderSide, OrderType};
 use crate::portfolio::Asset;
 use crate::ticker::Ticker;
 use crate::AppEvent;
 use account_api::AccountOverview;
 use anyhow::anyhow;
 use anyhow::{Context, Result};
+use data_stream::MarketStream;
 use reqwest::Client;
 use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
 use settings_api::ExchangeInfo;
 use settings_api::SymbolInfoFilter;
 use tokio::sync::broadcast::Sender;
+use tokio::sync::mpsc::UnboundedSender;
 use tokio::sync::RwLock;
 use tracing::error;
 
+use super::candle::CandleInterval;
+use super::store::CandleStore;
 use super::MarketplaceDataStream;
 use super::MarketplaceSettingsApi;
 use super::MarketplaceTradeApi;
@@ -26,8 +31,7 @@ use super::{MarketplaceAccountApi, MarketplaceDataApi};
 
 static ENDPOINT: LazyLock<String> = LazyLock::new(|| var("BINANCE_ENDPOINT").unwrap());
 static PUBLIC_ENDPOINT: LazyLock<String> = LazyLock::new(|| {
-    let default = var("BINANCE_ENDPOINT").unwrap();
-    var("BINANCE_PUBLIC_ENDPOINT").unwrap_or(default)
+    var("BINANCE_PUBLIC_ENDPOINT").unwrap_or_else(|_| var("BINANCE_ENDPOINT").unwrap())
 });
 static STREAM_ENDPOINT: LazyLock<String> =
     LazyLock::new(|| var("BINANCE_STREAM_ENDPOINT").unwrap());
@@ -37,6 +41,9 @@ pub mod account_api;
 pub mod account_stream;
 pub mod data_api;
 pub mod data_stream;
+pub mod download;
+pub mod import;
+pub mod listen_key_stream;
 pub mod settings_api;
 pub mod trade_api;
 mod utils;
@@ -44,20 +51,67 @@ mod utils;
 #[derive(Default, Debug, Clone)]
 pub struct Binance {
     client: Client,
+    credentials: Arc<Credentials>,
     exchange_info: Arc<RwLock<Option<ExchangeInfo>>>,
     account_overview: Arc<RwLock<Option<AccountOverview>>>,
+    // tickers of the market data stream, and control frames for the live socket
+    stream_tickers: Arc<RwLock<Vec<Ticker>>>,
+    stream_control: Arc<RwLock<Option<UnboundedSender<String>>>>,
+    market_streams: Vec<MarketStream>,
+    candle_store: Option<CandleStore>,
+    // klines and aggTrades api, BINANCE_PUBLIC_ENDPOINT when unset
+    public_endpoint: Option<String>,
 }
 
 impl Binance {
+    // Client configured from the BINANCE_* environment variables.
     pub fn new() -> Self {
+        Self::with_credentials(Credentials::from_env())
+    }
+
+    pub fn with_credentials(credentials: Credentials) -> Self {
         let client = Client::builder().build().unwrap();
 
         Self {
             client,
+            credentials: Arc::new(credentials),
+            market_streams: MarketStream::DEFAULT.to_vec(),
             ..Default::default()
         }
     }
 
+    pub fn with_market_streams(mut self, market_streams: Vec<MarketStream>) -> Self {
+        self.market_streams = market_streams;
+        self
+    }
+
+    // Serve candles from a local store, the klines api only fills its gaps.
+    pub fn with_candle_store(mut self, candle_store: CandleStore) -> Self {
+        self.candle_store = Some(candle_store);
+        self
+    }
+
+    pub fn with_public_endpoint(mut self, public_endpoint: String) -> Self {
+        self.public_endpoint = Some(public_endpoint);
+        self
+    }
+
+    // Order rules known beforehand, instead of loaded by init.
+    pub fn with_exchange_info(mut self, exchange_info: ExchangeInfo) -> Self {
+        self.exchange_info = Arc::new(RwLock::new(Some(exchange_info)));
+        self
+    }
+
+    fn public_endpoint(&self) -> &str {
+        self.public_endpoint
+            .as_deref()
+            .unwrap_or_else(|| PUBLIC_ENDPOINT.as_str())
+    }
+
+    pub fn candle_store(&self) -> Option<&CandleStore> {
+        self.candle_store.as_ref()
+    }
+
     pub async fn init(&mut self, tickers: &[Ticker]) -> Result<()> {
         let mut exchange_info = self.exchange_info.write().await;
         *exchange_info = {
@@ -69,6 +123,24 @@ impl Binance {
         }
         Ok(())
     }
+
+    // Load the exchange info of a ticker added at runtime.
+    pub async fn add_ticker_info(&self, ticker: &Ticker) -> Result<()> {
+        let res = self.get_exchange_info(std::slice::from_ref(ticker)).await?;
+        let mut exchange_info = self.exchange_info.write().await;
+        match exchange_info.as_mut() {
+            Some(exchange_info) => {
+                for info in res.symbols {
+                    exchange_info
+                        .symbols
+                        .retain(|symbol| symbol.symbol != info.symbol);
+                    exchange_info.symbols.push(info);
+                }
+            }
+            None => *exchange_info = Some(res),
+        }
+        Ok(())
+    }
 }
 
 impl crate::marketplace::Marketplace for Binance {}
@@ -94,6 +166,14 @@ impl MarketplaceSettingsApi for Binance {
         }
     }
 
+    async fn get_maker_fees(&self) -> Decimal {
+        let account = self.account_overview.read().await;
+        match account.as_ref() {
+            Some(account) => account.commission_rates.maker,
+            None => dec!(0.001),
+        }
+    }
+
     async fn adjust_order_price_and_amount(&self, order: &mut Order) -> Result<()> {
         let exchange_info = self.exchange_info.read().await;
         let exchange_info = exchange_info.as_ref().context("Empty exchange info")?;
@@ -103,6 +183,7 @@ impl MarketplaceSettingsApi for Binance {
         });
         let info = info.context("Ticker info not found")?;
 
+        let mut tick_size = None;
         let mut step_size = None;
         let mut min_qty = None;
         let mut max_qty = None;
@@ -122,7 +203,11 @@ impl MarketplaceSettingsApi for Binance {
                 SymbolInfoFilter::Notional { min_notional: min } => {
                     min_notional = Some(*min);
                 }
-                _ => {}
+                SymbolInfoFilter::PriceFilter {
+                    tick_size: tick, ..
+                } => {
+                    tick_size = Some(*tick);
+                }
             }
         }
 
@@ -131,6 +216,16 @@ impl MarketplaceSettingsApi for Binance {
         let max_qty = max_qty.context("No max_qty found")?;
         let min_notional = min_notional.context("No min_notional found")?;
 
+        // limit prices on the tick, never crossing the asked price
+        if let Some(tick_size) = tick_size.filter(|tick| *tick > dec!(0)) {
+            if matches!(order.order_type, OrderType::Limit | OrderType::LimitMaker) {
+                order.price = match order.side {
+                    OrderSide::Buy => floor_to_step(order.price, tick_size),
+                    OrderSide::Sell => ceil_to_step(order.price, tick_size),
+                };
+            }
+        }
+
         let price = order.price;
         let mut amount = order.amount;
 
@@ -156,22 +251,66 @@ impl MarketplaceSettingsApi for Binance {
         order.quote_amount = ceil_to_step(order.amount * order.price, dec!(0.01));
         Ok(())
     }
+
+    async fn floor_amount(&self, ticker: &Ticker, amount: Decimal) -> Result<Decimal> {
+        let exchange_info = self.exchange_info.read().await;
+        let exchange_info = exchange_info.as_ref().context("Empty exchange info")?;
+
+        let info = exchange_info
+            .symbols
+            .iter()
+            .find(|info| info.quote_asset == ticker.quote && info.base_asset == ticker.base)
+            .context("Ticker info not found")?;
+        let step_size = info
+            .filters
+            .iter()
+            .find_map(|filter| match filter {
+                SymbolInfoFilter::LotSize { step_size, .. } => Some(*step_size),
+                _ => None,
+            })
+            .context("No step_size found")?;
+
+        Ok(floor_to_step(amount, step_size))
+    }
 }
 
 impl MarketplaceDataApi for Binance {
     async fn get_candles(
         &self,
         ticker: &Ticker,
-        interval: &str,
+        interval: CandleInterval,
+        from: Option<u64>,
+        to: Option<u64>,
+    ) -> Result<Vec<super::MarketplaceCandle>> {
+        match &self.candle_store {
+            Some(store) => {
+                store
+                    .get_candles(&Klines(self), ticker, interval, from, to)
+                    .await
+            }
+            None => Klines(self).get_candles(ticker, interval, from, to).await,
+        }
+    }
+}
+
+// Candles of the klines api, without the store
+struct Klines<'a>(&'a Binance);
+
+impl MarketplaceDataApi for Klines<'_> {
+    async fn get_candles(
+        &self,
+        ticker: &Ticker,
+        interval: CandleInterval,
         from: Option<u64>,
         to: Option<u64>,
     ) -> Result<Vec<super::MarketplaceCandle>> {
         let candles = self
-            .get_candles(format!("{}", ticker).as_str(), interval, from, to)
+            .0
+            .get_candles(format!("{}", ticker).as_str(), interval.as_str(), from, to)
             .await?;
         Ok(candles
             .into_iter()
-            .map(|candle| candle.into_candle_event(ticker.clone()))
+            .map(|candle| candle.into_candle_event(ticker.clone(), interval))
             .collect())
     }
 }
@@ -222,6 +361,19 @@ impl MarketplaceTradeApi for Binance {
 
     async fn place_order(&mut self, order: &Order) -> Result<Order> {
         let res = Binance::place_order(self, order).await?;
-        Order::try_from(res).map_err(|err| anyhow!(err))
+        let mut placed = Order::try_from(res).map_err(|err| anyhow!(err))?;
+        // bookkeeping of the strategy, unknown to binance
+        placed.session_id = order.session_id.clone();
+        placed.strategy = order.strategy.clone();
+        placed.prev_order_id = order.prev_order_id.clone();
+        placed.buy_order_price = order.buy_order_price;
+        placed.sell_order_price = order.sell_order_price;
+        placed.exit_reason = order.exit_reason;
+        placed.fees = order.fees;
+        Ok(placed)
+    }
+
+    async fn cancel_order(&mut self, order: &Order) -> Result<()> {
+        Binance::cancel_order(self, order).await
     }
 }
@@ -2,7 +2,7 @@ use crate::{
     marketplace::binance::{Binance, ENDPOINT},
     ticker::Ticker,
 };
-use anyhow::Result;
+use anyhow::{Context, Result};
 use reqwest::Url;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Deserializer};
@@ -98,8 +98,9 @@ impl Binance {
         info!("{}", url);
 
         let r = self.client.get(url).send().await?;
-        let r = r.text().await.unwrap();
-        let r: ExchangeInfo = serde_json::de::from_str(r.as_str()).unwrap();
+        let r = r.text().await?;
+        let r: ExchangeInfo = serde_json::de::from_str(r.as_str())
+            .with_context(|| format!("Invalid exchange info response : {r}"))?;
 
         Ok(r)
     }
@@ -1,5 +1,3 @@
-use std::env;
-
 use crate::{
     marketplace::binance::Binance,
     order::{Order, OrderSide, OrderStatus, OrderTrade, OrderType},
@@ -88,6 +86,7 @@ impl TryFrom<&OrderResponse> for Order {
             strategy: None,
             next_order_id: None,
             prev_order_id: None,
+            exit_reason: None,
             reject_reason: None
         };
 
@@ -99,6 +98,8 @@ impl TryFrom<&OrderResponse> for Order {
                 trade_time: value.transact_time,
                 price: fill.price,
                 amount: fill.qty,
+                fee: fill.commission,
+                fee_asset: Some(fill.commission_asset.clone()),
             })
             .collect();
 
@@ -116,8 +117,7 @@ impl TryFrom<OrderResponse> for Order {
 
 impl Binance {
     pub async fn get_open_orders(&self, ticker: &Ticker) -> Result<Vec<OrderResponse>> {
-        let api_key = env::var("BINANCE_API_KEY")?;
-        let api_secret = env::var("BINANCE_API_SECRET")?;
+        let (api_key, api_secret) = self.credentials.hmac()?;
 
         let timestamp = Utc::now().timestamp_millis();
         let params = format!("timestamp={}&symbol={}", timestamp, ticker);
@@ -148,8 +148,7 @@ impl Binance {
     }
 
     pub async fn place_order(&self, order: &Order) -> Result<OrderResponse> {
-        let api_key = env::var("BINANCE_API_KEY")?;
-        let api_secret = env::var("BINANCE_API_SECRET")?;
+        let (api_key, api_secret) = self.credentials.hmac()?;
 
         let timestamp = Utc::now().timestamp_millis();
         let mut params = format!(
@@ -164,6 +163,12 @@ impl Binance {
                 params.push_str(&format!("&quantity={}", order.amount));
             }
             (OrderType::Limit, _) => {
+                params.push_str(&format!(
+                    "&quantity={}&price={}&timeInForce=GTC",
+                    order.amount, order.price
+                ));
+            }
+            (OrderType::LimitMaker, _) => {
                 params.push_str(&format!("&quantity={}&price={}", order.amount, order.price));
             }
             _ => {}
@@ -202,4 +207,40 @@ impl Binance {
 
         Ok(order_response)
     }
+
+    pub async fn cancel_order(&self, order: &Order) -> Result<()> {
+        let (api_key, api_secret) = self.credentials.hmac()?;
+
+        let timestamp = Utc::now().timestamp_millis();
+        let params = format!(
+            "timestamp={}&symbol={}&origClientOrderId={}",
+            timestamp, order.ticker, order.id
+        );
+
+        let mut mac: Hmac<Sha256> = Hmac::new_from_slice(api_secret.as_bytes())?;
+        mac.update(params.as_bytes());
+        let signature = encode(mac.finalize().into_bytes());
+
+        let url = format!(
+            "{}/api/v3/order?{}&signature={}",
+            *ENDPOINT, params, signature
+        );
+
+        info!("{}", url);
+
+        let res = self
+            .client
+            .delete(&url)
+            .header("X-MBX-APIKEY", api_key)
+            .send()
+            .await?;
+
+        if !res.status().is_success() {
+            let text = &res.text().await?;
+            error!("Binance order cancel failed : {}", text);
+            return Err(anyhow::anyhow!("Binance order cancel failed"));
+        }
+
+        Ok(())
+    }
 }
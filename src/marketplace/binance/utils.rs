@@ -4,6 +4,10 @@ pub fn ceil_to_step(value: Decimal, step: Decimal) -> Decimal {
     ((value / step).ceil()) * step
 }
 
+pub fn floor_to_step(value: Decimal, step: Decimal) -> Decimal {
+    ((value / step).floor()) * step
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -18,5 +22,6 @@ mod tests {
         let value = dec!(10.489630);
         let step = dec!(0.01);
         assert_eq!(dec!(10.49), ceil_to_step(value, step));
+        assert_eq!(dec!(10.48), floor_to_step(value, step));
     }
 }
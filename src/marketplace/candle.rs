@@ -0,0 +1,460 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::Sender;
+use tokio::sync::mpsc;
+use tracing::error;
+
+use crate::marketplace::{
+    MarketplaceCandle, MarketplaceEvent, MarketplaceStreamStatus, MarketplaceTrade, StreamStatus,
+};
+use crate::ticker::Ticker;
+use crate::AppEvent;
+
+#[derive(
+    Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord,
+)]
+pub enum CandleInterval {
+    #[default]
+    #[serde(rename = "1m")]
+    M1,
+    #[serde(rename = "5m")]
+    M5,
+    #[serde(rename = "15m")]
+    M15,
+    #[serde(rename = "1h")]
+    H1,
+    #[serde(rename = "4h")]
+    H4,
+    #[serde(rename = "1d")]
+    D1,
+}
+
+impl CandleInterval {
+    pub const ALL: [CandleInterval; 6] = [
+        CandleInterval::M1,
+        CandleInterval::M5,
+        CandleInterval::M15,
+        CandleInterval::H1,
+        CandleInterval::H4,
+        CandleInterval::D1,
+    ];
+
+    // Binance interval notation
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CandleInterval::M1 => "1m",
+            CandleInterval::M5 => "5m",
+            CandleInterval::M15 => "15m",
+            CandleInterval::H1 => "1h",
+            CandleInterval::H4 => "4h",
+            CandleInterval::D1 => "1d",
+        }
+    }
+
+    pub fn duration_ms(&self) -> u64 {
+        match self {
+            CandleInterval::M1 => 60_000,
+            CandleInterval::M5 => 5 * 60_000,
+            CandleInterval::M15 => 15 * 60_000,
+            CandleInterval::H1 => 3_600_000,
+            CandleInterval::H4 => 4 * 3_600_000,
+            CandleInterval::D1 => 24 * 3_600_000,
+        }
+    }
+
+    // Start time of the candle containing this time (UTC aligned, like Binance).
+    pub fn start_of(&self, time: u64) -> u64 {
+        time - time % self.duration_ms()
+    }
+}
+
+impl Display for CandleInterval {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for CandleInterval {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        CandleInterval::ALL
+            .into_iter()
+            .find(|interval| interval.as_str() == s)
+            .ok_or_else(|| format!("Unknown candle interval {s}"))
+    }
+}
+
+// Source of the aggregated candles.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CandleSource {
+    // 1m klines, higher intervals are built from them
+    Candles,
+    // trades, every interval (1m included) is built from them
+    Trades,
+}
+
+impl FromStr for CandleSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "candles" => Ok(CandleSource::Candles),
+            "trades" => Ok(CandleSource::Trades),
+            other => Err(format!("Unknown candle source {other}")),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Bucket {
+    start_time: u64,
+    // merge of the completed parts (closed 1m candles or trades)
+    done: Option<MarketplaceCandle>,
+    // last update of the 1m candle in progress
+    partial: Option<MarketplaceCandle>,
+    closed: bool,
+    // false when the first parts were missed (started mid-interval, or after a seek),
+    // such a candle has a wrong open, high and low and is never sent
+    complete: bool,
+}
+
+impl Bucket {
+    fn candle(&self) -> Option<MarketplaceCandle> {
+        match (&self.done, &self.partial) {
+            (Some(done), Some(partial)) => Some(merge(done.clone(), partial)),
+            (Some(candle), None) | (None, Some(candle)) => Some(candle.clone()),
+            (None, None) => None,
+        }
+    }
+}
+
+fn merge(mut candle: MarketplaceCandle, next: &MarketplaceCandle) -> MarketplaceCandle {
+    candle.high_price = candle.high_price.max(next.high_price);
+    candle.low_price = candle.low_price.min(next.low_price);
+    candle.close_price = next.close_price;
+    candle.volume += next.volume;
+    candle.trade_count += next.trade_count;
+    candle
+}
+
+// Builds higher timeframe candles locally, so only 1m klines (or trades) need to be streamed or recorded.
+pub struct CandleAggregator {
+    source: CandleSource,
+    intervals: Vec<CandleInterval>,
+    buckets: HashMap<(Ticker, CandleInterval), Bucket>,
+    // no data is missing from this time on, as in generated datasets
+    complete_since: Option<u64>,
+}
+
+impl CandleAggregator {
+    pub fn new(source: CandleSource, intervals: &[CandleInterval]) -> Self {
+        let mut aggregator = Self {
+            source,
+            intervals: Vec::new(),
+            buckets: HashMap::new(),
+            complete_since: None,
+        };
+        aggregator.add_intervals(intervals);
+        aggregator
+    }
+
+    pub fn with_complete_since(mut self, time: u64) -> Self {
+        self.complete_since = Some(time);
+        self
+    }
+
+    // Intervals needed by a strategy started later on.
+    pub fn add_intervals(&mut self, intervals: &[CandleInterval]) {
+        for interval in intervals {
+            if (self.source == CandleSource::Trades || *interval != CandleInterval::M1)
+                && !self.intervals.contains(interval)
+            {
+                self.intervals.push(*interval);
+            }
+        }
+    }
+
+    // Higher interval candles updated by a 1m candle, in progress or closed.
+    pub fn add_candle(&mut self, candle: &MarketplaceCandle) -> Vec<MarketplaceCandle> {
+        if self.source != CandleSource::Candles || candle.interval != CandleInterval::M1 {
+            return Vec::new();
+        }
+        let mut candles = Vec::new();
+        for interval in self.intervals.clone() {
+            candles.append(&mut self.update(interval, candle, candle.closed, true));
+        }
+        candles
+    }
+
+    // Candles built from trades are only sent once closed.
+    pub fn add_trade(&mut self, trade: &MarketplaceTrade) -> Vec<MarketplaceCandle> {
+        if self.source != CandleSource::Trades {
+            return Vec::new();
+        }
+        let part = MarketplaceCandle {
+            ticker: trade.ticker.clone(),
+            interval: CandleInterval::M1,
+            open_price: trade.price,
+            close_price: trade.price,
+            high_price: trade.price,
+            low_price: trade.price,
+            trade_count: 1,
+            start_time: trade.trade_time,
+            close_time: trade.trade_time,
+            volume: trade.price * trade.quantity,
+            closed: true,
+        };
+        let mut candles = Vec::new();
+        for interval in self.intervals.clone() {
+            candles.append(&mut self.update(interval, &part, true, false));
+        }
+        candles
+    }
+
+    // Close the candles ended before this time, when no newer data came to roll them over.
+    pub fn flush(&mut self, time: u64) -> Vec<MarketplaceCandle> {
+        let mut candles = Vec::new();
+        for ((_, interval), bucket) in self.buckets.iter_mut() {
+            if !bucket.closed && bucket.start_time + interval.duration_ms() <= time {
+                if let Some(candle) = bucket.candle().filter(|_| bucket.complete) {
+                    candles.push(finish(candle, *interval, bucket.start_time, true));
+                }
+                bucket.closed = true;
+            }
+        }
+        candles
+    }
+
+    fn update(
+        &mut self,
+        interval: CandleInterval,
+        part: &MarketplaceCandle,
+        part_closed: bool,
+        send_updates: bool,
+    ) -> Vec<MarketplaceCandle> {
+        let mut candles = Vec::new();
+        let start_time = interval.start_of(part.start_time);
+        let complete_since = self.complete_since;
+        let bucket = self
+            .buckets
+            .entry((part.ticker.clone(), interval))
+            .or_insert_with(|| Bucket {
+                start_time,
+                complete: part.start_time == start_time
+                    || complete_since.is_some_and(|since| since <= start_time),
+                ..Default::default()
+            });
+
+        if bucket.start_time != start_time {
+            if start_time < bucket.start_time {
+                // late data
+                return candles;
+            }
+            if !bucket.closed && bucket.complete {
+                if let Some(candle) = bucket.candle() {
+                    candles.push(finish(candle, interval, bucket.start_time, true));
+                }
+            }
+            let follows = bucket.start_time + interval.duration_ms() == start_time;
+            *bucket = Bucket {
+                start_time,
+                complete: follows || part.start_time == start_time,
+                ..Default::default()
+            };
+        } else if bucket.closed {
+            return candles;
+        }
+
+        if part_closed {
+            bucket.done = Some(match bucket.done.take() {
+                Some(done) => merge(done, part),
+                None => part.clone(),
+            });
+            bucket.partial = None;
+        } else {
+            bucket.partial = Some(part.clone());
+        }
+
+        let closed = part_closed && part.close_time + 1 >= start_time + interval.duration_ms();
+        if bucket.complete && (closed || send_updates) {
+            if let Some(candle) = bucket.candle() {
+                candles.push(finish(candle, interval, start_time, closed));
+            }
+        }
+        bucket.closed = closed;
+
+        candles
+    }
+}
+
+fn finish(
+    mut candle: MarketplaceCandle,
+    interval: CandleInterval,
+    start_time: u64,
+    closed: bool,
+) -> MarketplaceCandle {
+    candle.interval = interval;
+    candle.start_time = start_time;
+    candle.close_time = start_time + interval.duration_ms() - 1;
+    candle.closed = closed;
+    candle
+}
+
+// Publish the aggregated candles on the app bus.
+// The intervals of the strategies added at runtime come through rx_intervals.
+pub async fn start_candle_aggregator(
+    source: CandleSource,
+    intervals: Vec<CandleInterval>,
+    tx_app: Sender<AppEvent>,
+    mut rx_intervals: mpsc::Receiver<Vec<CandleInterval>>,
+) {
+    let mut aggregator = CandleAggregator::new(source, &intervals);
+    let mut rx_app = tx_app.subscribe();
+    loop {
+        let event = tokio::select! {
+            event = rx_app.recv() => event,
+            Some(intervals) = rx_intervals.recv() => {
+                aggregator.add_intervals(&intervals);
+                continue;
+            }
+        };
+        let candles = match event {
+            Ok(AppEvent::MarketPlace(MarketplaceEvent::Candle(candle))) => {
+                aggregator.add_candle(&candle)
+            }
+            Ok(AppEvent::MarketPlace(MarketplaceEvent::Trade(trade))) => {
+                aggregator.add_trade(&trade)
+            }
+            Ok(AppEvent::MarketPlace(MarketplaceEvent::Book(book))) => aggregator.flush(book.time),
+            Ok(AppEvent::MarketPlace(MarketplaceEvent::StreamStatus(
+                MarketplaceStreamStatus {
+                    status: StreamStatus::Seeked { .. },
+                    ..
+                },
+            ))) => {
+                let intervals = aggregator.intervals.clone();
+                aggregator = CandleAggregator::new(source, &intervals);
+                continue;
+            }
+            Ok(_) => continue,
+            Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                error!("Candle aggregator lagged by {} events", n);
+                continue;
+            }
+            Err(_) => return,
+        };
+        for candle in candles {
+            let _ = tx_app.send(AppEvent::MarketPlace(MarketplaceEvent::Candle(candle)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    fn candle(start_time: u64, close: Decimal, closed: bool) -> MarketplaceCandle {
+        MarketplaceCandle {
+            ticker: Ticker::new("BTC", "USDC"),
+            interval: CandleInterval::M1,
+            open_price: close,
+            close_price: close,
+            high_price: close,
+            low_price: close,
+            trade_count: 1,
+            start_time,
+            close_time: start_time + 59_999,
+            volume: dec!(10),
+            closed,
+        }
+    }
+
+    #[test]
+    fn test_aggregate_candles() {
+        let mut aggregator = CandleAggregator::new(CandleSource::Candles, &[CandleInterval::M5]);
+
+        let mut closed = Vec::new();
+        for i in 0..5 {
+            let updates =
+                aggregator.add_candle(&candle(i * 60_000, dec!(10) + Decimal::from(i), false));
+            assert_eq!(updates.len(), 1);
+            assert!(!updates[0].closed);
+            let updates =
+                aggregator.add_candle(&candle(i * 60_000, dec!(10) + Decimal::from(i), true));
+            assert_eq!(updates.len(), 1);
+            closed.extend(updates.into_iter().filter(|candle| candle.closed));
+        }
+
+        assert_eq!(closed.len(), 1);
+        let candle = &closed[0];
+        assert_eq!(candle.interval, CandleInterval::M5);
+        assert_eq!(candle.start_time, 0);
+        assert_eq!(candle.close_time, 299_999);
+        assert_eq!(candle.open_price, dec!(10));
+        assert_eq!(candle.close_price, dec!(14));
+        assert_eq!(candle.high_price, dec!(14));
+        assert_eq!(candle.low_price, dec!(10));
+        assert_eq!(candle.volume, dec!(50));
+        assert_eq!(candle.trade_count, 5);
+
+        // already closed
+        assert!(aggregator.flush(400_000).is_empty());
+    }
+
+    #[test]
+    fn test_aggregate_trades() {
+        let mut aggregator = CandleAggregator::new(CandleSource::Trades, &[CandleInterval::M1]);
+        let trade = |trade_time: u64, price: Decimal| MarketplaceTrade {
+            trade_id: trade_time,
+            trade_time,
+            ticker: Ticker::new("BTC", "USDC"),
+            price,
+            quantity: dec!(2),
+            aggressor_side: None,
+        };
+
+        assert!(aggregator.add_trade(&trade(0, dec!(10))).is_empty());
+        assert!(aggregator.add_trade(&trade(2_000, dec!(8))).is_empty());
+        let candles = aggregator.add_trade(&trade(61_000, dec!(12)));
+        assert_eq!(candles.len(), 1);
+        assert_eq!(candles[0].low_price, dec!(8));
+        assert_eq!(candles[0].close_price, dec!(8));
+        assert_eq!(candles[0].volume, dec!(36));
+        assert!(candles[0].closed);
+
+        let candles = aggregator.flush(120_000);
+        assert_eq!(candles.len(), 1);
+        assert_eq!(candles[0].start_time, 60_000);
+    }
+
+    #[test]
+    fn test_start_mid_bucket() {
+        let mut aggregator = CandleAggregator::new(CandleSource::Candles, &[CandleInterval::M5]);
+
+        // started at the third minute, the first 5m candle misses its open
+        for i in 2..5 {
+            assert!(aggregator
+                .add_candle(&candle(i * 60_000, dec!(10), true))
+                .is_empty());
+        }
+        let updates = aggregator.add_candle(&candle(300_000, dec!(20), false));
+        assert_eq!(updates.len(), 1);
+        assert!(!updates[0].closed);
+        assert_eq!(updates[0].start_time, 300_000);
+        assert_eq!(updates[0].open_price, dec!(20));
+        assert!(aggregator.flush(300_000).is_empty());
+
+        // a seek starts over mid-bucket
+        let mut aggregator = CandleAggregator::new(CandleSource::Candles, &[CandleInterval::M5]);
+        assert!(aggregator
+            .add_candle(&candle(360_000, dec!(10), true))
+            .is_empty());
+        assert!(aggregator.flush(600_000).is_empty());
+    }
+}
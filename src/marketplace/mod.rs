@@ -6,15 +6,19 @@ use serde::{Deserialize, Serialize};
 use tokio::sync::broadcast::Sender;
 
 use crate::{
-    order::{Order, OrderStatus, OrderTrade},
+    order::{Order, OrderSide, OrderStatus, OrderTrade},
     portfolio::Asset,
     ticker::Ticker,
     AppEvent,
 };
+use candle::CandleInterval;
 
 pub mod binance;
+pub mod candle;
 pub mod replay;
 pub mod simulation;
+pub mod store;
+pub mod synthetic;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum MarketplaceEvent {
@@ -28,26 +32,49 @@ pub enum MarketplaceEvent {
     PortfolioUpdate(MarketplacePortfolioUpdate),
     #[serde(rename = "O")]
     OrderUpdate(MarketplaceOrderUpdate),
+    #[serde(rename = "B")]
+    BalanceUpdate(MarketplaceBalanceUpdate),
+    #[serde(rename = "L")]
+    OrderListUpdate(MarketplaceOrderListUpdate),
+    #[serde(rename = "S")]
+    StreamStatus(MarketplaceStreamStatus),
+    #[serde(rename = "G")]
+    AggTrade(MarketplaceAggTrade),
+    #[serde(rename = "T")]
+    BookTicker(MarketplaceBookTicker),
 }
 
 impl MarketplaceEvent {
-    fn get_ticker(&self) -> Option<&Ticker> {
+    pub fn get_ticker(&self) -> Option<&Ticker> {
         match &self {
             Self::Trade(event) => Some(&event.ticker),
             Self::Candle(event) => Some(&event.ticker),
             Self::Book(event) => Some(&event.ticker),
+            Self::AggTrade(event) => Some(&event.ticker),
+            Self::BookTicker(event) => Some(&event.ticker),
             _ => None,
         }
     }
 
-    fn get_time(&self) -> Option<u64> {
+    pub fn get_time(&self) -> Option<u64> {
         match &self {
             Self::Trade(event) => Some(event.trade_time),
             Self::Candle(event) => Some(event.close_time),
             Self::Book(event) => Some(event.time),
+            Self::AggTrade(event) => Some(event.trade_time),
+            Self::BookTicker(event) => Some(event.time),
             _ => None,
         }
     }
+
+    // Time of the event in the stream order. None for the updates of a forming candle,
+    // which carry the close time of their candle (up to an interval ahead of the stream).
+    pub fn get_stream_time(&self) -> Option<u64> {
+        match &self {
+            Self::Candle(event) if !event.closed => None,
+            _ => self.get_time(),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -68,6 +95,70 @@ pub struct MarketplaceTrade {
     #[serde(with = "rust_decimal::serde::str")]
     #[serde(rename = "q")]
     pub quantity: Decimal,
+
+    // Buy when the buyer took the liquidity, unknown in older recordings
+    #[serde(rename = "S", default)]
+    pub aggressor_side: Option<OrderSide>,
+}
+
+// Trades of a single taker order at the same price
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MarketplaceAggTrade {
+    #[serde(rename = "a")]
+    pub agg_trade_id: u64,
+
+    #[serde(rename = "f")]
+    pub first_trade_id: u64,
+
+    #[serde(rename = "l")]
+    pub last_trade_id: u64,
+
+    #[serde(rename = "T")]
+    pub trade_time: u64,
+
+    #[serde(rename = "s")]
+    pub ticker: Ticker,
+
+    #[serde(with = "rust_decimal::serde::str")]
+    #[serde(rename = "p")]
+    pub price: Decimal,
+
+    #[serde(with = "rust_decimal::serde::str")]
+    #[serde(rename = "q")]
+    pub quantity: Decimal,
+
+    #[serde(rename = "S")]
+    pub aggressor_side: OrderSide,
+}
+
+// Best bid and ask update
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MarketplaceBookTicker {
+    // reception time, the spot stream has no event time
+    #[serde(rename = "E")]
+    pub time: u64,
+
+    #[serde(rename = "u")]
+    pub update_id: u64,
+
+    #[serde(rename = "s")]
+    pub ticker: Ticker,
+
+    #[serde(with = "rust_decimal::serde::str")]
+    #[serde(rename = "b")]
+    pub bid_price: Decimal,
+
+    #[serde(with = "rust_decimal::serde::str")]
+    #[serde(rename = "B")]
+    pub bid_quantity: Decimal,
+
+    #[serde(with = "rust_decimal::serde::str")]
+    #[serde(rename = "a")]
+    pub ask_price: Decimal,
+
+    #[serde(with = "rust_decimal::serde::str")]
+    #[serde(rename = "A")]
+    pub ask_quantity: Decimal,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -75,6 +166,10 @@ pub struct MarketplaceCandle {
     #[serde(rename = "s")]
     pub ticker: Ticker,
 
+    // recordings made before intervals were introduced only contain 1m candles
+    #[serde(rename = "i", default)]
+    pub interval: CandleInterval,
+
     #[serde(rename = "o")]
     #[serde(with = "rust_decimal::serde::str")]
     pub open_price: Decimal,
@@ -172,6 +267,69 @@ pub struct MarketplaceOrderUpdate {
     pub trade: Option<OrderTrade>,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum StreamStatus {
+    Connected,
+    // the data stream dropped, events are missing since `since`
+    Reconnecting { since: u64 },
+    // missing candles and the book of a ticker have been reloaded for the disconnect window
+    Backfilled { ticker: Ticker, from: u64, to: u64 },
+    // the replay jumped from one time to another
+    Seeked { from: u64, to: u64 },
+}
+
+// Market data stream health, strategies should not act on the book while it is reconnecting.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MarketplaceStreamStatus {
+    #[serde(rename = "E")]
+    pub time: u64,
+    #[serde(rename = "s")]
+    pub status: StreamStatus,
+}
+
+// Balance change outside of trading (deposit, withdrawal, transfer)
+// or funds locked/unlocked by another service. Only an event, the new balances
+// come with the PortfolioUpdate of the same change.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MarketplaceBalanceUpdate {
+    #[serde(rename = "E")]
+    pub time: u64,
+    #[serde(rename = "a")]
+    pub asset: String,
+    #[serde(rename = "d")]
+    #[serde(with = "rust_decimal::serde::str")]
+    pub delta: Decimal,
+    // the delta applies to the locked amount instead of the free amount
+    #[serde(rename = "l")]
+    pub locked: bool,
+    #[serde(rename = "T")]
+    pub clear_time: u64,
+}
+
+// Status of an order list (OCO, OTO...).
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MarketplaceOrderListUpdate {
+    #[serde(rename = "E")]
+    pub time: u64,
+    #[serde(rename = "s")]
+    pub ticker: Ticker,
+    #[serde(rename = "g")]
+    pub list_id: i64,
+    #[serde(rename = "C")]
+    pub client_list_id: String,
+    #[serde(rename = "c")]
+    pub contingency_type: String,
+    #[serde(rename = "l")]
+    pub status: String,
+    #[serde(rename = "L")]
+    pub order_status: String,
+    #[serde(rename = "r")]
+    pub reject_reason: Option<String>,
+    // (marketplace id, client id) of the orders in the list
+    #[serde(rename = "O")]
+    pub orders: Vec<(String, String)>,
+}
+
 pub trait Marketplace {}
 
 pub trait MarketplaceDataStream {
@@ -193,7 +351,7 @@ pub trait MarketplaceDataApi {
     fn get_candles(
         &self,
         ticker: &Ticker,
-        interval: &str,
+        interval: CandleInterval,
         from: Option<u64>,
         to: Option<u64>,
     ) -> impl std::future::Future<Output = Result<Vec<MarketplaceCandle>>>;
@@ -203,11 +361,21 @@ pub trait MarketplaceSettingsApi {
     // Get the fees ratio for this order.
     fn get_fees(&self) -> impl std::future::Future<Output = Decimal>;
 
+    // Fees ratio of the limit orders resting in the book.
+    fn get_maker_fees(&self) -> impl std::future::Future<Output = Decimal>;
+
     // Adjust the price and amount rounding according the marketplace settings.
     fn adjust_order_price_and_amount(
         &self,
         order: &mut Order,
     ) -> impl std::future::Future<Output = Result<()>>;
+
+    // Round a base amount down to the quantity step, for the sells of what is held.
+    fn floor_amount(
+        &self,
+        ticker: &Ticker,
+        amount: Decimal,
+    ) -> impl std::future::Future<Output = Result<Decimal>>;
 }
 
 pub trait MarketplaceAccountApi {
@@ -223,6 +391,9 @@ pub trait MarketplaceTradeApi {
     ) -> impl std::future::Future<Output = Result<Vec<Order>>>;
 
     fn place_order(&mut self, order: &Order) -> impl std::future::Future<Output = Result<Order>>;
+
+    // The cancellation is confirmed by an order update.
+    fn cancel_order(&mut self, order: &Order) -> impl std::future::Future<Output = Result<()>>;
 }
 
 pub trait MarketplaceMatching {
@@ -0,0 +1,432 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet, VecDeque};
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use chrono::DateTime;
+use tracing::info;
+
+use crate::marketplace::MarketplaceEvent;
+use crate::recorder::RecordedEvent;
+use crate::ticker::Ticker;
+
+use super::source::{read_lines, recording_files, ReplayEvents, TimeRange};
+
+// Trade ids remembered per ticker to find the duplicates
+const RECENT_IDS: usize = 100_000;
+// Gaps listed per stream, the others are only counted
+const LISTED_GAPS: usize = 10;
+
+pub struct CheckParams {
+    // longest time without an event before a stream is reported as interrupted, in ms
+    pub max_gap: u64,
+    // cleaned copy of the dataset (data_path/events.jsonl)
+    pub clean_path: Option<PathBuf>,
+    // out of order events are sorted back within this window of the cleaned copy, in ms
+    pub reorder_window: u64,
+}
+
+#[derive(Debug, PartialEq)]
+enum Issue {
+    Duplicate,
+    CrossedBook,
+    EmptyBook,
+}
+
+// Events a backtest should not consume.
+#[derive(Default)]
+struct Validator {
+    trade_ids: HashMap<(Ticker, RecordedEvent), (HashSet<u64>, VecDeque<u64>)>,
+}
+
+impl Validator {
+    fn check(&mut self, event: &MarketplaceEvent) -> Option<Issue> {
+        match event {
+            MarketplaceEvent::Trade(trade) => {
+                self.duplicate(&trade.ticker, RecordedEvent::Trade, trade.trade_id)
+            }
+            MarketplaceEvent::AggTrade(trade) => {
+                self.duplicate(&trade.ticker, RecordedEvent::AggTrade, trade.agg_trade_id)
+            }
+            MarketplaceEvent::Book(book) => match (book.bids.first(), book.asks.first()) {
+                (Some((bid, _)), Some((ask, _))) if bid >= ask => Some(Issue::CrossedBook),
+                (Some(_), Some(_)) => None,
+                _ => Some(Issue::EmptyBook),
+            },
+            MarketplaceEvent::BookTicker(book) if book.bid_price >= book.ask_price => {
+                Some(Issue::CrossedBook)
+            }
+            _ => None,
+        }
+    }
+
+    fn duplicate(&mut self, ticker: &Ticker, kind: RecordedEvent, id: u64) -> Option<Issue> {
+        let (ids, order) = self.trade_ids.entry((ticker.clone(), kind)).or_default();
+        if !ids.insert(id) {
+            return Some(Issue::Duplicate);
+        }
+        order.push_back(id);
+        if order.len() > RECENT_IDS {
+            if let Some(id) = order.pop_front() {
+                ids.remove(&id);
+            }
+        }
+        None
+    }
+}
+
+// Events of one ticker and type
+#[derive(Default, Debug)]
+pub struct StreamReport {
+    pub count: u64,
+    pub first: u64,
+    pub last: u64,
+    pub gap_count: u64,
+    pub longest_gap: u64,
+    // first gaps (start, end)
+    pub gaps: Vec<(u64, u64)>,
+    pub duplicates: u64,
+    pub crossed_books: u64,
+    pub empty_books: u64,
+}
+
+#[derive(Default, Debug)]
+pub struct DataReport {
+    pub files: usize,
+    pub lines: u64,
+    pub invalid_lines: u64,
+    // events older than the previous event of the same recording
+    pub out_of_order: u64,
+    pub max_delay: u64,
+    pub streams: BTreeMap<(String, &'static str), StreamReport>,
+    // events written to the cleaned copy and events left out
+    pub cleaned: Option<(u64, u64)>,
+}
+
+fn format_time(time: u64) -> String {
+    DateTime::from_timestamp_millis(time as i64)
+        .map(|time| time.format("%Y-%m-%d %H:%M:%S%.3f").to_string())
+        .unwrap_or_else(|| time.to_string())
+}
+
+impl Display for DataReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} files, {} lines, {} invalid lines",
+            self.files, self.lines, self.invalid_lines
+        )?;
+        writeln!(
+            f,
+            "{} events out of order (up to {} ms late)",
+            self.out_of_order, self.max_delay
+        )?;
+
+        let mut tickers: BTreeMap<&str, (u64, u64, u64)> = BTreeMap::new();
+        for ((ticker, _), stream) in &self.streams {
+            let coverage = tickers.entry(ticker).or_insert((u64::MAX, 0, 0));
+            coverage.0 = coverage.0.min(stream.first);
+            coverage.1 = coverage.1.max(stream.last);
+            coverage.2 += stream.count;
+        }
+        for (ticker, (first, last, count)) in tickers {
+            writeln!(
+                f,
+                "{} : {} events from {} to {}",
+                ticker,
+                count,
+                format_time(first),
+                format_time(last)
+            )?;
+        }
+
+        for ((ticker, kind), stream) in &self.streams {
+            write!(
+                f,
+                "  {} {} : {} events, {} gaps",
+                ticker, kind, stream.count, stream.gap_count
+            )?;
+            if stream.gap_count > 0 {
+                write!(f, " (longest {} ms)", stream.longest_gap)?;
+            }
+            if stream.duplicates > 0 {
+                write!(f, ", {} duplicates", stream.duplicates)?;
+            }
+            if stream.crossed_books > 0 || stream.empty_books > 0 {
+                write!(
+                    f,
+                    ", {} crossed books, {} empty books",
+                    stream.crossed_books, stream.empty_books
+                )?;
+            }
+            writeln!(f)?;
+            for (start, end) in &stream.gaps {
+                writeln!(
+                    f,
+                    "    gap {} -> {} ({} ms)",
+                    format_time(*start),
+                    format_time(*end),
+                    end - start
+                )?;
+            }
+        }
+
+        if let Some((written, dropped)) = self.cleaned {
+            writeln!(f, "cleaned copy : {} events, {} left out", written, dropped)?;
+        }
+        Ok(())
+    }
+}
+
+impl DataReport {
+    fn add_event(
+        &mut self,
+        event: &MarketplaceEvent,
+        time: u64,
+        max_gap: u64,
+    ) -> &mut StreamReport {
+        let ticker = event
+            .get_ticker()
+            .map(|ticker| ticker.to_string())
+            .unwrap_or_default();
+        let stream = self
+            .streams
+            .entry((ticker, RecordedEvent::of(event).as_str()))
+            .or_default();
+        if stream.count == 0 {
+            stream.first = time;
+        } else if time > stream.last + max_gap {
+            stream.gap_count += 1;
+            stream.longest_gap = stream.longest_gap.max(time - stream.last);
+            if stream.gaps.len() < LISTED_GAPS {
+                stream.gaps.push((stream.last, time));
+            }
+        }
+        stream.count += 1;
+        stream.last = stream.last.max(time);
+        stream
+    }
+}
+
+// Scan the recordings of the replay paths, each one in its own order.
+pub fn check_data(paths: &[PathBuf], params: &CheckParams) -> Result<DataReport> {
+    let mut report = DataReport::default();
+    let mut validator = Validator::default();
+
+    for path in paths {
+        let mut last_time = 0;
+        for file in recording_files(path, &TimeRange::default())? {
+            info!("Checking {}", file.display());
+            report.files += 1;
+            for line in read_lines(&file)? {
+                let line = line?;
+                report.lines += 1;
+                let Ok(event) = serde_json::from_str::<MarketplaceEvent>(&line) else {
+                    report.invalid_lines += 1;
+                    continue;
+                };
+                let Some(time) = event.get_time() else {
+                    continue;
+                };
+
+                // the forming candles carry their close time, they are not ordered
+                if let Some(time) = event.get_stream_time() {
+                    if time < last_time {
+                        report.out_of_order += 1;
+                        report.max_delay = report.max_delay.max(last_time - time);
+                    }
+                    last_time = last_time.max(time);
+                }
+
+                let issue = validator.check(&event);
+                let stream = report.add_event(&event, time, params.max_gap);
+                match issue {
+                    Some(Issue::Duplicate) => stream.duplicates += 1,
+                    Some(Issue::CrossedBook) => stream.crossed_books += 1,
+                    Some(Issue::EmptyBook) => stream.empty_books += 1,
+                    None => {}
+                }
+            }
+        }
+    }
+
+    if let Some(clean_path) = &params.clean_path {
+        report.cleaned = Some(write_clean_copy(paths, clean_path, params.reorder_window)?);
+    }
+
+    Ok(report)
+}
+
+// Market events without duplicates nor invalid books, in time order.
+// Events later than the reorder window are left out.
+fn write_clean_copy(
+    paths: &[PathBuf],
+    clean_path: &Path,
+    reorder_window: u64,
+) -> Result<(u64, u64)> {
+    fs::create_dir_all(clean_path)?;
+    let mut writer = BufWriter::new(File::create(clean_path.join("events.jsonl"))?);
+    let mut events = ReplayEvents::open(paths, TimeRange::default())?;
+    let mut validator = Validator::default();
+    // (time, sequence) keeps the recorded order of the events of the same time
+    let mut pending: BinaryHeap<Reverse<(u64, u64)>> = BinaryHeap::new();
+    let mut pending_events: HashMap<u64, MarketplaceEvent> = HashMap::new();
+    let (mut sequence, mut written, mut dropped, mut written_time) = (0, 0, 0, 0);
+    let mut clock = 0;
+
+    while let Some(event) = events.next_event()? {
+        if event.get_time().is_none() {
+            continue;
+        }
+        // the forming candles stay after the event received before them
+        let time = event.get_stream_time().unwrap_or(clock);
+        clock = time;
+        if time < written_time || validator.check(&event).is_some() {
+            dropped += 1;
+            continue;
+        }
+        pending.push(Reverse((time, sequence)));
+        pending_events.insert(sequence, event);
+        sequence += 1;
+
+        while let Some(Reverse((first, _))) = pending.peek() {
+            if first + reorder_window > time {
+                break;
+            }
+            let Some(Reverse((first, sequence))) = pending.pop() else {
+                break;
+            };
+            if let Some(event) = pending_events.remove(&sequence) {
+                write_event(&mut writer, &event)?;
+                written += 1;
+                written_time = first;
+            }
+        }
+    }
+    while let Some(Reverse((_, sequence))) = pending.pop() {
+        if let Some(event) = pending_events.remove(&sequence) {
+            write_event(&mut writer, &event)?;
+            written += 1;
+        }
+    }
+    writer.flush()?;
+
+    Ok((written, dropped))
+}
+
+fn write_event(writer: &mut BufWriter<File>, event: &MarketplaceEvent) -> Result<()> {
+    serde_json::to_writer(&mut *writer, event)?;
+    writer.write_all(b"\n")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::marketplace::{MarketplaceBook, MarketplaceTrade};
+    use rust_decimal_macros::dec;
+
+    fn trade(id: u64, time: u64) -> MarketplaceEvent {
+        MarketplaceEvent::Trade(MarketplaceTrade {
+            trade_id: id,
+            trade_time: time,
+            ticker: Ticker::new("BTC", "USDC"),
+            price: dec!(100),
+            quantity: dec!(1),
+            aggressor_side: None,
+        })
+    }
+
+    fn book(time: u64, bid: rust_decimal::Decimal) -> MarketplaceEvent {
+        MarketplaceEvent::Book(MarketplaceBook {
+            ticker: Ticker::new("BTC", "USDC"),
+            first_update_id: 0,
+            final_update_id: time,
+            time,
+            bids: vec![(bid, dec!(1))],
+            asks: vec![(dec!(101), dec!(1))],
+        })
+    }
+
+    #[test]
+    fn test_check_data() {
+        let dir = std::env::temp_dir().join(format!("check-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let events = [
+            trade(1, 1_000),
+            book(1_000, dec!(100)),
+            trade(2, 3_000),
+            // forming candle, timed by its close
+            MarketplaceEvent::Candle(crate::marketplace::MarketplaceCandle {
+                ticker: Ticker::new("BTC", "USDC"),
+                interval: crate::marketplace::candle::CandleInterval::M1,
+                open_price: dec!(100),
+                close_price: dec!(100),
+                high_price: dec!(100),
+                low_price: dec!(100),
+                trade_count: 1,
+                start_time: 0,
+                close_time: 59_999,
+                volume: dec!(1),
+                closed: false,
+            }),
+            // late, duplicate then crossed
+            trade(3, 2_000),
+            trade(2, 3_000),
+            book(4_000, dec!(102)),
+            // gap
+            trade(4, 100_000),
+            book(100_000, dec!(100)),
+        ];
+        let mut content: String = events
+            .iter()
+            .map(|event| format!("{}\n", serde_json::to_string(event).unwrap()))
+            .collect();
+        content.push_str("{\"P\":\n");
+        fs::write(dir.join("events.jsonl"), content).unwrap();
+
+        let clean_path = dir.join("clean");
+        let report = check_data(
+            std::slice::from_ref(&dir),
+            &CheckParams {
+                max_gap: 60_000,
+                clean_path: Some(clean_path.clone()),
+                reorder_window: 5_000,
+            },
+        )
+        .unwrap();
+
+        assert_eq!(report.lines, 10);
+        assert_eq!(report.invalid_lines, 1);
+        assert_eq!(report.out_of_order, 1);
+        assert_eq!(report.max_delay, 1_000);
+        let trades = &report.streams[&("BTCUSDC".to_string(), "trade")];
+        assert_eq!(trades.count, 5);
+        assert_eq!(trades.duplicates, 1);
+        assert_eq!(trades.gaps, vec![(3_000, 100_000)]);
+        let books = &report.streams[&("BTCUSDC".to_string(), "book")];
+        assert_eq!(books.crossed_books, 1);
+        assert_eq!(books.gap_count, 1);
+        assert_eq!(report.cleaned, Some((7, 2)));
+
+        let times: Vec<u64> = fs::read_to_string(clean_path.join("events.jsonl"))
+            .unwrap()
+            .lines()
+            .map(|line| {
+                serde_json::from_str::<MarketplaceEvent>(line)
+                    .unwrap()
+                    .get_time()
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(
+            times,
+            vec![1_000, 1_000, 2_000, 3_000, 59_999, 100_000, 100_000]
+        );
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -1,56 +1,289 @@
-use crate::marketplace::MarketplaceEvent;
+use crate::marketplace::candle::{CandleInterval, CandleSource};
+use crate::marketplace::{MarketplaceEvent, MarketplaceStreamStatus, StreamStatus};
 use crate::{marketplace::MarketplaceDataStream, ticker::Ticker, AppEvent};
 use anyhow::Result;
+use std::path::PathBuf;
 use std::time::Duration;
-use tokio::fs::File;
-use tokio::io::{AsyncBufReadExt, BufReader};
-use tracing::debug;
+use tokio::sync::broadcast::Sender;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tracing::{error, info};
 
+use super::source::{ReplayEvents, TimeRange};
 use super::ReplayMarketplace;
 
+// Events read on a blocking thread, the bounded channel keeps it just ahead of the replay.
+struct EventFeed {
+    rx: mpsc::Receiver<MarketplaceEvent>,
+    reader: JoinHandle<Result<()>>,
+}
+
+impl EventFeed {
+    async fn open(data_paths: Vec<PathBuf>, range: TimeRange) -> Result<Self> {
+        let mut events =
+            tokio::task::spawn_blocking(move || ReplayEvents::open(&data_paths, range)).await??;
+
+        let (tx, rx) = mpsc::channel::<MarketplaceEvent>(1000);
+        let reader = tokio::task::spawn_blocking(move || -> Result<()> {
+            while let Some(event) = events.next_event()? {
+                if tx.blocking_send(event).is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        });
+        Ok(Self { rx, reader })
+    }
+
+    async fn close(self) {
+        let Self { rx, reader } = self;
+        // stops the reader
+        drop(rx);
+        match reader.await {
+            Ok(Err(err)) => error!("Could not read replay events : {err}"),
+            Err(err) => error!("Replay reader failed : {err}"),
+            Ok(Ok(())) => {}
+        }
+    }
+}
+
+impl<F> ReplayMarketplace<F> {
+    // Wait until the event can be played, according to pause, steps and speed.
+    // Returns false when a seek was asked instead.
+    async fn wait_turn(
+        &self,
+        event: &MarketplaceEvent,
+        time: u64,
+        anchor: &mut Option<(u64, Instant)>,
+    ) -> bool {
+        loop {
+            let target = {
+                let mut control = self.control.write().await;
+                if control.seek.is_some() {
+                    return false;
+                }
+                if control.paused {
+                    *anchor = None;
+                    if control.steps > 0 {
+                        control.steps -= 1;
+                        return true;
+                    }
+                    if control.step_book {
+                        control.step_book = !matches!(event, MarketplaceEvent::Book(..));
+                        return true;
+                    }
+                    None
+                } else if let Some(speed) = control.speed {
+                    // recorded time and instant of the first event played at this speed
+                    let (start_time, start) = *anchor.get_or_insert((time, Instant::now()));
+                    let delay = time.saturating_sub(start_time) as f64 / 1000.0 / speed;
+                    Some(start + Duration::from_secs_f64(delay))
+                } else {
+                    *anchor = None;
+                    return true;
+                }
+            };
+
+            match target {
+                Some(target) => tokio::select! {
+                    _ = tokio::time::sleep_until(target) => return true,
+                    _ = self.control_changed.notified() => *anchor = None,
+                },
+                None => self.control_changed.notified().await,
+            }
+        }
+    }
+
+    // Reopen the feed at the asked time, the strategies reload their history on the status
+    async fn seek(
+        &self,
+        feed: &mut EventFeed,
+        last_time: &mut u64,
+        tx: &Sender<AppEvent>,
+    ) -> Result<()> {
+        let Some(seek) = self.control.write().await.seek.take() else {
+            return Ok(());
+        };
+        info!("Replay seek from {} to {}", last_time, seek);
+        let range = TimeRange {
+            from: Some(seek),
+            to: self.range.to,
+        };
+        let previous =
+            std::mem::replace(feed, EventFeed::open(self.data_paths.clone(), range).await?);
+        previous.close().await;
+        let _ = tx.send(AppEvent::MarketPlace(MarketplaceEvent::StreamStatus(
+            MarketplaceStreamStatus {
+                time: seek,
+                status: StreamStatus::Seeked {
+                    from: *last_time,
+                    to: seek,
+                },
+            },
+        )));
+        *last_time = seek;
+        Ok(())
+    }
+}
+
 impl<F> MarketplaceDataStream for ReplayMarketplace<F> {
     async fn start_data_stream(
         &mut self,
         tickers: &Vec<Ticker>,
         tx: tokio::sync::broadcast::Sender<AppEvent>,
     ) -> Result<()> {
-        let mut path = self.data_path.clone();
-        path.push("events.jsonl");
+        let mut feed = EventFeed::open(self.data_paths.clone(), self.range).await?;
+        let mut anchor = None;
+        let mut last_time = self.range.from.unwrap_or(0);
 
-        let file = File::open(path).await?;
-        let reader = BufReader::new(file);
-        let mut lines = reader.lines();
+        loop {
+            let Some(event) = feed.rx.recv().await else {
+                if !self.hold_end {
+                    break;
+                }
+                info!("Replay reached the end of the data");
+                while self.control.read().await.seek.is_none() {
+                    self.control_changed.notified().await;
+                }
+                self.seek(&mut feed, &mut last_time, &tx).await?;
+                anchor = None;
+                continue;
+            };
+            if let Some(event_ticker) = event.get_ticker() {
+                if !tickers.contains(event_ticker) {
+                    continue;
+                }
+            }
+            if self.candle_source == CandleSource::Trades
+                && matches!(&event, MarketplaceEvent::Candle(candle) if candle.interval == CandleInterval::M1)
+            {
+                continue;
+            }
+            // the forming candles are replayed at the time of the events around them
+            let time = event.get_stream_time().unwrap_or(last_time);
 
-        while let Ok(Some(line)) = lines.next_line().await {
-            while *self.paused.read().await {
-                tokio::time::sleep(Duration::from_secs(1)).await;
+            if !self.wait_turn(&event, time, &mut anchor).await {
+                self.seek(&mut feed, &mut last_time, &tx).await?;
+                anchor = None;
+                continue;
             }
-            match serde_json::de::from_str::<MarketplaceEvent>(&line) {
-                Ok(event) => {
-                    if let Some(event_ticker) = event.get_ticker() {
-                        if !tickers.contains(event_ticker) {
-                            continue;
-                        }
-                    }
-                    if matches!(
-                        event,
-                        MarketplaceEvent::Book(..)
-                            | MarketplaceEvent::Trade(..)
-                            | MarketplaceEvent::Candle(..)
-                    ) {
-                        tx.send(AppEvent::MarketPlace(event)).unwrap();
-                    }
 
-                    if self.read_interval > 0 {
-                        tokio::time::sleep(Duration::from_micros(self.read_interval)).await;
-                    }
-                }
-                Err(err) => {
-                    debug!("Could not parse line : {err}");
-                }
+            last_time = time;
+            tx.send(AppEvent::MarketPlace(event)).unwrap();
+
+            if anchor.is_none() && self.read_interval > 0 {
+                tokio::time::sleep(Duration::from_micros(self.read_interval)).await;
             }
         }
 
+        feed.close().await;
+
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::marketplace::replay::ReplayCommand;
+    use crate::marketplace::MarketplaceBook;
+    use tokio::sync::broadcast::error::TryRecvError;
+
+    #[tokio::test]
+    async fn test_step_and_seek() {
+        let ticker = Ticker::new("BTC", "USDC");
+        let path = std::env::temp_dir().join(format!("replay-{}.jsonl", uuid::Uuid::new_v4()));
+        let content: String = (1..=5)
+            .map(|i| {
+                let event = MarketplaceEvent::Book(MarketplaceBook {
+                    ticker: ticker.clone(),
+                    first_update_id: 0,
+                    final_update_id: i,
+                    time: i * 10,
+                    bids: Vec::new(),
+                    asks: Vec::new(),
+                });
+                format!("{}\n", serde_json::to_string(&event).unwrap())
+            })
+            .collect();
+        std::fs::write(&path, content).unwrap();
+
+        let mut replay = ReplayMarketplace::new(vec![path.clone()], (), 0);
+        replay.command(ReplayCommand::Step(2)).await;
+        let (tx, mut rx) = tokio::sync::broadcast::channel(100);
+        tokio::spawn({
+            let mut replay = replay.clone();
+            async move { replay.start_data_stream(&vec![ticker], tx).await }
+        });
+
+        let time = |event: AppEvent| match event {
+            AppEvent::MarketPlace(event) => event.get_time(),
+            _ => None,
+        };
+        assert_eq!(time(rx.recv().await.unwrap()), Some(10));
+        assert_eq!(time(rx.recv().await.unwrap()), Some(20));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(matches!(rx.try_recv(), Err(TryRecvError::Empty)));
+
+        replay.command(ReplayCommand::Seek(40)).await;
+        replay.toggle_pause().await;
+        assert!(matches!(
+            rx.recv().await.unwrap(),
+            AppEvent::MarketPlace(MarketplaceEvent::StreamStatus(..))
+        ));
+        assert_eq!(time(rx.recv().await.unwrap()), Some(40));
+        assert_eq!(time(rx.recv().await.unwrap()), Some(50));
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn test_restart_at_end() {
+        let ticker = Ticker::new("BTC", "USDC");
+        let path = std::env::temp_dir().join(format!("replay-{}.jsonl", uuid::Uuid::new_v4()));
+        let content: String = (1..=3)
+            .map(|i| {
+                let event = MarketplaceEvent::Book(MarketplaceBook {
+                    ticker: ticker.clone(),
+                    first_update_id: 0,
+                    final_update_id: i,
+                    time: i * 10,
+                    bids: Vec::new(),
+                    asks: Vec::new(),
+                });
+                format!("{}\n", serde_json::to_string(&event).unwrap())
+            })
+            .collect();
+        std::fs::write(&path, content).unwrap();
+
+        let mut replay = ReplayMarketplace::new(vec![path.clone()], (), 0).with_hold_end(true);
+        let (tx, mut rx) = tokio::sync::broadcast::channel(100);
+        let task = tokio::spawn({
+            let mut replay = replay.clone();
+            async move { replay.start_data_stream(&vec![ticker], tx).await }
+        });
+
+        for _ in 1..=3 {
+            rx.recv().await.unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!task.is_finished());
+
+        replay.command(ReplayCommand::Restart).await;
+        assert!(matches!(
+            rx.recv().await.unwrap(),
+            AppEvent::MarketPlace(MarketplaceEvent::StreamStatus(MarketplaceStreamStatus {
+                status: StreamStatus::Seeked { from: 30, to: 0 },
+                ..
+            }))
+        ));
+        match rx.recv().await.unwrap() {
+            AppEvent::MarketPlace(event) => assert_eq!(event.get_time(), Some(10)),
+            _ => panic!("expected the first book again"),
+        }
+
+        task.abort();
+        let _ = std::fs::remove_file(path);
+    }
+}
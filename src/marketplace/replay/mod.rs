@@ -1,56 +1,152 @@
 use crate::ticker::Ticker;
+use serde::{Deserialize, Serialize};
 use std::{path::PathBuf, sync::Arc};
-use tokio::fs::OpenOptions;
-use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::{
-    fs::File,
-    io::{AsyncReadExt, AsyncWriteExt},
-    sync::RwLock,
-};
-
-use super::{
-    Marketplace, MarketplaceCandle, MarketplaceDataApi, MarketplaceEvent, MarketplaceSettingsApi,
-};
+use tokio::sync::{Notify, RwLock};
 
+use super::candle::{CandleInterval, CandleSource};
+use super::store::CandleStore;
+use super::{Marketplace, MarketplaceCandle, MarketplaceDataApi, MarketplaceSettingsApi};
+use source::{ReplayEvents, TimeRange};
+
+pub mod check;
 pub mod data_stream;
+pub mod source;
+
+// Candle store of a dataset (data_path/candles), the history before its events
+pub const CANDLES_DIR: &str = "candles";
+
+// Transport commands of a running replay
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum ReplayCommand {
+    // play at N times the recorded pace, 0 goes back to the fixed read interval
+    Speed(f64),
+    // pause, then play the next N events
+    Step(u64),
+    // pause, then play up to the next book
+    StepBook,
+    // jump to a time (ms), backward or forward
+    Seek(u64),
+    // jump back to the start of the replay
+    Restart,
+}
+
+#[derive(Debug, Default)]
+struct ReplayControl {
+    paused: bool,
+    // multiplier of the recorded pace, the fixed read interval is used when unset
+    speed: Option<f64>,
+    // events to play while paused
+    steps: u64,
+    step_book: bool,
+    seek: Option<u64>,
+}
 
 #[derive(Clone, Debug)]
 pub struct ReplayMarketplace<F> {
-    data_path: PathBuf,
-    paused: Arc<RwLock<bool>>,
+    // recordings (files or directories) replayed together
+    data_paths: Vec<PathBuf>,
+    range: TimeRange,
+    control: Arc<RwLock<ReplayControl>>,
+    // wakes the data stream up when the control changes
+    control_changed: Arc<Notify>,
     read_interval: u64,
+    // the recorded 1m klines are left out when the candles are built from trades
+    candle_source: CandleSource,
+    // wait for a seek or a restart at the end of the data instead of ending the stream
+    hold_end: bool,
+    // candle stores of the datasets, served before the fallback
+    candle_stores: Vec<CandleStore>,
     fallback: F,
 }
 
-impl<F: MarketplaceSettingsApi + MarketplaceDataApi> ReplayMarketplace<F> {
-    pub fn new(data_path: PathBuf, fallback: F, read_interval: u64) -> Self {
+impl<F> ReplayMarketplace<F> {
+    pub fn new(data_paths: Vec<PathBuf>, fallback: F, read_interval: u64) -> Self {
+        let candle_stores = data_paths
+            .iter()
+            .map(|path| path.join(CANDLES_DIR))
+            .filter(|path| path.is_dir())
+            .map(CandleStore::new)
+            .collect();
         Self {
-            data_path,
-            paused: Arc::new(RwLock::new(false)),
+            data_paths,
+            range: TimeRange::default(),
+            control: Arc::default(),
+            control_changed: Arc::default(),
             read_interval,
+            candle_source: CandleSource::Candles,
+            hold_end: false,
+            candle_stores,
             fallback,
         }
     }
 
-    pub async fn get_start_time(&self) -> Option<u64> {
-        let mut path = self.data_path.clone();
-        path.push("events.jsonl");
+    pub fn with_time_range(mut self, range: TimeRange) -> Self {
+        self.range = range;
+        self
+    }
 
-        let file = File::open(path).await.ok()?;
-        let reader = BufReader::new(file);
-        let mut lines = reader.lines();
+    pub fn with_candle_source(mut self, candle_source: CandleSource) -> Self {
+        self.candle_source = candle_source;
+        self
+    }
 
-        while let Ok(Some(line)) = lines.next_line().await {
-            if let Ok(event) = serde_json::de::from_str::<MarketplaceEvent>(&line) {
-                return event.get_time();
-            }
+    pub fn with_hold_end(mut self, hold_end: bool) -> Self {
+        self.hold_end = hold_end;
+        self
+    }
+
+    pub fn with_speed(self, speed: Option<f64>) -> Self {
+        Self {
+            control: Arc::new(RwLock::new(ReplayControl {
+                speed,
+                ..Default::default()
+            })),
+            ..self
         }
-        None
+    }
+
+    // Time of the first replayed event
+    pub async fn get_start_time(&self) -> Option<u64> {
+        let data_paths = self.data_paths.clone();
+        let range = self.range;
+        tokio::task::spawn_blocking(move || {
+            ReplayEvents::open(&data_paths, range)
+                .and_then(|mut events| events.next_event())
+                .ok()
+                .flatten()
+                .and_then(|event| event.get_stream_time().or(event.get_time()))
+        })
+        .await
+        .ok()
+        .flatten()
     }
 
     pub async fn toggle_pause(&mut self) {
-        let mut paused = self.paused.write().await;
-        *paused = !*paused;
+        let mut control = self.control.write().await;
+        control.paused = !control.paused;
+        control.steps = 0;
+        control.step_book = false;
+        self.control_changed.notify_one();
+    }
+
+    pub async fn command(&mut self, cmd: ReplayCommand) {
+        let mut control = self.control.write().await;
+        match cmd {
+            ReplayCommand::Speed(speed) => {
+                control.speed = (speed > 0.0).then_some(speed);
+            }
+            ReplayCommand::Step(count) => {
+                control.paused = true;
+                control.steps += count;
+            }
+            ReplayCommand::StepBook => {
+                control.paused = true;
+                control.step_book = true;
+            }
+            ReplayCommand::Seek(time) => control.seek = Some(time),
+            ReplayCommand::Restart => control.seek = Some(self.range.from.unwrap_or(0)),
+        }
+        self.control_changed.notify_one();
     }
 }
 
@@ -61,63 +157,41 @@ impl<F: MarketplaceSettingsApi> MarketplaceSettingsApi for ReplayMarketplace<F>
         self.fallback.get_fees().await
     }
 
+    async fn get_maker_fees(&self) -> rust_decimal::Decimal {
+        self.fallback.get_maker_fees().await
+    }
+
     async fn adjust_order_price_and_amount(
         &self,
         order: &mut crate::order::Order,
     ) -> anyhow::Result<()> {
         self.fallback.adjust_order_price_and_amount(order).await
     }
+
+    async fn floor_amount(
+        &self,
+        ticker: &Ticker,
+        amount: rust_decimal::Decimal,
+    ) -> anyhow::Result<rust_decimal::Decimal> {
+        self.fallback.floor_amount(ticker, amount).await
+    }
 }
 
 impl<F: MarketplaceDataApi> MarketplaceDataApi for ReplayMarketplace<F> {
     async fn get_candles(
         &self,
         ticker: &Ticker,
-        interval: &str,
+        interval: CandleInterval,
         from: Option<u64>,
         to: Option<u64>,
     ) -> anyhow::Result<Vec<MarketplaceCandle>> {
-        let cache_path = if from.is_some() || to.is_some() {
-            let mut path = self.data_path.clone();
-            path.push(format!(
-                "candles-{}-{}-{}-{}.json",
-                ticker,
-                interval,
-                from.unwrap_or(0),
-                to.unwrap_or(0)
-            ));
-            Some(path)
-        } else {
-            None
-        };
-
-        let cache = if let Some(cache_path) = &cache_path {
-            File::open(cache_path).await.ok()
-        } else {
-            None
-        };
-
-        if let Some(mut cache) = cache {
-            let mut content = String::new();
-            cache.read_to_string(&mut content).await?;
-            let candles = serde_json::from_str::<Vec<MarketplaceCandle>>(content.as_str())?;
-            Ok(candles)
-        } else {
-            let candles = self
-                .fallback
-                .get_candles(ticker, interval, from, to)
-                .await?;
-            if let Some(cache_path) = &cache_path {
-                let mut cache = OpenOptions::new()
-                    .write(true)
-                    .create(true)
-                    .truncate(true)
-                    .open(cache_path)
-                    .await?;
-                let content = serde_json::to_string(&candles)?;
-                cache.write_all(content.as_bytes()).await?;
+        for store in self.candle_stores.iter() {
+            let candles = store.get_stored_candles(ticker, interval, from, to).await?;
+            if !candles.is_empty() {
+                return Ok(candles);
             }
-            Ok(candles)
         }
+        // the fallback serves them from its candle store when it has one
+        self.fallback.get_candles(ticker, interval, from, to).await
     }
 }
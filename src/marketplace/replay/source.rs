@@ -0,0 +1,361 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, VecDeque};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Lines, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use tracing::debug;
+
+use crate::marketplace::MarketplaceEvent;
+use crate::recorder::RecordIndex;
+
+// Time window of a replay, in ms, both ends included.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TimeRange {
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+}
+
+impl TimeRange {
+    pub fn contains(&self, time: u64) -> bool {
+        self.from.is_none_or(|from| time >= from) && self.to.is_none_or(|to| time <= to)
+    }
+
+    fn overlaps(&self, from: u64, to: u64) -> bool {
+        self.from.is_none_or(|start| to >= start) && self.to.is_none_or(|end| from <= end)
+    }
+}
+
+fn is_recording(path: &Path) -> bool {
+    let name = path.to_string_lossy();
+    name.ends_with(".jsonl") || name.ends_with(".jsonl.gz")
+}
+
+// Files of a replay path, in time order. A directory written by the recorder is read through
+// its index so the days outside of the range are not opened.
+pub(crate) fn recording_files(path: &Path, range: &TimeRange) -> Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    if let Some(index) = RecordIndex::load(path) {
+        return Ok(index
+            .segments
+            .iter()
+            .filter(|segment| segment.count > 0 && range.overlaps(segment.from, segment.to))
+            .map(|segment| path.join(&segment.file))
+            .filter(|file| file.exists())
+            .collect());
+    }
+
+    // events.jsonl of downloads and imports, or daily segments without index
+    let mut files: Vec<PathBuf> = fs::read_dir(path)
+        .with_context(|| format!("Could not read {}", path.display()))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|file| is_recording(file))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+// Stream time of a line, the forming candles are not time ordered
+fn parse_time(line: &str) -> Option<u64> {
+    serde_json::from_str::<MarketplaceEvent>(line)
+        .ok()
+        .and_then(|event| event.get_stream_time())
+}
+
+// Time of the first event starting at or after this offset, with the offset of its line.
+fn time_after(reader: &mut BufReader<File>, offset: u64) -> Result<Option<(u64, u64)>> {
+    let mut line = String::new();
+    let mut position = offset;
+    if offset > 0 {
+        // skip the end of the line containing offset - 1
+        reader.seek(SeekFrom::Start(offset - 1))?;
+        position += reader.read_line(&mut line)? as u64 - 1;
+    } else {
+        reader.seek(SeekFrom::Start(0))?;
+    }
+    loop {
+        line.clear();
+        let size = reader.read_line(&mut line)? as u64;
+        if size == 0 {
+            return Ok(None);
+        }
+        if let Some(time) = parse_time(&line) {
+            return Ok(Some((time, position)));
+        }
+        position += size;
+    }
+}
+
+// Lines of a recording file, gzipped or not.
+pub(crate) fn read_lines(path: &Path) -> Result<Lines<Box<dyn BufRead + Send>>> {
+    let file = File::open(path).with_context(|| format!("Could not open {}", path.display()))?;
+    let reader: Box<dyn BufRead + Send> = if path.extension().is_some_and(|ext| ext == "gz") {
+        Box::new(BufReader::new(flate2::read::GzDecoder::new(file)))
+    } else {
+        Box::new(BufReader::new(file))
+    };
+    Ok(reader.lines())
+}
+
+// Binary search of the first line of a time ordered file at or after this time.
+fn seek_time(file: File, from: u64) -> Result<BufReader<File>> {
+    let mut reader = BufReader::new(file);
+    let (mut low, mut high) = (0, reader.get_ref().metadata()?.len());
+    while low < high {
+        let mid = low + (high - low) / 2;
+        match time_after(&mut reader, mid)? {
+            Some((time, _)) if time < from => low = mid + 1,
+            _ => high = mid,
+        }
+    }
+    let start = time_after(&mut reader, low)?.map_or(low, |(_, position)| position);
+    reader.seek(SeekFrom::Start(start))?;
+    Ok(reader)
+}
+
+// Events of the files of a replay path, one after the other.
+struct Source {
+    files: VecDeque<PathBuf>,
+    lines: Option<Lines<Box<dyn BufRead + Send>>>,
+    range: TimeRange,
+    // stream time of the last event, the time of the forming candles following it
+    clock: Option<u64>,
+}
+
+impl Source {
+    fn open(&self, path: &Path) -> Result<Lines<Box<dyn BufRead + Send>>> {
+        match self.range.from {
+            Some(from) if path.extension().is_none_or(|ext| ext != "gz") => {
+                let file = File::open(path)
+                    .with_context(|| format!("Could not open {}", path.display()))?;
+                let reader: Box<dyn BufRead + Send> = Box::new(seek_time(file, from)?);
+                Ok(reader.lines())
+            }
+            _ => read_lines(path),
+        }
+    }
+
+    fn next_event(&mut self) -> Result<Option<(u64, MarketplaceEvent)>> {
+        loop {
+            let Some(lines) = self.lines.as_mut() else {
+                let Some(path) = self.files.pop_front() else {
+                    return Ok(None);
+                };
+                debug!("Replaying {}", path.display());
+                self.lines = Some(self.open(&path)?);
+                continue;
+            };
+            let Some(line) = lines.next() else {
+                self.lines = None;
+                continue;
+            };
+
+            let line = line?;
+            let event = match serde_json::from_str::<MarketplaceEvent>(&line) {
+                Ok(event) => event,
+                Err(err) => {
+                    debug!("Could not parse line : {err}");
+                    continue;
+                }
+            };
+            // account events of the recorded bot are not replayed
+            if event.get_time().is_none() {
+                continue;
+            }
+            let time = match (event.get_stream_time(), &event) {
+                (Some(time), _) => {
+                    if self.range.to.is_some_and(|to| time > to) {
+                        self.files.clear();
+                        self.lines = None;
+                        return Ok(None);
+                    }
+                    self.clock = Some(time);
+                    time
+                }
+                // received after the previous event, during its candle
+                (None, MarketplaceEvent::Candle(candle)) => {
+                    self.clock.unwrap_or(0).max(candle.start_time)
+                }
+                (None, _) => continue,
+            };
+            if self.range.contains(time) {
+                return Ok(Some((time, event)));
+            }
+        }
+    }
+}
+
+// Market events of several replay paths (files or directories), merged by time.
+pub struct ReplayEvents {
+    sources: Vec<Source>,
+    heads: Vec<Option<MarketplaceEvent>>,
+    queue: BinaryHeap<Reverse<(u64, usize)>>,
+}
+
+impl ReplayEvents {
+    pub fn open(paths: &[PathBuf], range: TimeRange) -> Result<Self> {
+        let mut events = Self {
+            sources: Vec::new(),
+            heads: Vec::new(),
+            queue: BinaryHeap::new(),
+        };
+        for path in paths {
+            let mut source = Source {
+                files: recording_files(path, &range)?.into(),
+                lines: None,
+                range,
+                clock: None,
+            };
+            let head = source.next_event()?;
+            if let Some((time, _)) = head {
+                events.queue.push(Reverse((time, events.sources.len())));
+            }
+            events.heads.push(head.map(|(_, event)| event));
+            events.sources.push(source);
+        }
+        Ok(events)
+    }
+
+    pub fn next_event(&mut self) -> Result<Option<MarketplaceEvent>> {
+        let Some(Reverse((_, index))) = self.queue.pop() else {
+            return Ok(None);
+        };
+        let event = self.heads[index].take();
+
+        let head = self.sources[index].next_event()?;
+        if let Some((time, _)) = head {
+            self.queue.push(Reverse((time, index)));
+        }
+        self.heads[index] = head.map(|(_, event)| event);
+
+        Ok(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::marketplace::MarketplaceBook;
+    use crate::ticker::Ticker;
+    use std::io::Write;
+
+    fn line(time: u64) -> String {
+        let event = MarketplaceEvent::Book(MarketplaceBook {
+            ticker: Ticker::new("BTC", "USDC"),
+            first_update_id: 0,
+            final_update_id: time,
+            time,
+            bids: Vec::new(),
+            asks: Vec::new(),
+        });
+        format!("{}\n", serde_json::to_string(&event).unwrap())
+    }
+
+    fn forming_candle(start_time: u64) -> String {
+        let event = MarketplaceEvent::Candle(crate::marketplace::MarketplaceCandle {
+            ticker: Ticker::new("BTC", "USDC"),
+            interval: crate::marketplace::candle::CandleInterval::M1,
+            open_price: rust_decimal_macros::dec!(1),
+            close_price: rust_decimal_macros::dec!(1),
+            high_price: rust_decimal_macros::dec!(1),
+            low_price: rust_decimal_macros::dec!(1),
+            trade_count: 1,
+            start_time,
+            close_time: start_time + 59_999,
+            volume: rust_decimal_macros::dec!(1),
+            closed: false,
+        });
+        format!("{}\n", serde_json::to_string(&event).unwrap())
+    }
+
+    #[test]
+    fn test_replay_events() {
+        let dir = std::env::temp_dir().join(format!("replay-{}", uuid::Uuid::new_v4()));
+        let days = dir.join("days");
+        fs::create_dir_all(&days).unwrap();
+
+        // daily segments, the first one compressed
+        let mut encoder = flate2::write::GzEncoder::new(
+            File::create(days.join("events-2025-01-01.jsonl.gz")).unwrap(),
+            flate2::Compression::default(),
+        );
+        for time in [10, 20, 30] {
+            encoder.write_all(line(time).as_bytes()).unwrap();
+        }
+        encoder.finish().unwrap();
+        let content: String = (40..100).step_by(10).map(line).collect();
+        fs::write(days.join("events-2025-01-02.jsonl"), content).unwrap();
+
+        // another recording, with an unparsable line
+        let other = dir.join("other.jsonl");
+        let content: String = [line(15), "{\"P\":\n".to_string(), line(45), line(95)].concat();
+        fs::write(&other, content).unwrap();
+
+        let times = |range: TimeRange| {
+            let mut events = ReplayEvents::open(&[days.clone(), other.clone()], range).unwrap();
+            let mut times = Vec::new();
+            while let Some(event) = events.next_event().unwrap() {
+                times.push(event.get_time().unwrap());
+            }
+            times
+        };
+
+        assert_eq!(
+            times(TimeRange::default()),
+            vec![10, 15, 20, 30, 40, 45, 50, 60, 70, 80, 90, 95]
+        );
+        assert_eq!(
+            times(TimeRange {
+                from: Some(25),
+                to: Some(60),
+            }),
+            vec![30, 40, 45, 50, 60]
+        );
+        assert_eq!(
+            times(TimeRange {
+                from: Some(85),
+                to: None,
+            }),
+            vec![90, 95]
+        );
+
+        // forming candles timed by their close time do not end the range nor break the seek
+        let live = dir.join("live.jsonl");
+        let content: String = [
+            line(100_000),
+            forming_candle(60_000),
+            line(110_000),
+            forming_candle(60_000),
+            line(120_000),
+            line(130_000),
+        ]
+        .concat();
+        fs::write(&live, content).unwrap();
+        let mut events = ReplayEvents::open(
+            &[live],
+            TimeRange {
+                from: Some(105_000),
+                to: Some(125_000),
+            },
+        )
+        .unwrap();
+        let mut kinds = Vec::new();
+        while let Some(event) = events.next_event().unwrap() {
+            kinds.push((event.get_stream_time(), event.get_time()));
+        }
+        assert_eq!(
+            kinds,
+            vec![
+                (Some(110_000), Some(110_000)),
+                (None, Some(119_999)),
+                (Some(120_000), Some(120_000))
+            ]
+        );
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
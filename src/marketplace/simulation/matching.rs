@@ -23,11 +23,18 @@ impl<S: MarketplaceSettingsApi> SimulationMarketplace<S> {
 
         let mut orders = self.orders.write().await;
         let book = self.order_book.read().await;
+        let mut resting = self.resting.write().await;
 
         for order in orders
             .iter_mut()
             .filter(|order| matches!(order.status, OrderStatus::Active))
         {
+            // resting orders are filled at their price, the others take the book
+            let maker = match order.order_type {
+                OrderType::LimitMaker => true,
+                OrderType::Limit => resting.contains(&order.id),
+                _ => false,
+            };
             if let Some(book) = book.get(&order.ticker) {
                 let book = match order.side {
                     OrderSide::Sell => book.bids.iter(),
@@ -38,7 +45,7 @@ impl<S: MarketplaceSettingsApi> SimulationMarketplace<S> {
                     if !matches!(order.status, OrderStatus::Active) {
                         break;
                     }
-                    let trade = match (order.side, order.order_type) {
+                    let mut trade = match (order.side, order.order_type) {
                         (OrderSide::Buy, OrderType::Market) => {
                             let to_fulfill_quote =
                                 order.quote_amount - order.cumulative_quote_amount;
@@ -53,6 +60,8 @@ impl<S: MarketplaceSettingsApi> SimulationMarketplace<S> {
                                     trade_time: time,
                                     amount: book_order.1,
                                     price: book_order.0,
+                                    fee: dec!(0),
+                                    fee_asset: None,
                                 }
                             } else {
                                 let eaten = to_fulfill_quote / book_order.0;
@@ -64,6 +73,8 @@ impl<S: MarketplaceSettingsApi> SimulationMarketplace<S> {
                                     trade_time: time,
                                     amount: eaten,
                                     price: book_order.0,
+                                    fee: dec!(0),
+                                    fee_asset: None,
                                 }
                             }
                         }
@@ -80,6 +91,8 @@ impl<S: MarketplaceSettingsApi> SimulationMarketplace<S> {
                                     trade_time: time,
                                     amount: book_order.1,
                                     price: book_order.0,
+                                    fee: dec!(0),
+                                    fee_asset: None,
                                 }
                             } else {
                                 order.filled_amount += to_fulfill;
@@ -90,10 +103,12 @@ impl<S: MarketplaceSettingsApi> SimulationMarketplace<S> {
                                     trade_time: time,
                                     amount: to_fulfill,
                                     price: book_order.0,
+                                    fee: dec!(0),
+                                    fee_asset: None,
                                 }
                             }
                         }
-                        (_, OrderType::Limit) => {
+                        (_, OrderType::Limit | OrderType::LimitMaker) => {
                             match order.side {
                                 OrderSide::Buy => {
                                     if book_order.0 > order.price {
@@ -107,28 +122,33 @@ impl<S: MarketplaceSettingsApi> SimulationMarketplace<S> {
                                 }
                             };
 
+                            let price = if maker { order.price } else { book_order.0 };
                             let to_fulfill = order.amount - order.filled_amount;
                             if to_fulfill <= dec!(0) {
                                 break;
                             }
                             if to_fulfill > book_order.1 {
                                 order.filled_amount += book_order.1;
-                                order.cumulative_quote_amount += book_order.1 * book_order.0;
+                                order.cumulative_quote_amount += book_order.1 * price;
                                 OrderTrade {
                                     id: Uuid::new_v4().to_string(),
                                     trade_time: time,
                                     amount: book_order.1,
-                                    price: book_order.0,
+                                    price,
+                                    fee: dec!(0),
+                                    fee_asset: None,
                                 }
                             } else {
                                 order.filled_amount += to_fulfill;
-                                order.cumulative_quote_amount += to_fulfill * book_order.0;
+                                order.cumulative_quote_amount += to_fulfill * price;
                                 order.status = OrderStatus::Executed;
                                 OrderTrade {
                                     id: Uuid::new_v4().to_string(),
                                     trade_time: time,
                                     amount: to_fulfill,
-                                    price: book_order.0,
+                                    price,
+                                    fee: dec!(0),
+                                    fee_asset: None,
                                 }
                             }
                         }
@@ -137,6 +157,24 @@ impl<S: MarketplaceSettingsApi> SimulationMarketplace<S> {
                         }
                     };
 
+                    let fees = if maker {
+                        self.settings.get_maker_fees().await
+                    } else {
+                        self.settings.get_fees().await
+                    };
+
+                    // fees apply to the received asset
+                    match order.side {
+                        OrderSide::Buy => {
+                            trade.fee = trade.amount * fees;
+                            trade.fee_asset = Some(order.ticker.base.clone());
+                        }
+                        OrderSide::Sell => {
+                            trade.fee = trade.price * trade.amount * fees;
+                            trade.fee_asset = Some(order.ticker.quote.clone());
+                        }
+                    }
+
                     order.trades.push(trade.clone());
 
                     self.notify_order_update(MarketplaceOrderUpdate {
@@ -162,12 +200,10 @@ impl<S: MarketplaceSettingsApi> SimulationMarketplace<S> {
                         trade.amount
                     );
 
-                    let fees = self.settings.get_fees().await;
-
                     match order.side {
                         OrderSide::Sell => {
                             // increase portfolio quote asset
-                            let added_quote_amount = trade.price * trade.amount * (dec!(1) - fees);
+                            let added_quote_amount = trade.price * trade.amount - trade.fee;
                             info!(" ADDED {} {}", added_quote_amount, order.ticker.quote);
                             self.update_asset_amount(
                                 &order.ticker.quote,
@@ -188,7 +224,7 @@ impl<S: MarketplaceSettingsApi> SimulationMarketplace<S> {
                         }
                         OrderSide::Buy => {
                             // increase portfolio base asset
-                            let added_base_amount = trade.amount * (dec!(1) - fees);
+                            let added_base_amount = trade.amount - trade.fee;
                             info!(" ADDED {} {}", added_base_amount, order.ticker.base);
                             self.update_asset_amount(
                                 &order.ticker.base,
@@ -211,6 +247,20 @@ impl<S: MarketplaceSettingsApi> SimulationMarketplace<S> {
                     };
                 }
             }
+
+            match order.status {
+                OrderStatus::Active
+                    if matches!(order.order_type, OrderType::Limit | OrderType::LimitMaker) =>
+                {
+                    resting.insert(order.id.clone());
+                }
+                OrderStatus::Executed => {
+                    resting.remove(&order.id);
+                    // limit buys filled under their price, market buys under the estimated price
+                    self.release_funds(order).await;
+                }
+                _ => {}
+            }
         }
 
         Ok(())
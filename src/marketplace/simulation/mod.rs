@@ -1,15 +1,15 @@
 use crate::marketplace::{Marketplace, MarketplaceBook, MarketplaceSettingsApi};
-use crate::order::Order;
+use crate::order::{Order, OrderSide};
 use crate::portfolio::Asset;
 use crate::ticker::Ticker;
 use anyhow::anyhow;
 use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use tokio::sync::broadcast::Sender;
 use tokio::sync::RwLock;
-use tracing::error;
+use tracing::{error, info};
 
 use super::{MarketplaceEvent, MarketplaceOrderUpdate, MarketplacePortfolioUpdate};
 
@@ -31,6 +31,8 @@ pub struct SimulationMarketplace<S: MarketplaceSettingsApi> {
     assets: Arc<RwLock<HashMap<String, Asset>>>,
     orders: Arc<RwLock<Vec<Order>>>,
     order_book: Arc<RwLock<HashMap<Ticker, MarketplaceBook>>>,
+    // limit orders left in the book after their first matching, filled as maker
+    resting: Arc<RwLock<HashSet<String>>>,
     current_time: Arc<RwLock<u64>>,
     settings: S,
     tx_account: Sender<MarketplaceEvent>,
@@ -45,6 +47,7 @@ impl<S: MarketplaceSettingsApi> SimulationMarketplace<S> {
             assets: Arc::new(Default::default()),
             orders: Arc::new(Default::default()),
             order_book: Arc::new(Default::default()),
+            resting: Arc::new(Default::default()),
             current_time: Arc::new(Default::default()),
             settings,
         }
@@ -95,6 +98,38 @@ impl<S: MarketplaceSettingsApi> SimulationMarketplace<S> {
         }
     }
 
+    // Unlock what a closed order did not use
+    async fn release_funds(&self, order: &Order) {
+        let (asset, unused, price) = match order.side {
+            OrderSide::Buy => (
+                &order.ticker.quote,
+                order.amount * order.price - order.cumulative_quote_amount,
+                dec!(1),
+            ),
+            OrderSide::Sell => (
+                &order.ticker.base,
+                order.amount - order.filled_amount,
+                order.price,
+            ),
+        };
+        if unused <= dec!(0) {
+            return;
+        }
+        info!(" RELEASED {} {}", unused, asset);
+        self.update_asset_locked(asset, -unused, Some(price)).await;
+        self.update_asset_amount(asset, unused, Some(price)).await;
+    }
+
+    // Back to the given assets without any order, when a replay seeks backward
+    pub async fn reset(&self, assets: HashMap<String, Asset>) {
+        self.orders.write().await.clear();
+        self.resting.write().await.clear();
+        self.order_book.write().await.clear();
+        *self.assets.write().await = assets.clone();
+        self.notify_portfolio_update(assets.into_values().collect())
+            .await;
+    }
+
     pub async fn update_asset_amount(
         &self,
         symbol: &str,
@@ -4,7 +4,7 @@ use uuid::Uuid;
 
 use super::SimulationMarketplace;
 use crate::marketplace::{MarketplaceOrderUpdate, MarketplaceSettingsApi};
-use crate::order::OrderSide;
+use crate::order::{OrderSide, OrderType};
 use crate::ticker::Ticker;
 use crate::{
     marketplace::MarketplaceTradeApi,
@@ -29,6 +29,21 @@ impl<S: MarketplaceSettingsApi> MarketplaceTradeApi for SimulationMarketplace<S>
 
         let mut order = order.clone();
 
+        // a limit maker order crossing the book would be filled as taker
+        let crossing = match self.order_book.read().await.get(&order.ticker) {
+            Some(book) => match order.side {
+                OrderSide::Buy => book.sell_price().is_some_and(|ask| order.price >= ask),
+                OrderSide::Sell => book.buy_price().is_some_and(|bid| order.price <= bid),
+            },
+            None => false,
+        };
+        if order.order_type == OrderType::LimitMaker && crossing {
+            info!("Limit maker order {} would immediately match", order.id);
+            order.status = OrderStatus::Rejected;
+            order.reject_reason = Some("Order would immediately match and take.".to_string());
+            return Ok(order);
+        }
+
         let amount_to_reserve = match order.side {
             OrderSide::Buy => order.amount * order.price,
             OrderSide::Sell => order.amount,
@@ -68,4 +83,35 @@ impl<S: MarketplaceSettingsApi> MarketplaceTradeApi for SimulationMarketplace<S>
 
         Ok(order)
     }
+
+    async fn cancel_order(&mut self, order: &Order) -> anyhow::Result<()> {
+        let time = { *self.current_time.read().await };
+
+        let cancelled = {
+            let mut orders = self.orders.write().await;
+            let Some(order) = orders.iter_mut().find(|o| o.id == order.id) else {
+                anyhow::bail!("Unknown order {}", order.id);
+            };
+            if order.status != OrderStatus::Active {
+                anyhow::bail!("Order {} is {}", order.id, order.status);
+            }
+            order.status = OrderStatus::Cancelled;
+            order.clone()
+        };
+        self.resting.write().await.remove(&cancelled.id);
+        self.release_funds(&cancelled).await;
+
+        self.notify_order_update(MarketplaceOrderUpdate {
+            time,
+            update_type: "CANCELED".to_owned(),
+            marketplace_id: cancelled.marketplace_id.clone().unwrap_or_default(),
+            client_id: cancelled.id.clone(),
+            status: cancelled.status.clone(),
+            working_time: cancelled.working_time,
+            trade: None,
+        })
+        .await;
+
+        Ok(())
+    }
 }
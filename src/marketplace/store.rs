@@ -0,0 +1,447 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Result;
+use chrono::Utc;
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::broadcast::{error::RecvError, Sender};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+use crate::marketplace::candle::CandleInterval;
+use crate::marketplace::{MarketplaceCandle, MarketplaceDataApi, MarketplaceEvent};
+use crate::ticker::Ticker;
+use crate::AppEvent;
+
+// Candles returned when no start time is given, like the klines api
+const DEFAULT_LIMIT: u64 = 500;
+
+type Series = BTreeMap<u64, MarketplaceCandle>;
+
+// Closed candles on disk, one file per symbol and interval (store_path/BTCUSDC/1m.jsonl).
+// Files are append only logs, loaded once in memory by start time.
+#[derive(Clone, Debug)]
+pub struct CandleStore {
+    path: PathBuf,
+    series: Arc<RwLock<HashMap<(Ticker, CandleInterval), Series>>>,
+}
+
+impl CandleStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            series: Arc::default(),
+        }
+    }
+
+    fn file(&self, ticker: &Ticker, interval: CandleInterval) -> PathBuf {
+        candle_file(&self.path, ticker, interval)
+    }
+
+    async fn load(&self, ticker: &Ticker, interval: CandleInterval) -> Result<()> {
+        if self
+            .series
+            .read()
+            .await
+            .contains_key(&(ticker.clone(), interval))
+        {
+            return Ok(());
+        }
+
+        let path = self.file(ticker, interval);
+        let mut series = Series::new();
+        let mut lines = 0;
+        if let Ok(content) = fs::read_to_string(&path).await {
+            for line in content.lines() {
+                lines += 1;
+                if let Ok(candle) = serde_json::from_str::<MarketplaceCandle>(line) {
+                    series.insert(candle.start_time, candle);
+                }
+            }
+        }
+        // rewrite the log once it holds replaced or broken lines
+        if lines > series.len() {
+            let mut content = String::new();
+            for candle in series.values() {
+                content.push_str(&serde_json::to_string(candle)?);
+                content.push('\n');
+            }
+            let tmp = path.with_extension("jsonl.tmp");
+            fs::write(&tmp, content).await?;
+            fs::rename(&tmp, &path).await?;
+        }
+
+        self.series
+            .write()
+            .await
+            .entry((ticker.clone(), interval))
+            .or_insert(series);
+        Ok(())
+    }
+
+    // Store the closed candles not already known.
+    pub async fn add_candles(&self, candles: &[MarketplaceCandle]) -> Result<()> {
+        let mut by_series: HashMap<(Ticker, CandleInterval), Vec<&MarketplaceCandle>> =
+            HashMap::new();
+        for candle in candles.iter().filter(|candle| candle.closed) {
+            by_series
+                .entry((candle.ticker.clone(), candle.interval))
+                .or_default()
+                .push(candle);
+        }
+
+        for ((ticker, interval), candles) in by_series {
+            self.load(&ticker, interval).await?;
+            let mut content = String::new();
+            {
+                let mut series = self.series.write().await;
+                let series = series.entry((ticker.clone(), interval)).or_default();
+                for candle in candles {
+                    if series
+                        .get(&candle.start_time)
+                        .is_some_and(|known| known.close_time == candle.close_time)
+                    {
+                        continue;
+                    }
+                    series.insert(candle.start_time, candle.clone());
+                    content.push_str(&serde_json::to_string(candle)?);
+                    content.push('\n');
+                }
+            }
+            if content.is_empty() {
+                continue;
+            }
+
+            let path = self.file(&ticker, interval);
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir).await?;
+            }
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .await?;
+            file.write_all(content.as_bytes()).await?;
+        }
+        Ok(())
+    }
+
+    // Candles of the range, in time order, with the same bounds as the klines api :
+    // open time between from and to, the last 500 candles until `to` (or now) without `from`.
+    // Missing candles are fetched from the fallback and stored, the stored candles
+    // are still returned when the fallback is not reachable.
+    pub async fn get_candles<F: MarketplaceDataApi>(
+        &self,
+        fallback: &F,
+        ticker: &Ticker,
+        interval: CandleInterval,
+        from: Option<u64>,
+        to: Option<u64>,
+    ) -> Result<Vec<MarketplaceCandle>> {
+        let now = Utc::now().timestamp_millis() as u64;
+        let duration = interval.duration_ms();
+        let Some((first, last)) = bounds(interval, from, to) else {
+            return Ok(Vec::new());
+        };
+
+        self.load(ticker, interval).await?;
+        let missing = {
+            let series = self.series.read().await;
+            let series = series.get(&(ticker.clone(), interval));
+            missing_ranges(series, first, last, duration)
+        };
+
+        let mut fetched = Vec::new();
+        let mut error = None;
+        for (start, end) in missing {
+            match fetch_range(fallback, ticker, interval, start, end).await {
+                Ok(mut candles) => fetched.append(&mut candles),
+                Err(err) => {
+                    warn!(
+                        "Could not fetch {} {} candles from {} to {} : {}",
+                        ticker, interval, start, end, err
+                    );
+                    error = Some(err);
+                }
+            }
+        }
+
+        // the candle in progress is returned but not stored
+        for candle in fetched.iter_mut() {
+            candle.closed = candle.close_time < now;
+        }
+        self.add_candles(&fetched).await?;
+
+        let mut candles = self.stored(ticker, interval, first, last).await;
+        if let Some(open) = fetched.into_iter().find(|candle| !candle.closed) {
+            if open.start_time >= first && open.start_time <= last {
+                candles.push(open);
+            }
+        }
+
+        match error {
+            Some(err) if candles.is_empty() => Err(err),
+            _ => Ok(candles),
+        }
+    }
+
+    // Stored candles of the range, with the bounds of get_candles, the missing ones are not fetched.
+    pub async fn get_stored_candles(
+        &self,
+        ticker: &Ticker,
+        interval: CandleInterval,
+        from: Option<u64>,
+        to: Option<u64>,
+    ) -> Result<Vec<MarketplaceCandle>> {
+        let Some((first, last)) = bounds(interval, from, to) else {
+            return Ok(Vec::new());
+        };
+        self.load(ticker, interval).await?;
+        Ok(self.stored(ticker, interval, first, last).await)
+    }
+
+    async fn stored(
+        &self,
+        ticker: &Ticker,
+        interval: CandleInterval,
+        first: u64,
+        last: u64,
+    ) -> Vec<MarketplaceCandle> {
+        let series = self.series.read().await;
+        series
+            .get(&(ticker.clone(), interval))
+            .map(|series| series.range(first..=last).map(|(_, c)| c.clone()).collect())
+            .unwrap_or_default()
+    }
+}
+
+// File of a series in a store directory
+pub(crate) fn candle_file(path: &Path, ticker: &Ticker, interval: CandleInterval) -> PathBuf {
+    path.join(ticker.to_string())
+        .join(format!("{}.jsonl", interval))
+}
+
+// First and last start times of a range, None when it is empty
+fn bounds(interval: CandleInterval, from: Option<u64>, to: Option<u64>) -> Option<(u64, u64)> {
+    let now = Utc::now().timestamp_millis() as u64;
+    let duration = interval.duration_ms();
+    let last = interval.start_of(to.unwrap_or(now).min(now));
+    let first = match from {
+        Some(from) => interval.start_of(from + duration - 1),
+        None => last.saturating_sub((DEFAULT_LIMIT - 1) * duration),
+    };
+    (first <= last).then_some((first, last))
+}
+
+// Runs of start times without a candle
+fn missing_ranges(
+    series: Option<&Series>,
+    first: u64,
+    last: u64,
+    duration: u64,
+) -> Vec<(u64, u64)> {
+    let mut ranges = Vec::new();
+    let mut start = None;
+    let mut time = first;
+    while time <= last {
+        let known = series.is_some_and(|series| series.contains_key(&time));
+        match (known, start) {
+            (false, None) => start = Some(time),
+            (true, Some(from)) => {
+                ranges.push((from, time - duration));
+                start = None;
+            }
+            _ => {}
+        }
+        time += duration;
+    }
+    if let Some(from) = start {
+        ranges.push((from, last));
+    }
+    ranges
+}
+
+// Page through a range, the klines api returns 500 candles per call.
+pub async fn fetch_range<F: MarketplaceDataApi>(
+    fallback: &F,
+    ticker: &Ticker,
+    interval: CandleInterval,
+    from: u64,
+    to: u64,
+) -> Result<Vec<MarketplaceCandle>> {
+    let mut candles = Vec::new();
+    let mut start = from;
+    while start <= to {
+        let page = fallback
+            .get_candles(ticker, interval, Some(start), Some(to))
+            .await?;
+        let Some(last) = page.last() else {
+            break;
+        };
+        start = last.start_time + interval.duration_ms();
+        candles.extend(page);
+    }
+    Ok(candles)
+}
+
+// Append the closed 1m candles of the app bus. The aggregated intervals are left out, a stored
+// candle is never replaced and one aggregated from a partial stream would hide the klines.
+pub async fn start_candle_store(store: CandleStore, tx_app: Sender<AppEvent>) {
+    info!("Storing candles in {}", store.path.display());
+    let mut rx = tx_app.subscribe();
+    loop {
+        match rx.recv().await {
+            Ok(AppEvent::MarketPlace(MarketplaceEvent::Candle(candle)))
+                if candle.closed && candle.interval == CandleInterval::M1 =>
+            {
+                if let Err(err) = store.add_candles(&[candle]).await {
+                    warn!("Could not store candle : {err}");
+                }
+            }
+            Ok(_) => {}
+            Err(RecvError::Lagged(n)) => warn!("Candle store lagged, {} events lost", n),
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // 1m candles of a day, 3 per call
+    struct Klines {
+        calls: AtomicUsize,
+    }
+
+    impl MarketplaceDataApi for Klines {
+        async fn get_candles(
+            &self,
+            ticker: &Ticker,
+            interval: CandleInterval,
+            from: Option<u64>,
+            to: Option<u64>,
+        ) -> Result<Vec<MarketplaceCandle>> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            let from = from.unwrap();
+            Ok((from..=to.unwrap().min(86_400_000 - 60_000))
+                .step_by(60_000)
+                .take(3)
+                .map(|start_time| MarketplaceCandle {
+                    ticker: ticker.clone(),
+                    interval,
+                    open_price: dec!(1),
+                    close_price: dec!(1),
+                    high_price: dec!(1),
+                    low_price: dec!(1),
+                    trade_count: 1,
+                    start_time,
+                    close_time: start_time + 59_999,
+                    volume: dec!(1),
+                    closed: true,
+                })
+                .collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_candle_store() {
+        let path = std::env::temp_dir().join(format!("store-{}", uuid::Uuid::new_v4()));
+        let ticker = Ticker::new("BTC", "USDC");
+        let klines = Klines {
+            calls: AtomicUsize::new(0),
+        };
+
+        let store = CandleStore::new(path.clone());
+        let candles = store
+            .get_candles(
+                &klines,
+                &ticker,
+                CandleInterval::M1,
+                Some(60_000),
+                Some(600_000),
+            )
+            .await
+            .unwrap();
+        assert_eq!(candles.len(), 10);
+        assert_eq!(klines.calls.load(Ordering::SeqCst), 4);
+
+        // a wider range only fetches the missing candles
+        let candles = store
+            .get_candles(&klines, &ticker, CandleInterval::M1, Some(0), Some(720_000))
+            .await
+            .unwrap();
+        assert_eq!(candles.len(), 13);
+        assert_eq!(candles[0].start_time, 0);
+        assert_eq!(klines.calls.load(Ordering::SeqCst), 6);
+
+        // served from disk
+        let store = CandleStore::new(path.clone());
+        let candles = store
+            .get_candles(&klines, &ticker, CandleInterval::M1, None, Some(720_000))
+            .await
+            .unwrap();
+        assert_eq!(candles.len(), 13);
+        assert_eq!(klines.calls.load(Ordering::SeqCst), 6);
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+
+    #[tokio::test]
+    async fn test_live_candles() {
+        let path = std::env::temp_dir().join(format!("store-{}", uuid::Uuid::new_v4()));
+        let ticker = Ticker::new("BTC", "USDC");
+        let klines = Klines {
+            calls: AtomicUsize::new(0),
+        };
+        let store = CandleStore::new(path.clone());
+        let (tx_app, _) = tokio::sync::broadcast::channel(16);
+        let task = tokio::spawn(start_candle_store(store.clone(), tx_app.clone()));
+        tokio::task::yield_now().await;
+
+        let candle = |interval: CandleInterval, start_time, price| MarketplaceCandle {
+            ticker: ticker.clone(),
+            interval,
+            open_price: price,
+            close_price: price,
+            high_price: price,
+            low_price: price,
+            trade_count: 1,
+            start_time,
+            close_time: start_time + interval.duration_ms() - 1,
+            volume: dec!(1),
+            closed: true,
+        };
+        for candle in [
+            candle(CandleInterval::M1, 0, dec!(2)),
+            // aggregated from a stream started mid-interval
+            candle(CandleInterval::H4, 0, dec!(2)),
+        ] {
+            tx_app
+                .send(AppEvent::MarketPlace(MarketplaceEvent::Candle(candle)))
+                .unwrap();
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        task.abort();
+
+        let candles = store
+            .get_candles(&klines, &ticker, CandleInterval::M1, Some(0), Some(0))
+            .await
+            .unwrap();
+        assert_eq!(candles[0].close_price, dec!(2));
+        assert_eq!(klines.calls.load(Ordering::SeqCst), 0);
+
+        // the 4h candles are left to the klines
+        let candles = store
+            .get_stored_candles(&ticker, CandleInterval::H4, Some(0), Some(0))
+            .await
+            .unwrap();
+        assert!(candles.is_empty());
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
@@ -0,0 +1,559 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use anyhow::Result;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rust_decimal::prelude::FromPrimitive;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::marketplace::candle::{CandleAggregator, CandleInterval, CandleSource};
+use crate::marketplace::replay::CANDLES_DIR;
+use crate::marketplace::store::candle_file;
+use crate::marketplace::{MarketplaceBook, MarketplaceCandle, MarketplaceEvent, MarketplaceTrade};
+use crate::order::OrderSide;
+use crate::ticker::Ticker;
+
+const DAY: f64 = 86_400_000.0;
+const HOUR: f64 = 3_600_000.0;
+
+// Price process of the mid price. Drifts and volatilities are per day, on the log price.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PriceProcess {
+    // geometric Brownian motion
+    Gbm {
+        drift: f64,
+        volatility: f64,
+    },
+    // Ornstein-Uhlenbeck around `mean`, `reversion` is the pull strength per day
+    MeanReverting {
+        mean: f64,
+        reversion: f64,
+        volatility: f64,
+    },
+    // geometric Brownian motion whose parameters switch between regimes
+    Regimes {
+        regimes: Vec<Regime>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Regime {
+    pub name: String,
+    pub drift: f64,
+    pub volatility: f64,
+    // mean time spent in the regime, in hours
+    pub duration: f64,
+}
+
+impl Regime {
+    // Range most of the time, trends and a rare crash
+    pub fn presets() -> Vec<Regime> {
+        let regime = |name: &str, drift, volatility, duration| Regime {
+            name: name.to_string(),
+            drift,
+            volatility,
+            duration,
+        };
+        vec![
+            regime("range", 0.0, 0.02, 12.0),
+            regime("up", 0.1, 0.03, 6.0),
+            regime("down", -0.1, 0.03, 6.0),
+            regime("crash", -3.0, 0.15, 0.5),
+        ]
+    }
+}
+
+// Sudden moves (flash crashes when negative), optionally recovered.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Jumps {
+    // jumps per day
+    pub rate: f64,
+    // mean log size of a jump, -0.1 drops the price by about 10%
+    pub size: f64,
+    pub size_volatility: f64,
+    // time for the jump to be mostly recovered (e-folding) in ms, 0 keeps the new price
+    pub recovery: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Scenario {
+    pub tickers: Vec<Ticker>,
+    pub from: u64,
+    pub to: u64,
+    // time between two books, in ms
+    pub step: u64,
+    // history of 1m candles before `from` (and their higher intervals), in ms
+    pub warmup: u64,
+    pub start_price: f64,
+    pub process: PriceProcess,
+    pub jumps: Option<Jumps>,
+    // relative spread between the best bid and ask, also the gap between two levels
+    pub spread: f64,
+    // levels on each side of the book
+    pub depth: usize,
+    // mean quantity of a level, in base asset
+    pub level_quantity: f64,
+    // mean trades between two books
+    pub trades_per_step: f64,
+    pub price_decimals: u32,
+    pub seed: u64,
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Self {
+            tickers: vec![Ticker::new("BTC", "USDC")],
+            from: 1735689600000,
+            to: 1735689600000 + DAY as u64,
+            step: 1000,
+            warmup: 2 * DAY as u64,
+            start_price: 100_000.0,
+            process: PriceProcess::Gbm {
+                drift: 0.0,
+                volatility: 0.03,
+            },
+            jumps: None,
+            spread: 0.0001,
+            depth: 5,
+            level_quantity: 0.5,
+            trades_per_step: 2.0,
+            price_decimals: 2,
+            seed: 0,
+        }
+    }
+}
+
+// Standard normal sample (Box-Muller)
+fn normal(rng: &mut StdRng) -> f64 {
+    let u1: f64 = rng.random::<f64>().max(f64::MIN_POSITIVE);
+    let u2: f64 = rng.random();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+fn poisson(rng: &mut StdRng, mean: f64) -> u64 {
+    let limit = (-mean).exp();
+    let mut count = 0;
+    let mut product: f64 = rng.random();
+    while product > limit {
+        count += 1;
+        product *= rng.random::<f64>();
+    }
+    count
+}
+
+fn to_decimal(value: f64, decimals: u32) -> Decimal {
+    Decimal::from_f64(value)
+        .unwrap_or_default()
+        .round_dp(decimals)
+}
+
+// Price path of one ticker
+struct Market {
+    ticker: Ticker,
+    log_price: f64,
+    // log offset of the jumps being recovered
+    jump_offset: f64,
+    regime: usize,
+    trade_id: u64,
+    update_id: u64,
+}
+
+impl Market {
+    fn new(ticker: Ticker, price: f64) -> Self {
+        Self {
+            ticker,
+            log_price: price.ln(),
+            jump_offset: 0.0,
+            regime: 0,
+            trade_id: 0,
+            update_id: 0,
+        }
+    }
+
+    fn mid_price(&self) -> f64 {
+        (self.log_price + self.jump_offset).exp()
+    }
+
+    fn step(&mut self, scenario: &Scenario, rng: &mut StdRng) {
+        let dt = scenario.step as f64 / DAY;
+        let increment = match &scenario.process {
+            PriceProcess::Gbm { drift, volatility } => {
+                (drift - volatility * volatility / 2.0) * dt + volatility * dt.sqrt() * normal(rng)
+            }
+            PriceProcess::MeanReverting {
+                mean,
+                reversion,
+                volatility,
+            } => {
+                reversion * (mean.ln() - self.log_price) * dt + volatility * dt.sqrt() * normal(rng)
+            }
+            PriceProcess::Regimes { regimes } if !regimes.is_empty() => {
+                let regime = &regimes[self.regime.min(regimes.len() - 1)];
+                let switch = 1.0 - (-(scenario.step as f64 / HOUR) / regime.duration).exp();
+                let increment = (regime.drift - regime.volatility * regime.volatility / 2.0) * dt
+                    + regime.volatility * dt.sqrt() * normal(rng);
+                if regimes.len() > 1 && rng.random_bool(switch.clamp(0.0, 1.0)) {
+                    let next = rng.random_range(0..regimes.len() - 1);
+                    self.regime = if next >= self.regime { next + 1 } else { next };
+                    debug!(
+                        "{} enters regime {}",
+                        self.ticker, regimes[self.regime].name
+                    );
+                }
+                increment
+            }
+            PriceProcess::Regimes { .. } => 0.0,
+        };
+        self.log_price += increment;
+
+        if let Some(jumps) = &scenario.jumps {
+            if jumps.recovery > 0 {
+                self.jump_offset *= (-(scenario.step as f64) / jumps.recovery as f64).exp();
+            }
+            let probability = 1.0 - (-jumps.rate * dt).exp();
+            if rng.random_bool(probability.clamp(0.0, 1.0)) {
+                let size = jumps.size + jumps.size_volatility * normal(rng);
+                if jumps.recovery > 0 {
+                    self.jump_offset += size;
+                } else {
+                    self.log_price += size;
+                }
+            }
+        }
+    }
+
+    fn book(&mut self, scenario: &Scenario, rng: &mut StdRng, time: u64) -> MarketplaceBook {
+        let mid = self.mid_price();
+        let tick = Decimal::new(1, scenario.price_decimals);
+        let best_bid = mid * (1.0 - scenario.spread / 2.0);
+        let best_ask = mid * (1.0 + scenario.spread / 2.0);
+        let mut quantity = || to_decimal(scenario.level_quantity * (0.5 + rng.random::<f64>()), 4);
+
+        let mut bids = Vec::with_capacity(scenario.depth);
+        let mut asks = Vec::with_capacity(scenario.depth);
+        for level in 0..scenario.depth {
+            let gap = scenario.spread * level as f64;
+            let bid = to_decimal(best_bid * (1.0 - gap), scenario.price_decimals);
+            let ask = to_decimal(best_ask * (1.0 + gap), scenario.price_decimals);
+            // rounding must not cross the book
+            let ask = ask.max(bid + tick);
+            bids.push((bid, quantity()));
+            asks.push((ask, quantity()));
+        }
+
+        self.update_id += 1;
+        MarketplaceBook {
+            ticker: self.ticker.clone(),
+            first_update_id: self.update_id,
+            final_update_id: self.update_id,
+            time,
+            bids,
+            asks,
+        }
+    }
+
+    // Trades hitting the book, buyers are more aggressive when the price goes up.
+    fn trades(
+        &mut self,
+        scenario: &Scenario,
+        rng: &mut StdRng,
+        book: &MarketplaceBook,
+        rising: bool,
+    ) -> Vec<MarketplaceTrade> {
+        let count = poisson(rng, scenario.trades_per_step);
+        let mut times: Vec<u64> = (0..count)
+            .map(|_| book.time - rng.random_range(0..scenario.step))
+            .collect();
+        times.sort();
+
+        times
+            .into_iter()
+            .map(|trade_time| {
+                let side = if rng.random_bool(if rising { 0.7 } else { 0.3 }) {
+                    OrderSide::Buy
+                } else {
+                    OrderSide::Sell
+                };
+                let price = match side {
+                    OrderSide::Buy => book.asks[0].0,
+                    OrderSide::Sell => book.bids[0].0,
+                };
+                let quantity = -(1.0 - rng.random::<f64>()).ln() * scenario.level_quantity / 10.0;
+                self.trade_id += 1;
+                MarketplaceTrade {
+                    trade_id: self.trade_id,
+                    trade_time,
+                    ticker: self.ticker.clone(),
+                    price,
+                    quantity: to_decimal(quantity, 5).max(Decimal::new(1, 5)),
+                    aggressor_side: Some(side),
+                }
+            })
+            .collect()
+    }
+}
+
+// Candles of the warm-up in a candle store, the replay serves them as the history before `from`.
+// The path is walked by minute and scaled to end at the start price.
+fn write_warmup(scenario: &Scenario, store_path: &Path) -> Result<()> {
+    let minutes = scenario.warmup / 60_000;
+    if minutes == 0 {
+        return Ok(());
+    }
+    let start = CandleInterval::M1.start_of(scenario.from) - minutes * 60_000;
+    let by_minute = Scenario {
+        step: 60_000,
+        ..scenario.clone()
+    };
+    // apart from the events, so the warm-up does not change them
+    let mut rng = StdRng::seed_from_u64(!scenario.seed);
+
+    for ticker in scenario.tickers.iter() {
+        let mut market = Market::new(ticker.clone(), scenario.start_price);
+        let mut prices = vec![market.mid_price()];
+        for _ in 0..minutes {
+            market.step(&by_minute, &mut rng);
+            prices.push(market.mid_price());
+        }
+        let scale = scenario.start_price / prices[prices.len() - 1];
+
+        let mut aggregator = CandleAggregator::new(CandleSource::Candles, &CandleInterval::ALL)
+            .with_complete_since(start);
+        let mut candles = Vec::new();
+        for (minute, prices) in prices.windows(2).enumerate() {
+            let start_time = start + minute as u64 * 60_000;
+            let open = to_decimal(prices[0] * scale, scenario.price_decimals);
+            let close = to_decimal(prices[1] * scale, scenario.price_decimals);
+            let trades = poisson(
+                &mut rng,
+                scenario.trades_per_step * 60_000.0 / scenario.step as f64,
+            );
+            let candle = MarketplaceCandle {
+                ticker: ticker.clone(),
+                interval: CandleInterval::M1,
+                open_price: open,
+                close_price: close,
+                high_price: open.max(close),
+                low_price: open.min(close),
+                trade_count: trades,
+                start_time,
+                close_time: start_time + 59_999,
+                volume: to_decimal(trades as f64 * scenario.level_quantity / 10.0, 5),
+                closed: true,
+            };
+            candles.extend(aggregator.add_candle(&candle));
+            candles.push(candle);
+        }
+
+        for interval in CandleInterval::ALL {
+            let path = candle_file(store_path, ticker, interval);
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let mut writer = BufWriter::new(File::create(path)?);
+            for candle in candles
+                .iter()
+                .filter(|candle| candle.interval == interval && candle.closed)
+            {
+                serde_json::to_writer(&mut writer, candle)?;
+                writer.write_all(b"\n")?;
+            }
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
+
+// Write a replay dataset (data_path/events.jsonl) of trades, 1m candles and books
+// following the scenario, with the warm-up candles in data_path/candles.
+// The same seed always gives the same events.
+pub fn generate(scenario: &Scenario, data_path: &Path) -> Result<usize> {
+    anyhow::ensure!(scenario.step > 0, "The step must be positive");
+    anyhow::ensure!(scenario.depth > 0, "The book needs at least one level");
+
+    let mut rng = StdRng::seed_from_u64(scenario.seed);
+    let mut markets: Vec<Market> = scenario
+        .tickers
+        .iter()
+        .map(|ticker| Market::new(ticker.clone(), scenario.start_price))
+        .collect();
+    let mut aggregator = CandleAggregator::new(CandleSource::Trades, &[CandleInterval::M1])
+        .with_complete_since(scenario.from);
+
+    fs::create_dir_all(data_path)?;
+    write_warmup(scenario, &data_path.join(CANDLES_DIR))?;
+    let mut writer = BufWriter::new(File::create(data_path.join("events.jsonl"))?);
+    let mut count = 0;
+
+    let mut time = scenario.from + scenario.step;
+    while time <= scenario.to {
+        let mut events = Vec::new();
+        for market in markets.iter_mut() {
+            let previous = market.mid_price();
+            market.step(scenario, &mut rng);
+            let book = market.book(scenario, &mut rng, time);
+            let rising = market.mid_price() >= previous;
+            for trade in market.trades(scenario, &mut rng, &book, rising) {
+                events.extend(
+                    aggregator
+                        .add_trade(&trade)
+                        .into_iter()
+                        .map(MarketplaceEvent::Candle),
+                );
+                events.push(MarketplaceEvent::Trade(trade));
+            }
+            events.push(MarketplaceEvent::Book(book));
+        }
+        events.extend(
+            aggregator
+                .flush(time)
+                .into_iter()
+                .map(MarketplaceEvent::Candle),
+        );
+
+        // stable: the book of a ticker stays after its trades
+        events.sort_by_key(|event| event.get_time());
+        for event in events {
+            serde_json::to_writer(&mut writer, &event)?;
+            writer.write_all(b"\n")?;
+            count += 1;
+        }
+        time += scenario.step;
+    }
+    writer.flush()?;
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::marketplace::replay::ReplayMarketplace;
+    use crate::marketplace::MarketplaceDataApi;
+    use rust_decimal_macros::dec;
+
+    fn read_events(data_path: &Path) -> Vec<MarketplaceEvent> {
+        fs::read_to_string(data_path.join("events.jsonl"))
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_generate() {
+        let dir = std::env::temp_dir().join(format!("synthetic-{}", uuid::Uuid::new_v4()));
+        let scenario = Scenario {
+            to: Scenario::default().from + 10 * 60_000,
+            process: PriceProcess::Regimes {
+                regimes: Regime::presets(),
+            },
+            jumps: Some(Jumps {
+                rate: 500.0,
+                size: -0.05,
+                size_volatility: 0.01,
+                recovery: 0,
+            }),
+            seed: 42,
+            ..Default::default()
+        };
+
+        generate(&scenario, &dir.join("a")).unwrap();
+        generate(&scenario, &dir.join("b")).unwrap();
+        let events = read_events(&dir.join("a"));
+        assert_eq!(
+            fs::read_to_string(dir.join("a/events.jsonl")).unwrap(),
+            fs::read_to_string(dir.join("b/events.jsonl")).unwrap()
+        );
+        assert!(events
+            .windows(2)
+            .all(|events| events[0].get_time() <= events[1].get_time()));
+
+        let books: Vec<&MarketplaceBook> = events
+            .iter()
+            .filter_map(|event| match event {
+                MarketplaceEvent::Book(book) => Some(book),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(books.len(), 600);
+        assert!(books.iter().all(|book| book.bids[0].0 < book.asks[0].0));
+        // about 3 crashes of 5% in 10 minutes
+        assert!(books.last().unwrap().bids[0].0 < books[0].bids[0].0);
+
+        let candles = events
+            .iter()
+            .filter(|event| matches!(event, MarketplaceEvent::Candle(..)))
+            .count();
+        assert_eq!(candles, 10);
+
+        generate(
+            &Scenario {
+                seed: 43,
+                ..scenario
+            },
+            &dir.join("c"),
+        )
+        .unwrap();
+        assert_ne!(
+            fs::read_to_string(dir.join("a/events.jsonl")).unwrap(),
+            fs::read_to_string(dir.join("c/events.jsonl")).unwrap()
+        );
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    struct Offline;
+
+    impl MarketplaceDataApi for Offline {
+        async fn get_candles(
+            &self,
+            _ticker: &Ticker,
+            _interval: CandleInterval,
+            _from: Option<u64>,
+            _to: Option<u64>,
+        ) -> Result<Vec<MarketplaceCandle>> {
+            anyhow::bail!("offline")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replay_warmup() {
+        let dir = std::env::temp_dir().join(format!("synthetic-{}", uuid::Uuid::new_v4()));
+        let scenario = Scenario {
+            to: Scenario::default().from + 10 * 60_000,
+            ..Default::default()
+        };
+        generate(&scenario, &dir).unwrap();
+
+        // the strategies load their history until the replay start, without the network
+        let replay = ReplayMarketplace::new(vec![dir.clone()], Offline, 0);
+        let ticker = Ticker::new("BTC", "USDC");
+        let candles = replay
+            .get_candles(&ticker, CandleInterval::M1, None, Some(scenario.from))
+            .await
+            .unwrap();
+        assert_eq!(candles.len(), 499);
+        assert!(candles
+            .windows(2)
+            .all(|candles| candles[0].close_time + 1 == candles[1].start_time));
+        let last = candles.last().unwrap();
+        assert_eq!(last.close_time + 1, scenario.from);
+        assert_eq!(last.close_price, dec!(100000));
+
+        let days = replay
+            .get_candles(&ticker, CandleInterval::D1, None, Some(scenario.from))
+            .await
+            .unwrap();
+        assert_eq!(days.len(), 2);
+        assert_eq!(days[1].close_price, dec!(100000));
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
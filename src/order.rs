@@ -138,12 +138,38 @@ impl TryFrom<String> for OrderStatus {
     }
 }
 
+// Why a SELL order closed its position
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Copy, strum_macros::Display)]
+pub enum ExitReason {
+    #[strum(serialize = "profit")]
+    TakeProfit,
+    #[strum(serialize = "stop loss")]
+    StopLoss,
+    #[strum(serialize = "atr stop")]
+    AtrStop,
+    #[strum(serialize = "trailing stop")]
+    TrailingStop,
+    #[strum(serialize = "max hold")]
+    MaxHold,
+}
+
+impl ExitReason {
+    pub fn is_stop(&self) -> bool {
+        !matches!(self, ExitReason::TakeProfit)
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct OrderTrade {
     pub id: String,
     pub trade_time: u64,
     pub amount: Decimal,
     pub price: Decimal,
+    // commission actually charged by the marketplace for this trade
+    #[serde(default)]
+    pub fee: Decimal,
+    #[serde(default)]
+    pub fee_asset: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -162,7 +188,7 @@ pub struct Order {
     pub amount: Decimal,
     pub quote_amount: Decimal, // for MARKET BUY orders
 
-    pub fees: Decimal,
+    pub fees: Decimal, // taker fee ratio when placed, for trades without commissions
     pub price: Decimal, // for LIMIT orders
 
     pub filled_amount: Decimal,
@@ -182,6 +208,9 @@ pub struct Order {
     pub profit: Decimal,
     
     pub reject_reason: Option<String>,
+
+    #[serde(default)]
+    pub exit_reason: Option<ExitReason>,
 }
 
 impl Order {
@@ -227,7 +256,7 @@ impl Order {
             marketplace_id: None,
             filled_amount: dec!(0),
             buy_order_price: None,
-            sell_order_price: sell_order.map(|sell_order| sell_order.get_trade_total_price()),
+            sell_order_price: sell_order.map(|sell_order| sell_order.get_net_quote_amount()),
             trades: Vec::new(),
             session_id: sell_order
                 .map(|sell_order| sell_order.session_id.clone())
@@ -235,6 +264,7 @@ impl Order {
             next_order_id: None,
             prev_order_id: sell_order.map(|sell_order| sell_order.id.clone()),
             reject_reason: None,
+            exit_reason: None,
             strategy: sell_order.and_then(|sell_order| sell_order.strategy.clone())
         }
     }
@@ -262,14 +292,15 @@ impl Order {
             price,
             marketplace_id: None,
             filled_amount: dec!(0),
-            buy_order_price: buy_order.map(|buy_order| buy_order.get_trade_total_price()),
+            buy_order_price: buy_order.map(|buy_order| buy_order.get_net_quote_amount()),
             sell_order_price: None,
             trades: Vec::new(),
             session_id: buy_order.and_then(|buy_order| buy_order.session_id.clone()),
             strategy: buy_order.and_then(|buy_order| buy_order.strategy.clone()),
             next_order_id: None,
             prev_order_id: buy_order.map(|buy_order| buy_order.id.clone()),
-            reject_reason: None
+            reject_reason: None,
+            exit_reason: None,
         }
     }
 
@@ -287,6 +318,15 @@ impl Order {
         }
     }
 
+    // Executed, or stopped after a partial fill
+    pub fn has_traded(&self) -> bool {
+        match self.status {
+            OrderStatus::Executed => true,
+            OrderStatus::Cancelled | OrderStatus::Expired => self.filled_amount > dec!(0),
+            _ => false,
+        }
+    }
+
     pub fn get_last_trade_time(&self) -> Option<u64> {
         self.trades.iter().map(|trade| trade.trade_time).max()
     }
@@ -297,4 +337,63 @@ impl Order {
             .map(|trade| trade.amount * trade.price)
             .sum::<Decimal>()
     }
+
+    fn get_fees_in(&self, asset: &str) -> Decimal {
+        self.trades
+            .iter()
+            .filter(|trade| trade.fee_asset.as_deref() == Some(asset))
+            .map(|trade| trade.fee)
+            .sum::<Decimal>()
+    }
+
+    // Commission paid in the base asset (usually on BUY orders).
+    pub fn get_base_fees(&self) -> Decimal {
+        self.get_fees_in(&self.ticker.base)
+    }
+
+    // Commission paid in the quote asset (usually on SELL orders).
+    pub fn get_quote_fees(&self) -> Decimal {
+        self.get_fees_in(&self.ticker.quote)
+    }
+
+    // Quote amount spent for a BUY or received for a SELL, quote commissions included.
+    // Commissions paid in the base asset show up in the amount available for the next order.
+    pub fn get_net_quote_amount(&self) -> Decimal {
+        match self.side {
+            OrderSide::Buy => self.get_trade_total_price() + self.get_quote_fees(),
+            OrderSide::Sell => self.get_trade_total_price() - self.get_quote_fees(),
+        }
+    }
+
+    // Base amount received by a BUY order.
+    // Falls back to the fees ratio when the marketplace did not report commissions.
+    pub fn get_net_base_amount(&self, fees: Decimal) -> Decimal {
+        if self.trades.iter().any(|trade| trade.fee_asset.is_some()) {
+            self.filled_amount - self.get_base_fees()
+        } else {
+            self.filled_amount * (dec!(1) - fees)
+        }
+    }
+}
+
+// Base amount still held by executed orders (of a session) and its average buy price,
+// commissions included. None once everything is sold.
+pub fn get_cost_basis<'a>(
+    orders: impl IntoIterator<Item = &'a Order>,
+    fees: Decimal,
+) -> Option<(Decimal, Decimal)> {
+    let (mut bought, mut spent, mut sold) = (dec!(0), dec!(0), dec!(0));
+    for order in orders.into_iter().filter(|order| order.has_traded()) {
+        match order.side {
+            OrderSide::Buy => {
+                bought += order.get_net_base_amount(fees);
+                spent += order.get_net_quote_amount();
+            }
+            OrderSide::Sell => sold += order.filled_amount,
+        }
+    }
+    if bought <= dec!(0) || bought <= sold {
+        return None;
+    }
+    Some((bought - sold, spent / bought))
 }
@@ -130,12 +130,7 @@ impl Portfolio {
     }
 
     pub fn update_value(&mut self) {
-        self.value = Some(
-            self.assets
-                .iter()
-                .flat_map(|(_, asset)| asset.value)
-                .sum(),
-        );
+        self.value = Some(self.assets.iter().flat_map(|(_, asset)| asset.value).sum());
     }
 }
 
@@ -0,0 +1,428 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::broadcast::{error::RecvError, Sender};
+use tracing::{error, info, warn};
+
+use crate::marketplace::candle::CandleInterval;
+use crate::marketplace::MarketplaceEvent;
+use crate::ticker::Ticker;
+use crate::AppEvent;
+
+pub const INDEX_FILE: &str = "index.json";
+
+// Recorded event types
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RecordedEvent {
+    Trade,
+    AggTrade,
+    Candle,
+    Book,
+    BookTicker,
+    Order,
+    Portfolio,
+    Balance,
+    Stream,
+}
+
+impl RecordedEvent {
+    pub const ALL: [RecordedEvent; 9] = [
+        RecordedEvent::Trade,
+        RecordedEvent::AggTrade,
+        RecordedEvent::Candle,
+        RecordedEvent::Book,
+        RecordedEvent::BookTicker,
+        RecordedEvent::Order,
+        RecordedEvent::Portfolio,
+        RecordedEvent::Balance,
+        RecordedEvent::Stream,
+    ];
+
+    pub const MARKET_DATA: [RecordedEvent; 5] = [
+        RecordedEvent::Trade,
+        RecordedEvent::AggTrade,
+        RecordedEvent::Candle,
+        RecordedEvent::Book,
+        RecordedEvent::BookTicker,
+    ];
+
+    pub fn of(event: &MarketplaceEvent) -> Self {
+        match event {
+            MarketplaceEvent::Trade(..) => RecordedEvent::Trade,
+            MarketplaceEvent::AggTrade(..) => RecordedEvent::AggTrade,
+            MarketplaceEvent::Candle(..) => RecordedEvent::Candle,
+            MarketplaceEvent::Book(..) => RecordedEvent::Book,
+            MarketplaceEvent::BookTicker(..) => RecordedEvent::BookTicker,
+            MarketplaceEvent::OrderUpdate(..) | MarketplaceEvent::OrderListUpdate(..) => {
+                RecordedEvent::Order
+            }
+            MarketplaceEvent::PortfolioUpdate(..) => RecordedEvent::Portfolio,
+            MarketplaceEvent::BalanceUpdate(..) => RecordedEvent::Balance,
+            MarketplaceEvent::StreamStatus(..) => RecordedEvent::Stream,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RecordedEvent::Trade => "trade",
+            RecordedEvent::AggTrade => "aggTrade",
+            RecordedEvent::Candle => "candle",
+            RecordedEvent::Book => "book",
+            RecordedEvent::BookTicker => "bookTicker",
+            RecordedEvent::Order => "order",
+            RecordedEvent::Portfolio => "portfolio",
+            RecordedEvent::Balance => "balance",
+            RecordedEvent::Stream => "stream",
+        }
+    }
+}
+
+impl FromStr for RecordedEvent {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        RecordedEvent::ALL
+            .into_iter()
+            .find(|event| event.as_str() == s)
+            .ok_or_else(|| format!("Unknown event type {s}"))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RecorderConfig {
+    pub data_path: PathBuf,
+    pub events: Vec<RecordedEvent>,
+    // minimum time between two recorded books of a ticker, in ms
+    pub book_interval: Option<u64>,
+    // gzip the segments of the previous days
+    pub compress: bool,
+}
+
+// One file per UTC day
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Segment {
+    pub file: String,
+    pub day: String,
+    pub from: u64,
+    pub to: u64,
+    pub count: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct RecordIndex {
+    pub segments: Vec<Segment>,
+}
+
+impl RecordIndex {
+    pub fn load(data_path: &Path) -> Option<Self> {
+        let content = std::fs::read_to_string(data_path.join(INDEX_FILE)).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    async fn save(&self, data_path: &Path) -> Result<()> {
+        let path = data_path.join(INDEX_FILE);
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)?).await?;
+        fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+}
+
+pub struct Recorder {
+    config: RecorderConfig,
+    index: RecordIndex,
+    // day and writer of the current segment
+    current: Option<(String, BufWriter<File>)>,
+    last_books: HashMap<Ticker, u64>,
+    // no new attempt to open a file until the retry time after an I/O error
+    retry_at: Option<Instant>,
+    dropped: u64,
+}
+
+impl Recorder {
+    pub async fn new(config: RecorderConfig) -> Self {
+        if let Err(err) = fs::create_dir_all(&config.data_path).await {
+            warn!(
+                "Could not create record directory {} : {}",
+                config.data_path.display(),
+                err
+            );
+        }
+        let index = RecordIndex::load(&config.data_path).unwrap_or_default();
+        Self {
+            config,
+            index,
+            current: None,
+            last_books: HashMap::new(),
+            retry_at: None,
+            dropped: 0,
+        }
+    }
+
+    fn accept(&mut self, event: &MarketplaceEvent, time: u64) -> bool {
+        if !self.config.events.contains(&RecordedEvent::of(event)) {
+            return false;
+        }
+        match event {
+            // higher intervals are aggregated again when replaying
+            MarketplaceEvent::Candle(candle) => candle.interval == CandleInterval::M1,
+            MarketplaceEvent::Book(book) => match self.config.book_interval {
+                Some(interval) => {
+                    let last = self.last_books.get(&book.ticker);
+                    if last.is_some_and(|last| time < last + interval) {
+                        return false;
+                    }
+                    self.last_books.insert(book.ticker.clone(), time);
+                    true
+                }
+                None => true,
+            },
+            _ => true,
+        }
+    }
+
+    pub async fn record(&mut self, event: &MarketplaceEvent) {
+        let time = event
+            .get_time()
+            .unwrap_or_else(|| Utc::now().timestamp_millis() as u64);
+        if !self.accept(event, time) {
+            return;
+        }
+
+        let day = DateTime::from_timestamp_millis(time as i64)
+            .unwrap_or_default()
+            .format("%Y-%m-%d")
+            .to_string();
+        if self
+            .current
+            .as_ref()
+            .is_none_or(|(current, _)| current < &day)
+        {
+            if self
+                .retry_at
+                .is_some_and(|retry_at| Instant::now() < retry_at)
+            {
+                self.dropped += 1;
+                return;
+            }
+            self.rotate(&day).await;
+        }
+
+        let Some((current_day, writer)) = self.current.as_mut() else {
+            self.dropped += 1;
+            return;
+        };
+        // late events of a previous day go to the current segment
+        let day = current_day.clone();
+        let mut line = match serde_json::to_string(event) {
+            Ok(line) => line,
+            Err(err) => {
+                error!("Could not serialize event : {err}");
+                return;
+            }
+        };
+        line.push('\n');
+        if let Err(err) = writer.write_all(line.as_bytes()).await {
+            self.fail(err);
+            return;
+        }
+
+        if let Some(segment) = self.index.segments.iter_mut().rev().find(|s| s.day == day) {
+            segment.from = segment.from.min(time);
+            segment.to = segment.to.max(time);
+            segment.count += 1;
+        }
+    }
+
+    pub async fn flush(&mut self) {
+        if let Some((_, writer)) = self.current.as_mut() {
+            if let Err(err) = writer.flush().await {
+                self.fail(err);
+                return;
+            }
+        }
+        if self.dropped > 0 {
+            warn!("Recorder dropped {} events", self.dropped);
+            self.dropped = 0;
+        }
+        if let Err(err) = self.index.save(&self.config.data_path).await {
+            warn!("Could not save record index : {err}");
+        }
+    }
+
+    // Stop writing until the retry time, the bot keeps running without recording.
+    fn fail(&mut self, err: std::io::Error) {
+        warn!("Recorder I/O error, recording paused : {err}");
+        self.current = None;
+        self.retry_at = Some(Instant::now() + Duration::from_secs(30));
+    }
+
+    async fn rotate(&mut self, day: &str) {
+        let previous = self.current.take();
+        self.flush_writer(previous).await;
+
+        let file = format!("events-{day}.jsonl");
+        let path = self.config.data_path.join(&file);
+        match OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await
+        {
+            Ok(writer) => {
+                info!("Recording to {}", path.display());
+                self.current = Some((day.to_string(), BufWriter::new(writer)));
+                self.retry_at = None;
+                if !self.index.segments.iter().any(|segment| segment.day == day) {
+                    self.index.segments.push(Segment {
+                        file,
+                        day: day.to_string(),
+                        from: u64::MAX,
+                        to: 0,
+                        count: 0,
+                    });
+                }
+            }
+            Err(err) => {
+                self.fail(err);
+                return;
+            }
+        }
+
+        if self.config.compress {
+            self.compress_closed_segments(day).await;
+        }
+        if let Err(err) = self.index.save(&self.config.data_path).await {
+            warn!("Could not save record index : {err}");
+        }
+    }
+
+    async fn flush_writer(&mut self, current: Option<(String, BufWriter<File>)>) {
+        if let Some((_, mut writer)) = current {
+            if let Err(err) = writer.flush().await {
+                warn!("Could not flush record segment : {err}");
+            }
+        }
+    }
+
+    async fn compress_closed_segments(&mut self, current_day: &str) {
+        for segment in self.index.segments.iter_mut() {
+            if segment.day.as_str() >= current_day || !segment.file.ends_with(".jsonl") {
+                continue;
+            }
+            let path = self.config.data_path.join(&segment.file);
+            let compressed = format!("{}.gz", segment.file);
+            let target = self.config.data_path.join(&compressed);
+            let res = tokio::task::spawn_blocking(move || gzip(&path, &target)).await;
+            match res {
+                Ok(Ok(())) => segment.file = compressed,
+                Ok(Err(err)) => warn!("Could not compress {} : {}", segment.file, err),
+                Err(err) => warn!("Could not compress {} : {}", segment.file, err),
+            }
+        }
+    }
+
+    pub async fn close(&mut self) {
+        self.flush().await;
+        let current = self.current.take();
+        self.flush_writer(current).await;
+    }
+}
+
+fn gzip(path: &Path, target: &Path) -> std::io::Result<()> {
+    let mut input = std::fs::File::open(path)?;
+    let output = std::fs::File::create(target)?;
+    let mut encoder = flate2::write::GzEncoder::new(output, flate2::Compression::default());
+    std::io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?.sync_all()?;
+    std::fs::remove_file(path)
+}
+
+// Record the marketplace events of the app bus.
+pub async fn start_recorder(config: RecorderConfig, tx_app: Sender<AppEvent>) {
+    let mut rx = tx_app.subscribe();
+    let mut recorder = Recorder::new(config).await;
+    let mut flush = tokio::time::interval(Duration::from_secs(5));
+
+    loop {
+        tokio::select! {
+            event = rx.recv() => match event {
+                Ok(AppEvent::MarketPlace(event)) => recorder.record(&event).await,
+                Ok(_) => {}
+                Err(RecvError::Lagged(n)) => warn!("Recorder lagged, {} events lost", n),
+                Err(RecvError::Closed) => break,
+            },
+            _ = flush.tick() => recorder.flush().await,
+        }
+    }
+
+    recorder.close().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::marketplace::MarketplaceBook;
+
+    fn book(time: u64) -> MarketplaceEvent {
+        MarketplaceEvent::Book(MarketplaceBook {
+            ticker: Ticker::new("BTC", "USDC"),
+            first_update_id: 0,
+            final_update_id: time,
+            time,
+            bids: Vec::new(),
+            asks: Vec::new(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_record_rotation() {
+        let data_path = std::env::temp_dir().join(format!("record-{}", uuid::Uuid::new_v4()));
+        let mut recorder = Recorder::new(RecorderConfig {
+            data_path: data_path.clone(),
+            events: vec![RecordedEvent::Book],
+            book_interval: Some(250),
+            compress: true,
+        })
+        .await;
+
+        // 2025-01-01 23:59:59.800
+        let day_end = 1735775999800;
+        for time in [day_end, day_end + 100, day_end + 250, day_end + 300] {
+            recorder.record(&book(time)).await;
+        }
+        recorder.close().await;
+
+        let index = RecordIndex::load(&data_path).unwrap();
+        assert_eq!(
+            index.segments,
+            vec![
+                Segment {
+                    file: "events-2025-01-01.jsonl.gz".to_string(),
+                    day: "2025-01-01".to_string(),
+                    from: day_end,
+                    to: day_end,
+                    count: 1,
+                },
+                Segment {
+                    file: "events-2025-01-02.jsonl".to_string(),
+                    day: "2025-01-02".to_string(),
+                    from: day_end + 250,
+                    to: day_end + 250,
+                    count: 1,
+                },
+            ]
+        );
+        assert!(data_path.join("events-2025-01-01.jsonl.gz").exists());
+        assert!(!data_path.join("events-2025-01-01.jsonl").exists());
+
+        let _ = std::fs::remove_dir_all(data_path);
+    }
+}
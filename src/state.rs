@@ -10,7 +10,7 @@ use serde::{Deserialize, Serialize};
 use crate::order::OrderType;
 use crate::{
     marketplace::MarketplaceOrderUpdate,
-    order::{Order, OrderSide, OrderStatus},
+    order::{get_cost_basis, Order, OrderSide, OrderStatus},
     portfolio::Portfolio,
     ticker::Ticker,
 };
@@ -65,9 +65,35 @@ impl State {
     }
 
     pub fn update_order(&mut self, update: MarketplaceOrderUpdate) {
-        if let Some(existing) = self.find_by_id(&update.client_id) {
+        let id = update.client_id.clone();
+        if let Some(existing) = self.find_by_id(&id) {
             existing.update(update);
         }
+        self.detach_unfilled(&id);
+    }
+
+    // A cancelled, rejected or expired order no longer closes its parent, so the parent
+    // position can be exited again for what it did not fill (see `get_exit_fills`).
+    pub fn detach_unfilled(&mut self, id: &String) {
+        let Some(prev_order_id) = self
+            .orders
+            .iter()
+            .find(|order| {
+                order.id == *id
+                    && matches!(
+                        order.status,
+                        OrderStatus::Cancelled | OrderStatus::Rejected | OrderStatus::Expired
+                    )
+            })
+            .and_then(|order| order.prev_order_id.clone())
+        else {
+            return;
+        };
+        if let Some(prev_order) = self.find_by_id(&prev_order_id) {
+            if prev_order.next_order_id.as_ref() == Some(id) {
+                prev_order.next_order_id = None;
+            }
+        }
     }
 
     pub fn add_order(&mut self, order: Order) -> anyhow::Result<Order> {
@@ -80,11 +106,11 @@ impl State {
                 self.portfolio
                     .reserve_funds(&order.ticker.quote, order.quote_amount)?;
             }
-            (OrderSide::Buy, OrderType::Limit) => {
+            (OrderSide::Buy, OrderType::Limit | OrderType::LimitMaker) => {
                 self.portfolio
-                    .reserve_funds(&order.ticker.quote, order.amount)?;
+                    .reserve_funds(&order.ticker.quote, order.amount * order.price)?;
             }
-            (OrderSide::Sell, OrderType::Market | OrderType::Limit) => {
+            (OrderSide::Sell, OrderType::Market | OrderType::Limit | OrderType::LimitMaker) => {
                 self.portfolio
                     .reserve_funds(&order.ticker.base, order.amount)?;
             }
@@ -138,20 +164,46 @@ impl State {
         sessions.into_iter().dedup().count()
     }
 
-    pub fn get_session_profit(&self, session_id: &String) -> Decimal {
+    // Base amount sold and quote received by the detached exits of a buy order
+    pub fn get_exit_fills(&self, buy_order_id: &String) -> (Decimal, Decimal) {
         self.orders
             .iter()
             .filter(|order| {
-                order.session_id.as_ref() == Some(session_id)
-                    && matches!(order.status, OrderStatus::Executed)
+                order.side == OrderSide::Sell && order.prev_order_id.as_ref() == Some(buy_order_id)
+            })
+            .fold((dec!(0), dec!(0)), |(sold, received), order| {
+                (
+                    sold + order.filled_amount,
+                    received + order.get_net_quote_amount(),
+                )
             })
+    }
+
+    pub fn get_session_profit(&self, session_id: &String) -> Decimal {
+        self.orders
+            .iter()
+            .filter(|order| order.session_id.as_ref() == Some(session_id) && order.has_traded())
             .map(|order| match order.side {
-                OrderSide::Buy => -order.get_trade_total_price(),
-                OrderSide::Sell => order.get_trade_total_price(),
+                OrderSide::Buy => -order.get_net_quote_amount(),
+                OrderSide::Sell => order.get_net_quote_amount(),
             })
             .sum()
     }
 
+    // Base amount held by a session and its average buy price
+    pub fn get_session_cost_basis(
+        &self,
+        session_id: &String,
+        fees: Decimal,
+    ) -> Option<(Decimal, Decimal)> {
+        get_cost_basis(
+            self.orders
+                .iter()
+                .filter(|order| order.session_id.as_ref() == Some(session_id)),
+            fees,
+        )
+    }
+
     pub fn get_session_start(&self, session_id: &String) -> Option<u64> {
         self.orders
             .iter()
@@ -247,8 +299,7 @@ impl State {
             })
             .fold(dec!(0), |acc, order| {
                 if let Some(parent_order_price) = order.buy_order_price {
-                    return acc
-                        + (order.get_trade_total_price() * dec!(0.999) - parent_order_price);
+                    return acc + (order.get_net_quote_amount() - parent_order_price);
                 }
                 acc
             })
@@ -0,0 +1,627 @@
+use super::StrategyAction;
+use crate::marketplace::candle::CandleInterval;
+use crate::marketplace::{
+    Marketplace, MarketplaceBook, MarketplaceCandle, MarketplaceDataApi, MarketplaceEvent,
+    MarketplaceSettingsApi,
+};
+use crate::order::{ExitReason, Order, OrderSide, OrderStatus};
+use crate::state::State;
+use crate::strategy::rules::{deserialize_rules, indicator_values, Rule, RuleContext, RuleResult};
+use crate::strategy::scalping::PriceStats;
+use crate::strategy::{Strategy, StrategyCommand, StrategyEvent, StrategyStatus};
+use crate::ticker::Ticker;
+use crate::utils::{deserialize_secs, serialize_secs};
+use crate::AppEvent;
+use anyhow::{Context, Result};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::Sender;
+use tokio::sync::RwLock;
+use tracing::{error, info};
+
+const MAX_CANDLES: usize = 500;
+
+// Dollar-cost averaging : a base buy on schedule (or signal), safety buys as the price drops,
+// the whole position sold once above its average price by the target.
+// Each cycle is a session, its buys chained by prev_order_id up to the sell.
+#[derive(Clone, Debug)]
+pub struct DcaStrategy<M> {
+    marketplace: M,
+    ticker: Ticker,
+    state: Arc<RwLock<State>>,
+    params: DcaParams,
+    // 1m candles for the entry rules, the latest first
+    candles: VecDeque<MarketplaceCandle>,
+    // only the indicator values of the entry rules, on the closed candles
+    stats: PriceStats,
+    // last order sent, until the state has it
+    sent: Option<(String, u64)>,
+    status: StrategyStatus,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DcaParams {
+    // quote amount of the base buy
+    pub quote_amount: Decimal,
+    // delay between the base buys of two cycles, 0 to only wait for the entry rules
+    #[serde(
+        serialize_with = "serialize_secs",
+        deserialize_with = "deserialize_secs"
+    )]
+    pub interval: Duration,
+    // signal of the base buy, see Rule in rules.rs, without the price stats ones
+    #[serde(deserialize_with = "deserialize_entry")]
+    pub entry: Vec<Rule>,
+    pub safety_orders: u32,
+    // drop under the base buy price of the first safety buy,
+    // each next one step_scale times further than the previous
+    pub safety_step: Decimal,
+    pub step_scale: Decimal,
+    // quote amount of each safety buy, volume_scale times the previous one
+    pub volume_scale: Decimal,
+    // profit of the position over its average price, fees included
+    pub target_profit: Decimal,
+}
+
+impl Default for DcaParams {
+    fn default() -> Self {
+        Self {
+            quote_amount: dec!(20),
+            interval: Duration::from_secs(24 * 3600),
+            entry: vec![],
+            safety_orders: 5,
+            safety_step: dec!(0.02),
+            step_scale: dec!(1),
+            volume_scale: dec!(1.5),
+            target_profit: dec!(0.015),
+        }
+    }
+}
+
+// The price stats are computed by the scalping strategy only
+fn deserialize_entry<'de, D>(deserializer: D) -> std::result::Result<Vec<Rule>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let rules = deserialize_rules(deserializer)?;
+    if let Some(rule) = rules.iter().find(|rule| rule.needs_price_stats()) {
+        return Err(serde::de::Error::custom(format!(
+            "{rule:?} is not available to the dca entry"
+        )));
+    }
+    Ok(rules)
+}
+
+impl DcaParams {
+    // Drop under the base price triggering the safety buy n (from 1)
+    pub fn safety_drop(&self, n: u32) -> Decimal {
+        let mut step = self.safety_step;
+        let mut drop = dec!(0);
+        for _ in 0..n {
+            drop += step;
+            step *= self.step_scale;
+        }
+        drop
+    }
+
+    // Quote amount of the safety buy n (from 1)
+    pub fn safety_quote_amount(&self, n: u32) -> Decimal {
+        (0..n).fold(self.quote_amount, |amount, _| amount * self.volume_scale)
+    }
+}
+
+impl<M: Marketplace + MarketplaceSettingsApi + MarketplaceDataApi> DcaStrategy<M> {
+    pub fn new(
+        state: Arc<RwLock<State>>,
+        marketplace: M,
+        ticker: Ticker,
+        params: DcaParams,
+    ) -> Self {
+        Self {
+            marketplace,
+            ticker,
+            state,
+            params,
+            candles: VecDeque::new(),
+            stats: PriceStats::default(),
+            sent: None,
+            status: StrategyStatus::New,
+        }
+    }
+
+    fn check_entry(
+        &self,
+        state: &State,
+        price: Decimal,
+        book: &MarketplaceBook,
+    ) -> Vec<StrategyAction> {
+        let ctx = RuleContext {
+            ticker: &self.ticker,
+            stats: &self.stats,
+            state,
+            price,
+            book,
+            candles: &self.candles,
+        };
+        self.params
+            .entry
+            .iter()
+            .filter_map(|rule| match rule.check(&ctx) {
+                RuleResult::Pass => None,
+                RuleResult::Ignore { reason, details } => Some(StrategyAction::Ignore {
+                    ticker: self.ticker.clone(),
+                    reason: format!("DCA {}", reason),
+                    details,
+                }),
+            })
+            .collect()
+    }
+
+    // Next order of the cycle : base buy, safety buy or sell of the position
+    fn plan(
+        &self,
+        state: &State,
+        book: &MarketplaceBook,
+        time: u64,
+        fees: Decimal,
+    ) -> Vec<StrategyAction> {
+        let (Some(bid), Some(ask)) = (book.buy_price(), book.sell_price()) else {
+            return vec![];
+        };
+        let orders: Vec<&Order> = state
+            .orders
+            .iter()
+            .filter(|order| order.ticker == self.ticker && order.strategy.as_deref() == Some("dca"))
+            .collect();
+
+        if let Some((id, sent_time)) = &self.sent {
+            if !orders.iter().any(|order| &order.id == id)
+                && time.saturating_sub(*sent_time) < 60_000
+            {
+                return vec![];
+            }
+        }
+        if orders.iter().any(|order| {
+            matches!(
+                order.status,
+                OrderStatus::Draft
+                    | OrderStatus::Sent
+                    | OrderStatus::Active
+                    | OrderStatus::PendingCancel
+            )
+        }) {
+            return vec![];
+        }
+
+        // a cycle is open until its sell is executed
+        let last_executed = orders
+            .iter()
+            .filter(|order| order.status == OrderStatus::Executed)
+            .max_by_key(|order| order.creation_time);
+
+        let order = match last_executed {
+            Some(last_buy) if last_buy.side == OrderSide::Buy => {
+                let Some(session_id) = last_buy.session_id.as_ref() else {
+                    return vec![];
+                };
+                let Some((amount, cost_price)) = state.get_session_cost_basis(session_id, fees)
+                else {
+                    return vec![];
+                };
+
+                if bid * (dec!(1) - fees) >= cost_price * (dec!(1) + self.params.target_profit) {
+                    let mut order =
+                        Order::new_sell(self.ticker.clone(), amount, bid, time, Some(last_buy));
+                    order.exit_reason = Some(ExitReason::TakeProfit);
+                    info!(
+                        "{} DCA sell {} at {}, average {}",
+                        self.ticker, amount, bid, cost_price
+                    );
+                    order
+                } else {
+                    let buys: Vec<&&Order> = orders
+                        .iter()
+                        .filter(|order| {
+                            order.session_id.as_ref() == Some(session_id)
+                                && order.side == OrderSide::Buy
+                                && order.status == OrderStatus::Executed
+                        })
+                        .collect();
+                    let safety = buys.len() as u32;
+                    let Some(base_buy) = buys.iter().min_by_key(|order| order.creation_time) else {
+                        return vec![];
+                    };
+                    if safety > self.params.safety_orders
+                        || ask
+                            > base_buy.get_order_base_price()
+                                * (dec!(1) - self.params.safety_drop(safety))
+                    {
+                        return vec![];
+                    }
+                    let quote_amount = self.params.safety_quote_amount(safety);
+                    Order::new_buy(
+                        self.ticker.clone(),
+                        quote_amount / ask,
+                        ask,
+                        quote_amount,
+                        time,
+                        Some(last_buy),
+                    )
+                }
+            }
+            _ => {
+                let last_base = orders
+                    .iter()
+                    .filter(|order| {
+                        order.side == OrderSide::Buy
+                            && order.prev_order_id.is_none()
+                            && order.status == OrderStatus::Executed
+                    })
+                    .map(|order| order.creation_time)
+                    .max();
+                if last_base.is_some_and(|last_base| {
+                    Duration::from_millis(time.saturating_sub(last_base)) < self.params.interval
+                }) {
+                    return vec![];
+                }
+                let ignores = self.check_entry(state, ask, book);
+                if !ignores.is_empty() {
+                    return ignores;
+                }
+                Order::new_buy(
+                    self.ticker.clone(),
+                    self.params.quote_amount / ask,
+                    ask,
+                    self.params.quote_amount,
+                    time,
+                    None,
+                )
+            }
+        };
+
+        let (asset, amount) = match order.side {
+            OrderSide::Buy => (&self.ticker.quote, order.quote_amount),
+            OrderSide::Sell => (&self.ticker.base, order.amount),
+        };
+        if !state.portfolio.check_funds(asset, amount) {
+            return vec![StrategyAction::Ignore {
+                ticker: self.ticker.clone(),
+                reason: format!("DCA {} no funds", order.side),
+                details: Some(format!("{} {}", amount, asset)),
+            }];
+        }
+
+        let mut order = order;
+        order.strategy = Some("dca".to_string());
+        order.fees = fees;
+        vec![StrategyAction::PlaceOrder { order }]
+    }
+
+    async fn on_depth_event(
+        &mut self,
+        event: &MarketplaceBook,
+        tx_app: &Sender<AppEvent>,
+    ) -> Result<()> {
+        let fees = self.marketplace.get_fees().await;
+        let actions = {
+            let state = self.state.read().await;
+            self.plan(&state, event, event.time, fees)
+        };
+
+        for mut action in actions {
+            if let StrategyAction::PlaceOrder { order } = &mut action {
+                // the sell keeps the amount of the position, floored to the step
+                let mut adjusted = order.clone();
+                if let Err(err) = self
+                    .marketplace
+                    .adjust_order_price_and_amount(&mut adjusted)
+                    .await
+                {
+                    error!("Failed to adjust order amount : {}", err);
+                    continue;
+                }
+                match order.side {
+                    OrderSide::Buy => *order = adjusted,
+                    OrderSide::Sell => match self
+                        .marketplace
+                        .floor_amount(&self.ticker, order.amount)
+                        .await
+                    {
+                        Ok(amount) if amount > dec!(0) => order.amount = amount,
+                        _ => continue,
+                    },
+                }
+                self.sent = Some((order.id.clone(), event.time));
+            }
+            tx_app.send(AppEvent::Strategy(StrategyEvent::Action(Box::new(action))))?;
+        }
+        Ok(())
+    }
+
+    fn add_candle(&mut self, candle: &MarketplaceCandle) {
+        if self
+            .candles
+            .front()
+            .is_some_and(|last| candle.start_time < last.start_time)
+        {
+            return;
+        }
+        if self
+            .candles
+            .front()
+            .is_some_and(|last| candle.start_time == last.start_time)
+        {
+            self.candles.pop_front();
+            self.candles.push_front(candle.clone());
+            return;
+        }
+        self.candles.push_front(candle.clone());
+        self.candles.truncate(MAX_CANDLES);
+        // the previous candle is closed
+        self.update_indicator_stats();
+    }
+
+    // Indicators of the entry rules over the closed candles, the first one is still open
+    fn update_indicator_stats(&mut self) {
+        self.stats.indicators = indicator_values(&self.params.entry, self.candles.iter().skip(1));
+    }
+}
+
+impl<M> Strategy for DcaStrategy<M>
+where
+    M: Marketplace + MarketplaceSettingsApi + MarketplaceDataApi,
+{
+    fn name(&self) -> &'static str {
+        "dca"
+    }
+
+    fn ticker(&self) -> &Ticker {
+        &self.ticker
+    }
+
+    async fn init(&mut self, start_time: Option<u64>) -> Result<()> {
+        self.status = StrategyStatus::Initializing;
+        // the entry rules look at the 1m candles
+        if !self.params.entry.is_empty() {
+            let mut candles = self
+                .marketplace
+                .get_candles(&self.ticker, CandleInterval::M1, None, start_time)
+                .await
+                .context(format!("Failed to load candles of {}", self.ticker))?;
+            candles.reverse();
+            candles.truncate(MAX_CANDLES);
+            self.candles = VecDeque::from(candles);
+            self.update_indicator_stats();
+        }
+        self.status = StrategyStatus::Running;
+        Ok(())
+    }
+
+    async fn on_event(&mut self, event: &AppEvent, tx_app: &Sender<AppEvent>) -> Result<()> {
+        match event {
+            AppEvent::MarketPlace(MarketplaceEvent::Candle(event))
+                if self.ticker == event.ticker && event.interval == CandleInterval::M1 =>
+            {
+                self.add_candle(event);
+            }
+            AppEvent::MarketPlace(MarketplaceEvent::Book(event)) if self.ticker == event.ticker => {
+                self.on_depth_event(event, tx_app).await?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    async fn on_command(&mut self, command: StrategyCommand) {
+        self.status = match (command, self.status) {
+            (StrategyCommand::Stop, _) => StrategyStatus::Stopped,
+            (StrategyCommand::Pause, StrategyStatus::Running) => StrategyStatus::Paused,
+            (StrategyCommand::Resume, StrategyStatus::Paused) => StrategyStatus::Running,
+            (_, status) => status,
+        };
+    }
+
+    fn status(&self) -> StrategyStatus {
+        self.status
+    }
+
+    fn params(&self) -> serde_json::Value {
+        serde_json::to_value(&self.params).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::marketplace::binance::Binance;
+    use crate::marketplace::MarketplaceOrderUpdate;
+    use crate::order::OrderTrade;
+    use crate::portfolio::Asset;
+
+    fn book(ticker: &Ticker, bid: Decimal, ask: Decimal) -> MarketplaceBook {
+        MarketplaceBook {
+            ticker: ticker.clone(),
+            first_update_id: 0,
+            final_update_id: 0,
+            time: 0,
+            bids: vec![(bid, dec!(10))],
+            asks: vec![(ask, dec!(10))],
+        }
+    }
+
+    fn execute(state: &mut State, order: Order, price: Decimal, time: u64) -> Order {
+        let order = state.add_order(order).unwrap();
+        state.update_order(MarketplaceOrderUpdate {
+            time,
+            update_type: "TRADE".to_string(),
+            marketplace_id: order.id.clone(),
+            client_id: order.id.clone(),
+            status: OrderStatus::Executed,
+            working_time: Some(time),
+            trade: Some(OrderTrade {
+                id: order.id.clone(),
+                trade_time: time,
+                amount: order.amount,
+                price,
+                fee: dec!(0),
+                fee_asset: None,
+            }),
+        });
+        state
+            .orders
+            .iter()
+            .find(|o| o.id == order.id)
+            .unwrap()
+            .clone()
+    }
+
+    fn place(actions: Vec<StrategyAction>) -> Order {
+        match actions.into_iter().next() {
+            Some(StrategyAction::PlaceOrder { order }) => order,
+            action => panic!("no order : {:?}", action),
+        }
+    }
+
+    #[test]
+    fn test_dca_cycle() {
+        let ticker = Ticker::new("BTC", "USDC");
+        let params = DcaParams {
+            quote_amount: dec!(100),
+            safety_step: dec!(0.1),
+            volume_scale: dec!(2),
+            target_profit: dec!(0.05),
+            ..Default::default()
+        };
+        assert_eq!(params.safety_drop(2), dec!(0.2));
+        assert_eq!(params.safety_quote_amount(2), dec!(400));
+        let strategy = DcaStrategy::new(
+            Arc::new(RwLock::new(State::new())),
+            Binance::default(),
+            ticker.clone(),
+            params,
+        );
+
+        let mut state = State::new();
+        state.portfolio.update_asset(Asset {
+            symbol: "USDC".to_string(),
+            amount: dec!(10000),
+            locked: dec!(0),
+            value: None,
+        });
+
+        let base = place(strategy.plan(&state, &book(&ticker, dec!(99), dec!(100)), 0, dec!(0)));
+        assert_eq!((base.side, base.amount), (OrderSide::Buy, dec!(1)));
+        let base = execute(&mut state, base, dec!(100), 0);
+
+        // no safety buy above a 10% drop, one of 200 USDC under
+        assert!(strategy
+            .plan(&state, &book(&ticker, dec!(94), dec!(95)), 1000, dec!(0))
+            .is_empty());
+        let safety =
+            place(strategy.plan(&state, &book(&ticker, dec!(79), dec!(80)), 2000, dec!(0)));
+        assert_eq!(safety.quote_amount, dec!(200));
+        assert_eq!(safety.prev_order_id, Some(base.id.clone()));
+        assert_eq!(safety.session_id, base.session_id);
+        execute(&mut state, safety, dec!(80), 2000);
+
+        // 300 USDC for 3.5 BTC, sold 5% above
+        let session_id = base.session_id.clone().unwrap();
+        let (amount, price) = state.get_session_cost_basis(&session_id, dec!(0)).unwrap();
+        assert_eq!(amount, dec!(3.5));
+        assert_eq!(price.round_dp(4), dec!(85.7143));
+        state.portfolio.update_asset(Asset {
+            symbol: "BTC".to_string(),
+            amount,
+            locked: dec!(0),
+            value: None,
+        });
+        assert!(strategy
+            .plan(&state, &book(&ticker, dec!(89), dec!(90)), 3000, dec!(0))
+            .is_empty());
+        let sell = place(strategy.plan(&state, &book(&ticker, dec!(91), dec!(92)), 4000, dec!(0)));
+        assert_eq!((sell.side, sell.amount), (OrderSide::Sell, dec!(3.5)));
+        assert_eq!(sell.session_id, Some(session_id.clone()));
+        execute(&mut state, sell, dec!(91), 4000);
+        assert_eq!(state.get_session_cost_basis(&session_id, dec!(0)), None);
+        assert_eq!(state.get_session_profit(&session_id), dec!(18.5));
+
+        // the next cycle waits for the interval
+        assert!(strategy
+            .plan(&state, &book(&ticker, dec!(91), dec!(92)), 5000, dec!(0))
+            .is_empty());
+
+        // only the entry rules without price stats are accepted
+        assert!(serde_json::from_str::<DcaParams>(
+            r#"{"entry": [{"rule": "max_spread", "max": 0.001}]}"#
+        )
+        .is_ok());
+        assert!(
+            serde_json::from_str::<DcaParams>(r#"{"entry": [{"rule": "downtrend"}]}"#).is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dca_sell_step() {
+        let ticker = Ticker::new("BTC", "USDC");
+        let state = Arc::new(RwLock::new(State::new()));
+        let exchange_info = serde_json::from_value(serde_json::json!({
+            "symbols": [{
+                "symbol": "BTCUSDC",
+                "baseAsset": "BTC",
+                "quoteAsset": "USDC",
+                "baseAssetPrecision": 8,
+                "quoteAssetPrecision": 8,
+                "filters": [
+                    {"filterType": "PRICE_FILTER", "minPrice": "0.01", "maxPrice": "1000000", "tickSize": "0.01"},
+                    {"filterType": "LOT_SIZE", "minQty": "0.001", "maxQty": "9000", "stepSize": "0.001"},
+                    {"filterType": "NOTIONAL", "minNotional": "5"}
+                ]
+            }]
+        }))
+        .unwrap();
+        let mut strategy = DcaStrategy::new(
+            state.clone(),
+            Binance::default().with_exchange_info(exchange_info),
+            ticker.clone(),
+            DcaParams {
+                quote_amount: dec!(12.345),
+                ..Default::default()
+            },
+        );
+
+        // a base buy filled for an amount off the step
+        {
+            let mut state = state.write().await;
+            for (symbol, amount) in [("USDC", dec!(1000)), ("BTC", dec!(0.12345))] {
+                state.portfolio.update_asset(Asset {
+                    symbol: symbol.to_string(),
+                    amount,
+                    locked: dec!(0),
+                    value: None,
+                });
+            }
+            let base =
+                place(strategy.plan(&state, &book(&ticker, dec!(99), dec!(100)), 0, dec!(0)));
+            assert_eq!(base.amount, dec!(0.12345));
+            execute(&mut state, base, dec!(100), 0);
+        }
+
+        let (tx_app, mut rx_app) = tokio::sync::broadcast::channel(16);
+        strategy
+            .on_depth_event(&book(&ticker, dec!(200), dec!(201)), &tx_app)
+            .await
+            .unwrap();
+        let Ok(AppEvent::Strategy(StrategyEvent::Action(action))) = rx_app.try_recv() else {
+            panic!("no action");
+        };
+        let StrategyAction::PlaceOrder { order } = *action else {
+            panic!("no sell : {:?}", action);
+        };
+        assert_eq!((order.side, order.amount), (OrderSide::Sell, dec!(0.123)));
+    }
+}
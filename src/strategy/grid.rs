@@ -0,0 +1,679 @@
+use super::StrategyAction;
+use crate::marketplace::{
+    Marketplace, MarketplaceBook, MarketplaceDataApi, MarketplaceEvent, MarketplaceSettingsApi,
+};
+use crate::order::{Order, OrderSide, OrderStatus, OrderType};
+use crate::state::State;
+use crate::strategy::{Strategy, StrategyCommand, StrategyEvent, StrategyStatus};
+use crate::ticker::Ticker;
+use crate::AppEvent;
+use anyhow::{Context, Result};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use tokio::sync::broadcast::Sender;
+use tokio::sync::RwLock;
+use tracing::info;
+use uuid::Uuid;
+
+// Spot grid for range-bound pairs : limit buys under the price and limit sells over it,
+// each fill replaced by the opposite order one step away.
+// Each level is a session chained by prev_order_id, its session profit is the level profit.
+#[derive(Clone, Debug)]
+pub struct GridStrategy<M> {
+    marketplace: M,
+    ticker: Ticker,
+    state: Arc<RwLock<State>>,
+    params: GridParams,
+    // ascending, set from the first book or the orders of the restored grid
+    prices: Vec<Decimal>,
+    // sessions of the levels are "{grid_id}-{level}"
+    grid_id: String,
+    // last order sent by level, until the state has it
+    sent: HashMap<usize, (String, u64)>,
+    status: StrategyStatus,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GridParams {
+    // bounds of the grid, the first price -/+ range when not set
+    pub lower: Option<Decimal>,
+    pub upper: Option<Decimal>,
+    pub range: Decimal,
+    // evenly spaced levels between lower and upper
+    pub levels: usize,
+    // quote amount bought at each level
+    pub quote_amount: Decimal,
+    // Limit or LimitMaker
+    pub order_type: OrderType,
+    // seconds before a level places again an order which was not filled (rejected, cancelled)
+    pub retry_delay: u64,
+}
+
+impl Default for GridParams {
+    fn default() -> Self {
+        Self {
+            lower: None,
+            upper: None,
+            range: dec!(0.05),
+            levels: 10,
+            quote_amount: dec!(20),
+            order_type: OrderType::Limit,
+            retry_delay: 60,
+        }
+    }
+}
+
+impl<M: Marketplace + MarketplaceSettingsApi> GridStrategy<M> {
+    pub fn new(
+        state: Arc<RwLock<State>>,
+        marketplace: M,
+        ticker: Ticker,
+        params: GridParams,
+    ) -> Self {
+        Self {
+            marketplace,
+            ticker,
+            state,
+            params,
+            prices: Vec::new(),
+            grid_id: Uuid::new_v4().to_string(),
+            sent: HashMap::new(),
+            status: StrategyStatus::New,
+        }
+    }
+
+    // Level prices from lower to upper
+    fn grid_prices(&self, price: Decimal) -> Vec<Decimal> {
+        let lower = self
+            .params
+            .lower
+            .unwrap_or(price * (dec!(1) - self.params.range));
+        let upper = self
+            .params
+            .upper
+            .unwrap_or(price * (dec!(1) + self.params.range));
+        if self.params.levels == 0 || lower <= dec!(0) || upper <= lower {
+            return Vec::new();
+        }
+        let step = (upper - lower) / Decimal::from(self.params.levels);
+        (0..=self.params.levels)
+            .map(|level| lower + step * Decimal::from(level))
+            .collect()
+    }
+
+    fn session_id(&self, level: usize) -> String {
+        format!("{}-{}", self.grid_id, level)
+    }
+
+    // Grid id and level of a grid order of the ticker
+    fn grid_level<'a>(&self, order: &'a Order) -> Option<(&'a str, usize)> {
+        if order.ticker != self.ticker || order.strategy.as_deref() != Some("grid") {
+            return None;
+        }
+        let (grid_id, level) = order.session_id.as_ref()?.rsplit_once('-')?;
+        Some((grid_id, level.parse().ok()?))
+    }
+
+    // Takes over the last grid of the ticker in the state, its prices come from the orders
+    // of two levels at least. False when there is none.
+    fn restore(&mut self, state: &State) -> bool {
+        let Some(grid_id) = state
+            .orders
+            .iter()
+            .filter_map(|order| Some((self.grid_level(order)?.0, order.creation_time)))
+            .max_by_key(|(_, time)| *time)
+            .map(|(grid_id, _)| grid_id.to_string())
+        else {
+            return false;
+        };
+
+        // a buy is at the price of its level, a sell one step above
+        let points: BTreeMap<usize, Decimal> = state
+            .orders
+            .iter()
+            .filter_map(|order| {
+                let (id, level) = self.grid_level(order)?;
+                (id == grid_id).then(|| match order.side {
+                    OrderSide::Buy => (level, order.price),
+                    OrderSide::Sell => (level + 1, order.price),
+                })
+            })
+            .collect();
+        let (Some((&first, &first_price)), Some((&last, &last_price))) =
+            (points.first_key_value(), points.last_key_value())
+        else {
+            return false;
+        };
+        if first == last {
+            return false;
+        }
+
+        let step = (last_price - first_price) / Decimal::from(last - first);
+        let lower = first_price - step * Decimal::from(first);
+        self.prices = (0..=self.params.levels.max(last))
+            .map(|level| lower + step * Decimal::from(level))
+            .collect();
+        self.grid_id = grid_id;
+        true
+    }
+
+    // Cancels the open orders of the grid, stopped once none is left
+    fn cancel_orders(&mut self, state: &State) -> Vec<StrategyAction> {
+        let open: Vec<&Order> = state
+            .orders
+            .iter()
+            .filter(|order| {
+                self.grid_level(order)
+                    .is_some_and(|(grid_id, _)| grid_id == self.grid_id)
+                    && matches!(
+                        order.status,
+                        OrderStatus::Draft
+                            | OrderStatus::Sent
+                            | OrderStatus::Active
+                            | OrderStatus::PendingCancel
+                    )
+            })
+            .collect();
+        if open.is_empty() {
+            info!("{} grid {} stopped", self.ticker, self.grid_id);
+            self.status = StrategyStatus::Stopped;
+        }
+        open.into_iter()
+            .filter(|order| order.status == OrderStatus::Active)
+            .map(|order| StrategyAction::Cancel {
+                order_id: order.id.clone(),
+                reason: "Grid stopped".to_string(),
+                details: None,
+            })
+            .collect()
+    }
+
+    // Next order of a level : buys at its price, sells one step above.
+    // None while an order of the level is pending.
+    fn plan_level(
+        &self,
+        level: usize,
+        state: &State,
+        price: Decimal,
+        time: u64,
+        fees: Decimal,
+    ) -> Option<StrategyAction> {
+        let session_id = self.session_id(level);
+        let orders: Vec<&Order> = state
+            .orders
+            .iter()
+            .filter(|order| order.session_id.as_ref() == Some(&session_id))
+            .collect();
+
+        if let Some((id, sent_time)) = self.sent.get(&level) {
+            if !orders.iter().any(|order| &order.id == id)
+                && time.saturating_sub(*sent_time) < self.params.retry_delay * 1000
+            {
+                return None;
+            }
+        }
+
+        if let Some(last) = orders.iter().max_by_key(|order| order.creation_time) {
+            if matches!(
+                last.status,
+                OrderStatus::Draft
+                    | OrderStatus::Sent
+                    | OrderStatus::Active
+                    | OrderStatus::PendingCancel
+            ) {
+                return None;
+            }
+            if last.status != OrderStatus::Executed
+                && time.saturating_sub(last.creation_time) < self.params.retry_delay * 1000
+            {
+                return None;
+            }
+        }
+
+        let (buy_price, sell_price) = (self.prices[level], self.prices[level + 1]);
+        let last_executed = orders
+            .iter()
+            .filter(|order| order.status == OrderStatus::Executed)
+            .max_by_key(|order| order.creation_time);
+
+        let mut order = match last_executed {
+            Some(buy_order) if buy_order.side == OrderSide::Buy => Order::new_sell(
+                self.ticker.clone(),
+                buy_order.get_net_base_amount(fees) - state.get_exit_fills(&buy_order.id).0,
+                sell_price,
+                time,
+                Some(buy_order),
+            ),
+            Some(sell_order) => {
+                info!(
+                    "{} grid level {} at {}, profit {}",
+                    self.ticker,
+                    level,
+                    buy_price,
+                    state.get_session_profit(&session_id)
+                );
+                let amount = self.params.quote_amount / buy_price;
+                Order::new_buy(
+                    self.ticker.clone(),
+                    amount,
+                    buy_price,
+                    amount * buy_price,
+                    time,
+                    Some(sell_order),
+                )
+            }
+            None if buy_price < price => {
+                let amount = self.params.quote_amount / buy_price;
+                Order::new_buy(
+                    self.ticker.clone(),
+                    amount,
+                    buy_price,
+                    amount * buy_price,
+                    time,
+                    None,
+                )
+            }
+            // levels over the price start by selling the portfolio base
+            None => Order::new_sell(
+                self.ticker.clone(),
+                self.params.quote_amount / buy_price,
+                sell_price,
+                time,
+                None,
+            ),
+        };
+
+        let (asset, amount) = match order.side {
+            OrderSide::Buy => (&self.ticker.quote, order.amount * order.price),
+            OrderSide::Sell => (&self.ticker.base, order.amount),
+        };
+        if !state.portfolio.check_funds(asset, amount) {
+            return Some(StrategyAction::Ignore {
+                ticker: self.ticker.clone(),
+                reason: format!("Grid {} no funds", order.side),
+                details: Some(format!("level {} : {} {}", level, amount, asset)),
+            });
+        }
+
+        order.order_type = self.params.order_type;
+        order.session_id = Some(session_id);
+        order.strategy = Some("grid".to_string());
+        order.fees = fees;
+        Some(StrategyAction::PlaceOrder { order })
+    }
+
+    async fn on_depth_event(
+        &mut self,
+        event: &MarketplaceBook,
+        tx_app: &Sender<AppEvent>,
+    ) -> Result<()> {
+        if self.status == StrategyStatus::Stopping {
+            let state = self.state.clone();
+            let actions = self.cancel_orders(&*state.read().await);
+            for action in actions {
+                tx_app.send(AppEvent::Strategy(StrategyEvent::Action(Box::new(action))))?;
+            }
+            return Ok(());
+        }
+
+        let price = event
+            .buy_price()
+            .context(format!("Current buy price missing for {}.", event.ticker))?;
+
+        if self.prices.is_empty() {
+            self.prices = self.grid_prices(price);
+            info!("{} grid {:?}", self.ticker, self.prices);
+        }
+
+        // the grid orders rest in the book, unless placed at the market
+        let fees = match self.params.order_type {
+            OrderType::Limit | OrderType::LimitMaker => self.marketplace.get_maker_fees().await,
+            _ => self.marketplace.get_fees().await,
+        };
+        let actions: Vec<StrategyAction> = {
+            let state = self.state.read().await;
+            (0..self.prices.len().saturating_sub(1))
+                .filter_map(|level| self.plan_level(level, &state, price, event.time, fees))
+                .collect()
+        };
+
+        for mut action in actions {
+            if let StrategyAction::PlaceOrder { order } = &mut action {
+                // the sells keep the amount bought floored to the step, adjusting rounds it up
+                let mut adjusted = order.clone();
+                if self
+                    .marketplace
+                    .adjust_order_price_and_amount(&mut adjusted)
+                    .await
+                    .is_err()
+                {
+                    continue;
+                }
+                match order.side {
+                    OrderSide::Buy => *order = adjusted,
+                    OrderSide::Sell => {
+                        match self
+                            .marketplace
+                            .floor_amount(&self.ticker, order.amount)
+                            .await
+                        {
+                            Ok(amount) if amount > dec!(0) => order.amount = amount,
+                            _ => continue,
+                        }
+                        order.price = adjusted.price;
+                    }
+                }
+                if let Some(level) = order
+                    .session_id
+                    .as_ref()
+                    .and_then(|session_id| session_id.rsplit('-').next())
+                    .and_then(|level| level.parse().ok())
+                {
+                    self.sent.insert(level, (order.id.clone(), event.time));
+                }
+            }
+            tx_app.send(AppEvent::Strategy(StrategyEvent::Action(Box::new(action))))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<M> Strategy for GridStrategy<M>
+where
+    M: Marketplace + MarketplaceSettingsApi + MarketplaceDataApi,
+{
+    fn name(&self) -> &'static str {
+        "grid"
+    }
+
+    fn ticker(&self) -> &Ticker {
+        &self.ticker
+    }
+
+    async fn init(&mut self, _start_time: Option<u64>) -> Result<()> {
+        let state = self.state.clone();
+        if self.restore(&*state.read().await) {
+            info!("{} grid {} {:?}", self.ticker, self.grid_id, self.prices);
+        }
+        self.status = StrategyStatus::Running;
+        Ok(())
+    }
+
+    async fn on_event(&mut self, event: &AppEvent, tx_app: &Sender<AppEvent>) -> Result<()> {
+        if let AppEvent::MarketPlace(MarketplaceEvent::Book(event)) = event {
+            if self.ticker == event.ticker {
+                self.on_depth_event(event, tx_app).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn on_command(&mut self, command: StrategyCommand) {
+        self.status = match (command, self.status) {
+            // the open orders are cancelled first, a second stop leaves them
+            (StrategyCommand::Stop, StrategyStatus::Running | StrategyStatus::Paused) => {
+                StrategyStatus::Stopping
+            }
+            (StrategyCommand::Stop, _) => StrategyStatus::Stopped,
+            (StrategyCommand::Pause, StrategyStatus::Running) => StrategyStatus::Paused,
+            (StrategyCommand::Resume, StrategyStatus::Paused) => StrategyStatus::Running,
+            (_, status) => status,
+        };
+    }
+
+    fn status(&self) -> StrategyStatus {
+        self.status
+    }
+
+    fn params(&self) -> serde_json::Value {
+        serde_json::to_value(&self.params).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::marketplace::binance::Binance;
+    use crate::marketplace::MarketplaceOrderUpdate;
+    use crate::order::OrderTrade;
+    use crate::portfolio::Asset;
+
+    #[test]
+    fn test_grid_levels() {
+        let ticker = Ticker::new("BTC", "USDC");
+        let mut strategy = GridStrategy::new(
+            Arc::new(RwLock::new(State::new())),
+            Binance::default(),
+            ticker.clone(),
+            GridParams {
+                lower: Some(dec!(90)),
+                upper: Some(dec!(110)),
+                levels: 4,
+                ..Default::default()
+            },
+        );
+        strategy.prices = strategy.grid_prices(dec!(101));
+        assert_eq!(
+            strategy.prices,
+            vec![dec!(90), dec!(95), dec!(100), dec!(105), dec!(110)]
+        );
+
+        let mut state = State::new();
+        state.portfolio.update_asset(Asset {
+            symbol: "USDC".to_string(),
+            amount: dec!(1000),
+            locked: dec!(0),
+            value: None,
+        });
+
+        // under the price a buy, over it a sell of the base the portfolio does not have
+        let Some(StrategyAction::PlaceOrder { order }) =
+            strategy.plan_level(2, &state, dec!(101), 0, dec!(0))
+        else {
+            panic!("no buy");
+        };
+        assert_eq!((order.side, order.price), (OrderSide::Buy, dec!(100)));
+        assert_eq!(order.order_type, OrderType::Limit);
+        assert!(matches!(
+            strategy.plan_level(3, &state, dec!(101), 0, dec!(0)),
+            Some(StrategyAction::Ignore { .. })
+        ));
+
+        // the filled buy is followed by a sell one step above, in the same session
+        let buy = state.add_order(order).unwrap();
+        assert_eq!(
+            strategy
+                .plan_level(2, &state, dec!(101), 0, dec!(0))
+                .map(|_| ()),
+            None
+        );
+        state.update_order(MarketplaceOrderUpdate {
+            time: 1000,
+            update_type: "TRADE".to_string(),
+            marketplace_id: "1".to_string(),
+            client_id: buy.id.clone(),
+            status: OrderStatus::Executed,
+            working_time: Some(1000),
+            trade: Some(OrderTrade {
+                id: "1".to_string(),
+                trade_time: 1000,
+                amount: buy.amount,
+                price: dec!(100),
+                fee: dec!(0),
+                fee_asset: None,
+            }),
+        });
+        state.portfolio.update_asset(Asset {
+            symbol: "BTC".to_string(),
+            amount: buy.amount,
+            locked: dec!(0),
+            value: None,
+        });
+        let Some(StrategyAction::PlaceOrder { order }) =
+            strategy.plan_level(2, &state, dec!(99), 2000, dec!(0))
+        else {
+            panic!("no sell");
+        };
+        assert_eq!((order.side, order.price), (OrderSide::Sell, dec!(105)));
+        assert_eq!(order.prev_order_id, Some(buy.id));
+        assert_eq!(order.session_id, buy.session_id);
+    }
+
+    #[tokio::test]
+    async fn test_grid_sell_step() {
+        let ticker = Ticker::new("BTC", "USDC");
+        let state = Arc::new(RwLock::new(State::new()));
+        let exchange_info = serde_json::from_value(serde_json::json!({
+            "symbols": [{
+                "symbol": "BTCUSDC",
+                "baseAsset": "BTC",
+                "quoteAsset": "USDC",
+                "baseAssetPrecision": 8,
+                "quoteAssetPrecision": 8,
+                "filters": [
+                    {"filterType": "PRICE_FILTER", "minPrice": "0.01", "maxPrice": "1000000", "tickSize": "0.01"},
+                    {"filterType": "LOT_SIZE", "minQty": "0.001", "maxQty": "9000", "stepSize": "0.001"},
+                    {"filterType": "NOTIONAL", "minNotional": "5"}
+                ]
+            }]
+        }))
+        .unwrap();
+        let mut strategy = GridStrategy::new(
+            state.clone(),
+            Binance::default().with_exchange_info(exchange_info),
+            ticker.clone(),
+            GridParams {
+                lower: Some(dec!(90)),
+                upper: Some(dec!(110)),
+                levels: 4,
+                quote_amount: dec!(12.345),
+                ..Default::default()
+            },
+        );
+        strategy.prices = strategy.grid_prices(dec!(101));
+
+        // a buy of level 2 filled for an amount off the step
+        {
+            let mut state = state.write().await;
+            for (symbol, amount) in [("USDC", dec!(1000)), ("BTC", dec!(0.12345))] {
+                state.portfolio.update_asset(Asset {
+                    symbol: symbol.to_string(),
+                    amount,
+                    locked: dec!(0),
+                    value: None,
+                });
+            }
+            let Some(StrategyAction::PlaceOrder { order }) =
+                strategy.plan_level(2, &state, dec!(101), 0, dec!(0))
+            else {
+                panic!("no buy");
+            };
+            let buy = state.add_order(order).unwrap();
+            state.update_order(MarketplaceOrderUpdate {
+                time: 1000,
+                update_type: "TRADE".to_string(),
+                marketplace_id: "1".to_string(),
+                client_id: buy.id.clone(),
+                status: OrderStatus::Executed,
+                working_time: Some(1000),
+                trade: Some(OrderTrade {
+                    id: "1".to_string(),
+                    trade_time: 1000,
+                    amount: dec!(0.12345),
+                    price: dec!(100),
+                    fee: dec!(0),
+                    fee_asset: Some("BNB".to_string()),
+                }),
+            });
+        }
+
+        let (tx_app, mut rx_app) = tokio::sync::broadcast::channel(16);
+        let book = MarketplaceBook {
+            ticker,
+            first_update_id: 0,
+            final_update_id: 0,
+            time: 2000,
+            bids: vec![(dec!(99), dec!(1))],
+            asks: vec![(dec!(99.05), dec!(1))],
+        };
+        strategy.on_depth_event(&book, &tx_app).await.unwrap();
+        let sell = std::iter::from_fn(|| rx_app.try_recv().ok())
+            .find_map(|event| match event {
+                AppEvent::Strategy(StrategyEvent::Action(action)) => match *action {
+                    StrategyAction::PlaceOrder { order } if order.side == OrderSide::Sell => {
+                        Some(order)
+                    }
+                    _ => None,
+                },
+                _ => None,
+            })
+            .expect("no sell");
+        assert_eq!((sell.amount, sell.price), (dec!(0.123), dec!(105)));
+    }
+
+    #[tokio::test]
+    async fn test_grid_restart_and_stop() {
+        let ticker = Ticker::new("BTC", "USDC");
+        let state = Arc::new(RwLock::new(State::new()));
+        // orders of a previous grid : a buy at level 1 and a sell of level 3
+        for (side, level, price, status) in [
+            (OrderSide::Buy, 1, dec!(95), OrderStatus::Active),
+            (OrderSide::Sell, 3, dec!(110), OrderStatus::Executed),
+        ] {
+            let mut order = match side {
+                OrderSide::Buy => Order::new_buy(ticker.clone(), dec!(1), price, price, 0, None),
+                OrderSide::Sell => Order::new_sell(ticker.clone(), dec!(1), price, 0, None),
+            };
+            order.session_id = Some(format!("0b5e-{level}"));
+            order.strategy = Some("grid".to_string());
+            order.status = status;
+            state.write().await.orders.push(order);
+        }
+
+        let mut strategy = GridStrategy::new(
+            state.clone(),
+            Binance::default(),
+            ticker.clone(),
+            GridParams {
+                levels: 4,
+                ..Default::default()
+            },
+        );
+        strategy.init(None).await.unwrap();
+        assert_eq!(strategy.grid_id, "0b5e");
+        assert_eq!(
+            strategy.prices,
+            vec![dec!(90), dec!(95), dec!(100), dec!(105), dec!(110)]
+        );
+
+        // the stop cancels the open orders before ending
+        let (tx_app, mut rx_app) = tokio::sync::broadcast::channel(16);
+        let book = MarketplaceBook {
+            ticker,
+            first_update_id: 0,
+            final_update_id: 0,
+            time: 0,
+            bids: vec![(dec!(100), dec!(1))],
+            asks: vec![(dec!(100.05), dec!(1))],
+        };
+        strategy.on_command(StrategyCommand::Stop).await;
+        strategy.on_depth_event(&book, &tx_app).await.unwrap();
+        assert_eq!(strategy.status(), StrategyStatus::Stopping);
+        let Ok(AppEvent::Strategy(StrategyEvent::Action(action))) = rx_app.try_recv() else {
+            panic!("no action");
+        };
+        let StrategyAction::Cancel { order_id, .. } = *action else {
+            panic!("no cancel");
+        };
+        assert_eq!(order_id, state.read().await.orders[0].id);
+
+        state.write().await.orders[0].status = OrderStatus::Cancelled;
+        strategy.on_depth_event(&book, &tx_app).await.unwrap();
+        assert_eq!(strategy.status(), StrategyStatus::Stopped);
+    }
+}
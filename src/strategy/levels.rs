@@ -0,0 +1,273 @@
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
+
+use crate::marketplace::candle::CandleInterval;
+use crate::marketplace::MarketplaceCandle;
+
+const DAY_MS: u64 = 24 * 3_600_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LevelSource {
+    SwingHigh(CandleInterval),
+    SwingLow(CandleInterval),
+    PrevDayHigh,
+    PrevDayLow,
+    PrevDayClose,
+    PrevWeekHigh,
+    PrevWeekLow,
+    Round,
+}
+
+impl LevelSource {
+    // longer horizons make stronger levels
+    fn weight(&self) -> Decimal {
+        match self {
+            LevelSource::SwingHigh(interval) | LevelSource::SwingLow(interval) => match interval {
+                CandleInterval::D1 => dec!(3),
+                CandleInterval::H4 => dec!(2),
+                _ => dec!(1),
+            },
+            LevelSource::PrevWeekHigh | LevelSource::PrevWeekLow => dec!(3),
+            LevelSource::PrevDayHigh | LevelSource::PrevDayLow | LevelSource::PrevDayClose => {
+                dec!(2)
+            }
+            LevelSource::Round => dec!(1),
+        }
+    }
+}
+
+// Support or resistance, depending on the side of the price
+#[derive(Clone, Debug, PartialEq)]
+pub struct Level {
+    pub price: Decimal,
+    pub source: LevelSource,
+    pub touches: u32,
+    pub last_touch: u64,
+}
+
+impl Level {
+    fn new(price: Decimal, source: LevelSource, time: u64) -> Self {
+        Self {
+            price,
+            source,
+            touches: 1,
+            last_touch: time,
+        }
+    }
+
+    // Touches weighted by the horizon of the level, fading with the days since the last touch
+    pub fn strength(&self, time: u64) -> Decimal {
+        let age_days = Decimal::from(time.saturating_sub(self.last_touch) / DAY_MS);
+        Decimal::from(self.touches) * self.source.weight() / (dec!(1) + age_days / dec!(30))
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LevelParams {
+    // swings closer than this ratio of the price are touches of the same level
+    pub tolerance: Decimal,
+    // round numbers around the price, multiples of the step
+    pub round_step: Option<Decimal>,
+}
+
+impl Default for LevelParams {
+    fn default() -> Self {
+        Self {
+            tolerance: dec!(0.003),
+            round_step: None,
+        }
+    }
+}
+
+fn add_touch(
+    levels: &mut Vec<Level>,
+    price: Decimal,
+    source: LevelSource,
+    time: u64,
+    tolerance: Decimal,
+) {
+    match levels
+        .iter_mut()
+        .find(|level| (level.price - price).abs() <= price * tolerance)
+    {
+        Some(level) => {
+            let touches = Decimal::from(level.touches);
+            level.price = (level.price * touches + price) / (touches + dec!(1));
+            level.touches += 1;
+            level.last_touch = level.last_touch.max(time);
+        }
+        None => levels.push(Level::new(price, source, time)),
+    }
+}
+
+// Swing highs and lows of the candles (latest first), clustered into levels
+pub fn swing_levels(candles: &[MarketplaceCandle], tolerance: Decimal) -> Vec<Level> {
+    let mut levels = vec![];
+    for window in candles.windows(3) {
+        let (next, candle, prev) = (&window[0], &window[1], &window[2]);
+        if candle.high_price > prev.high_price && candle.high_price > next.high_price {
+            add_touch(
+                &mut levels,
+                candle.high_price,
+                LevelSource::SwingHigh(candle.interval),
+                candle.start_time,
+                tolerance,
+            );
+        }
+        if candle.low_price < prev.low_price && candle.low_price < next.low_price {
+            add_touch(
+                &mut levels,
+                candle.low_price,
+                LevelSource::SwingLow(candle.interval),
+                candle.start_time,
+                tolerance,
+            );
+        }
+    }
+    levels
+}
+
+// High, low and close of the previous day, high and low of the previous week (from monday).
+// daily: latest first
+pub fn previous_levels(daily: &[MarketplaceCandle], time: u64) -> Vec<Level> {
+    let mut levels = vec![];
+    let today = CandleInterval::D1.start_of(time);
+    if let Some(day) = daily.iter().find(|candle| candle.start_time < today) {
+        levels.push(Level::new(
+            day.high_price,
+            LevelSource::PrevDayHigh,
+            day.start_time,
+        ));
+        levels.push(Level::new(
+            day.low_price,
+            LevelSource::PrevDayLow,
+            day.start_time,
+        ));
+        levels.push(Level::new(
+            day.close_price,
+            LevelSource::PrevDayClose,
+            day.start_time,
+        ));
+    }
+
+    // 1970-01-01 was a thursday
+    let week = today - ((today / DAY_MS + 3) % 7) * DAY_MS;
+    let prev_week: Vec<&MarketplaceCandle> = daily
+        .iter()
+        .filter(|candle| candle.start_time < week && candle.start_time >= week - 7 * DAY_MS)
+        .collect();
+    if let (Some(high), Some(low)) = (
+        prev_week.iter().max_by_key(|candle| candle.high_price),
+        prev_week.iter().min_by_key(|candle| candle.low_price),
+    ) {
+        levels.push(Level::new(
+            high.high_price,
+            LevelSource::PrevWeekHigh,
+            high.start_time,
+        ));
+        levels.push(Level::new(
+            low.low_price,
+            LevelSource::PrevWeekLow,
+            low.start_time,
+        ));
+    }
+    levels
+}
+
+// Round numbers just under and above the price
+pub fn round_levels(price: Decimal, step: Decimal, time: u64) -> Vec<Level> {
+    if step <= dec!(0) {
+        return vec![];
+    }
+    let below = (price / step).floor() * step;
+    vec![
+        Level::new(below, LevelSource::Round, time),
+        Level::new(below + step, LevelSource::Round, time),
+    ]
+}
+
+// Levels of the daily and 4h candles (latest first) around the price, sorted by price
+pub fn find_levels(
+    daily: &[MarketplaceCandle],
+    four_hours: &[MarketplaceCandle],
+    price: Decimal,
+    time: u64,
+    params: &LevelParams,
+) -> Vec<Level> {
+    let mut levels = swing_levels(daily, params.tolerance);
+    levels.append(&mut swing_levels(four_hours, params.tolerance));
+    levels.append(&mut previous_levels(daily, time));
+    if let Some(step) = params.round_step {
+        levels.append(&mut round_levels(price, step, time));
+    }
+    levels.sort_by_key(|level| level.price);
+    levels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ticker::Ticker;
+
+    fn candle(start_time: u64, high: Decimal, low: Decimal) -> MarketplaceCandle {
+        MarketplaceCandle {
+            ticker: Ticker::new("BTC", "USDC"),
+            interval: CandleInterval::D1,
+            open_price: low,
+            close_price: high,
+            high_price: high,
+            low_price: low,
+            trade_count: 0,
+            start_time,
+            close_time: start_time + DAY_MS - 1,
+            volume: dec!(1),
+            closed: true,
+        }
+    }
+
+    #[test]
+    fn test_levels() {
+        // 2025-01-06 is a monday, 10 days from 2025-01-01, latest first
+        let start = 1_735_689_600_000;
+        let highs = [100, 104, 101, 99, 105, 100, 98, 102, 97, 103];
+        let daily: Vec<MarketplaceCandle> = highs
+            .iter()
+            .enumerate()
+            .map(|(day, high)| {
+                let high = Decimal::from(*high);
+                candle(start + day as u64 * DAY_MS, high, high - dec!(5))
+            })
+            .rev()
+            .collect();
+        let time = start + 10 * DAY_MS + 1000;
+
+        let levels = swing_levels(&daily, dec!(0.01));
+        let resistance = levels
+            .iter()
+            .find(|level| {
+                level.source == LevelSource::SwingHigh(CandleInterval::D1) && level.touches > 1
+            })
+            .unwrap();
+        // 104 and 105 are the same level, touched twice
+        assert_eq!(resistance.price, dec!(104.5));
+        assert_eq!(resistance.last_touch, start + 4 * DAY_MS);
+        assert!(
+            resistance.strength(time)
+                > Level::new(dec!(102), LevelSource::SwingHigh(CandleInterval::D1), start)
+                    .strength(time)
+        );
+
+        let previous = previous_levels(&daily, time);
+        assert_eq!(previous[0].source, LevelSource::PrevDayHigh);
+        assert_eq!(previous[0].price, dec!(103));
+        // the week of 2024-12-30, from 01-01 to 01-05
+        assert_eq!(previous[3].price, dec!(105));
+        assert_eq!(previous[4].price, dec!(94));
+
+        let levels = round_levels(dec!(101.5), dec!(5), time);
+        assert_eq!(levels[0].price, dec!(100));
+        assert_eq!(levels[1].price, dec!(105));
+    }
+}
@@ -1,22 +1,52 @@
-use crate::{order::Order, ticker::Ticker, AppEvent};
+use crate::{
+    indicators::IndicatorKind,
+    marketplace::{candle::CandleInterval, MarketplaceEvent},
+    order::Order,
+    ticker::Ticker,
+    AppEvent,
+};
 use anyhow::Result;
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
-use tokio::sync::broadcast::Sender;
+use std::future::Future;
+use tokio::sync::broadcast::{error::RecvError, Sender};
+use tokio::sync::mpsc::Receiver;
+use tracing::{debug, warn};
 
+pub mod dca;
+pub mod grid;
+pub mod levels;
+pub mod registry;
+pub mod rules;
 pub mod scalping;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum StrategyEvent {
-    Action(StrategyAction),
+    // boxed, the order makes it much larger than the other events
+    Action(Box<StrategyAction>),
+    // sent when a strategy starts and after each command
+    Status {
+        ticker: Ticker,
+        name: String,
+        status: StrategyStatus,
+        params: serde_json::Value,
+    },
+    // the strategy indicator values, with the forming candle, after each candle update
+    Indicators {
+        ticker: Ticker,
+        interval: CandleInterval,
+        values: Vec<(IndicatorKind, Decimal)>,
+    },
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum StrategyStatus {
     New,
     Initializing,
     Running,
     Paused,
     Stopping,
-    Stopped
+    Stopped,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -42,9 +72,96 @@ pub enum StrategyAction {
     },
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StrategyCommand {
+    // the book is ignored until resumed, so no orders are placed; the pending orders are kept
+    Pause,
+    Resume,
+    Stop,
+}
+
 pub trait Strategy {
+    // Name of the strategy in the registry
+    fn name(&self) -> &'static str;
+
+    fn ticker(&self) -> &Ticker;
+
+    // Candle intervals needed by the strategy, aggregated from the 1m candles.
+    fn intervals(&self) -> Vec<CandleInterval> {
+        vec![CandleInterval::M1]
+    }
+
+    // Load the history up to start_time (now when None).
+    fn init(&mut self, start_time: Option<u64>) -> impl Future<Output = Result<()>>;
+
+    fn on_event(
+        &mut self,
+        event: &AppEvent,
+        tx_app: &Sender<AppEvent>,
+    ) -> impl Future<Output = Result<()>>;
+
+    fn on_command(&mut self, command: StrategyCommand) -> impl Future<Output = ()>;
+
+    fn status(&self) -> StrategyStatus;
+
+    fn params(&self) -> serde_json::Value;
+
+    // Feed the app events to the strategy until it is stopped.
     fn start(
         &mut self,
         tx_app: Sender<AppEvent>,
-    ) -> impl std::future::Future<Output = ()>;
+        mut rx_cmd: Receiver<StrategyCommand>,
+    ) -> impl Future<Output = ()> {
+        async move {
+            let mut rx_app = tx_app.subscribe();
+            let mut commands_open = true;
+            self.send_status(&tx_app);
+            while self.status() != StrategyStatus::Stopped {
+                tokio::select! {
+                    command = rx_cmd.recv(), if commands_open => match command {
+                        Some(command) => {
+                            self.on_command(command).await;
+                            self.send_status(&tx_app);
+                        }
+                        None => commands_open = false,
+                    },
+                    event = rx_app.recv() => match event {
+                        Ok(event) => {
+                            // paused, the candles still come so the history stays in sync
+                            let active = match self.status() {
+                                StrategyStatus::Running | StrategyStatus::Stopping => true,
+                                StrategyStatus::Paused => !matches!(
+                                    event,
+                                    AppEvent::MarketPlace(MarketplaceEvent::Book(_))
+                                ),
+                                _ => false,
+                            };
+                            if active {
+                                let status = self.status();
+                                if let Err(err) = self.on_event(&event, &tx_app).await {
+                                    debug!("{} strategy event failed : {}", self.ticker(), err);
+                                }
+                                if self.status() != status {
+                                    self.send_status(&tx_app);
+                                }
+                            }
+                        }
+                        Err(RecvError::Lagged(n)) => {
+                            warn!("{} strategy lagged, {} events lost", self.ticker(), n)
+                        }
+                        Err(RecvError::Closed) => break,
+                    },
+                }
+            }
+        }
+    }
+
+    fn send_status(&self, tx_app: &Sender<AppEvent>) {
+        let _ = tx_app.send(AppEvent::Strategy(StrategyEvent::Status {
+            ticker: self.ticker().clone(),
+            name: self.name().to_string(),
+            status: self.status(),
+            params: self.params(),
+        }));
+    }
 }
@@ -0,0 +1,282 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::Sender;
+use tokio::sync::RwLock;
+
+use super::dca::{DcaParams, DcaStrategy};
+use super::grid::{GridParams, GridStrategy};
+use super::scalping::{ScalpingParams, ScalpingStrategy};
+use super::{Strategy, StrategyCommand, StrategyStatus};
+use crate::marketplace::candle::CandleInterval;
+use crate::marketplace::{Marketplace, MarketplaceDataApi, MarketplaceSettingsApi};
+use crate::state::State;
+use crate::ticker::Ticker;
+use crate::AppEvent;
+
+// Names accepted by --strategy and the strategy config files
+pub const STRATEGIES: &[&str] = &["scalping", "grid", "dca"];
+
+// Strategy of a ticker, params missing from the config keep their default value.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StrategyConfig {
+    pub symbol: String,
+    pub strategy: String,
+    #[serde(default)]
+    pub params: serde_json::Value,
+}
+
+// Strategies chosen by ticker, `default` for the tickers not listed.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StrategyConfigs {
+    pub default: String,
+    #[serde(default)]
+    pub tickers: Vec<StrategyConfig>,
+    // params by strategy name, under the params of the ticker configs
+    #[serde(default)]
+    pub defaults: HashMap<String, serde_json::Value>,
+}
+
+impl StrategyConfigs {
+    pub fn new(default: String) -> Self {
+        Self {
+            default,
+            tickers: Vec::new(),
+            defaults: HashMap::new(),
+        }
+    }
+
+    pub fn with_defaults(mut self, strategy: &str, params: serde_json::Value) -> Self {
+        self.defaults.insert(strategy.to_string(), params);
+        self
+    }
+
+    // Ticker configs of a json file, a list of StrategyConfig.
+    pub fn with_file(mut self, path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read {}", path.display()))?;
+        self.tickers = serde_json::from_str(&content)
+            .with_context(|| format!("Invalid strategy config {}", path.display()))?;
+        Ok(self)
+    }
+
+    pub fn get(&self, ticker: &Ticker) -> StrategyConfig {
+        let symbol = ticker.to_string();
+        let mut config = self
+            .tickers
+            .iter()
+            .find(|config| config.symbol == symbol)
+            .cloned()
+            .unwrap_or_else(|| StrategyConfig {
+                symbol,
+                strategy: self.default.clone(),
+                params: serde_json::Value::Null,
+            });
+        if let Some(serde_json::Value::Object(defaults)) = self.defaults.get(&config.strategy) {
+            let mut params = defaults.clone();
+            if let serde_json::Value::Object(own) = config.params {
+                params.extend(own);
+            }
+            config.params = serde_json::Value::Object(params);
+        }
+        config
+    }
+}
+
+fn parse_params<P: for<'de> Deserialize<'de> + Default>(params: &serde_json::Value) -> Result<P> {
+    if params.is_null() {
+        return Ok(P::default());
+    }
+    Ok(serde_json::from_value(params.clone())?)
+}
+
+// Strategies of the registry, so they can be chosen at runtime.
+// built once by ticker, the size of the variants does not matter
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug)]
+pub enum AnyStrategy<M> {
+    Scalping(ScalpingStrategy<M>),
+    Grid(GridStrategy<M>),
+    Dca(DcaStrategy<M>),
+}
+
+impl<M> AnyStrategy<M>
+where
+    M: Marketplace + MarketplaceSettingsApi + MarketplaceDataApi,
+{
+    pub fn build(
+        config: &StrategyConfig,
+        state: Arc<RwLock<State>>,
+        marketplace: M,
+        ticker: Ticker,
+    ) -> Result<Self> {
+        let strategy = match config.strategy.as_str() {
+            "scalping" => Self::Scalping(ScalpingStrategy::new(
+                state,
+                marketplace,
+                ticker,
+                parse_params::<ScalpingParams>(&config.params)
+                    .with_context(|| format!("Invalid scalping params for {}", config.symbol))?,
+            )),
+            "grid" => Self::Grid(GridStrategy::new(
+                state,
+                marketplace,
+                ticker,
+                parse_params::<GridParams>(&config.params)
+                    .with_context(|| format!("Invalid grid params for {}", config.symbol))?,
+            )),
+            "dca" => Self::Dca(DcaStrategy::new(
+                state,
+                marketplace,
+                ticker,
+                parse_params::<DcaParams>(&config.params)
+                    .with_context(|| format!("Invalid dca params for {}", config.symbol))?,
+            )),
+            other => bail!(
+                "Unknown strategy {other}, available : {}",
+                STRATEGIES.join(", ")
+            ),
+        };
+        Ok(strategy)
+    }
+}
+
+impl<M> Strategy for AnyStrategy<M>
+where
+    M: Marketplace + MarketplaceSettingsApi + MarketplaceDataApi,
+{
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Scalping(strategy) => strategy.name(),
+            Self::Grid(strategy) => strategy.name(),
+            Self::Dca(strategy) => strategy.name(),
+        }
+    }
+
+    fn ticker(&self) -> &Ticker {
+        match self {
+            Self::Scalping(strategy) => strategy.ticker(),
+            Self::Grid(strategy) => strategy.ticker(),
+            Self::Dca(strategy) => strategy.ticker(),
+        }
+    }
+
+    fn intervals(&self) -> Vec<CandleInterval> {
+        match self {
+            Self::Scalping(strategy) => strategy.intervals(),
+            Self::Grid(strategy) => strategy.intervals(),
+            Self::Dca(strategy) => strategy.intervals(),
+        }
+    }
+
+    async fn init(&mut self, start_time: Option<u64>) -> Result<()> {
+        match self {
+            Self::Scalping(strategy) => strategy.init(start_time).await,
+            Self::Grid(strategy) => strategy.init(start_time).await,
+            Self::Dca(strategy) => strategy.init(start_time).await,
+        }
+    }
+
+    async fn on_event(&mut self, event: &AppEvent, tx_app: &Sender<AppEvent>) -> Result<()> {
+        match self {
+            Self::Scalping(strategy) => strategy.on_event(event, tx_app).await,
+            Self::Grid(strategy) => strategy.on_event(event, tx_app).await,
+            Self::Dca(strategy) => strategy.on_event(event, tx_app).await,
+        }
+    }
+
+    async fn on_command(&mut self, command: StrategyCommand) {
+        match self {
+            Self::Scalping(strategy) => strategy.on_command(command).await,
+            Self::Grid(strategy) => strategy.on_command(command).await,
+            Self::Dca(strategy) => strategy.on_command(command).await,
+        }
+    }
+
+    fn status(&self) -> StrategyStatus {
+        match self {
+            Self::Scalping(strategy) => strategy.status(),
+            Self::Grid(strategy) => strategy.status(),
+            Self::Dca(strategy) => strategy.status(),
+        }
+    }
+
+    fn params(&self) -> serde_json::Value {
+        match self {
+            Self::Scalping(strategy) => strategy.params(),
+            Self::Grid(strategy) => strategy.params(),
+            Self::Dca(strategy) => strategy.params(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::marketplace::binance::Binance;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_strategy_configs() {
+        let configs = StrategyConfigs {
+            default: "scalping".to_string(),
+            tickers: serde_json::from_str(
+                r#"[{"symbol": "ETHUSDC", "strategy": "scalping", "params": {"target_profit": 0.5, "reentry_delay": 60}}]"#,
+            )
+            .unwrap(),
+            defaults: HashMap::new(),
+        };
+        let state = Arc::new(RwLock::new(State::new()));
+
+        let eth = Ticker::new("ETH", "USDC");
+        let strategy = AnyStrategy::build(
+            &configs.get(&eth),
+            state.clone(),
+            Binance::default(),
+            eth.clone(),
+        )
+        .unwrap();
+        let params: ScalpingParams = serde_json::from_value(strategy.params()).unwrap();
+        assert_eq!(params.target_profit, dec!(0.5));
+        assert_eq!(params.reentry_delay.as_secs(), 60);
+        assert_eq!(
+            params.session_count,
+            ScalpingParams::default().session_count
+        );
+
+        let btc = Ticker::new("BTC", "USDC");
+        let strategy = AnyStrategy::build(
+            &configs.get(&btc),
+            state.clone(),
+            Binance::default(),
+            btc.clone(),
+        )
+        .unwrap();
+        assert_eq!(strategy.name(), "scalping");
+        assert_eq!(strategy.status(), StrategyStatus::New);
+
+        // the ticker params go over the defaults of the strategy
+        let configs = configs.with_defaults(
+            "scalping",
+            serde_json::json!({"target_profit": 0.3, "session_count": 1}),
+        );
+        let params: ScalpingParams = serde_json::from_value(configs.get(&eth).params).unwrap();
+        assert_eq!(params.target_profit, dec!(0.5));
+        assert_eq!(params.session_count, 1);
+        let params: ScalpingParams = serde_json::from_value(configs.get(&btc).params).unwrap();
+        assert_eq!(params.target_profit, dec!(0.3));
+
+        let mut config = configs.get(&Ticker::new("BNB", "USDC"));
+        config.strategy = "unknown".to_string();
+        assert!(AnyStrategy::build(
+            &config,
+            state,
+            Binance::default(),
+            Ticker::new("BNB", "USDC")
+        )
+        .is_err());
+    }
+}
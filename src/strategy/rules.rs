@@ -0,0 +1,455 @@
+use std::collections::VecDeque;
+use std::fmt::Display;
+
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use super::scalping::{PriceStats, PriceTrend};
+use crate::indicators::catalogue::{IndicatorOutput, IndicatorValue, NamedIndicator};
+use crate::marketplace::{MarketplaceBook, MarketplaceCandle};
+use crate::state::State;
+use crate::ticker::Ticker;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    Entry,
+    Reentry,
+    Exit,
+}
+
+impl Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let phase = match self {
+            Phase::Entry => "Entry",
+            Phase::Reentry => "Reentry",
+            Phase::Exit => "Exit",
+        };
+        write!(f, "{phase}")
+    }
+}
+
+// What a rule can look at before an order of a phase is placed
+pub struct RuleContext<'a> {
+    pub ticker: &'a Ticker,
+    pub stats: &'a PriceStats,
+    pub state: &'a State,
+    // price of the order about to be placed
+    pub price: Decimal,
+    pub book: &'a MarketplaceBook,
+    // 1m candles, the latest first
+    pub candles: &'a VecDeque<MarketplaceCandle>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RuleResult {
+    Pass,
+    Ignore {
+        reason: String,
+        details: Option<String>,
+    },
+}
+
+impl RuleResult {
+    fn ignore(reason: &str, details: Option<String>) -> Self {
+        Self::Ignore {
+            reason: reason.to_string(),
+            details,
+        }
+    }
+}
+
+// Checks of a phase, all of them must pass for the order to be placed.
+// In the strategy params as {"rule": "max_spread", "max": 0.001}.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "rule", rename_all = "snake_case")]
+pub enum Rule {
+    // long trend down or crashing, unless the short trend is bull
+    Downtrend,
+    // short trend bull, the price may still go up
+    HoldBull,
+    // price under the long support
+    BelowLongSupport,
+    // price under the short or long resistance
+    BelowShortResistance,
+    BelowLongResistance,
+    // (ask - bid) / bid above max
+    MaxSpread {
+        max: Decimal,
+    },
+    // base volume of the last candles (1m) under min
+    MinVolume {
+        min: Decimal,
+        candles: usize,
+    },
+    // price over the session (or anchored) VWAP + std_devs x its standard deviation
+    AboveVwap {
+        #[serde(default)]
+        std_devs: Decimal,
+        #[serde(default)]
+        anchored: bool,
+    },
+    // price under the session (or anchored) VWAP - std_devs x its standard deviation
+    BelowVwap {
+        #[serde(default)]
+        std_devs: Decimal,
+        #[serde(default)]
+        anchored: bool,
+    },
+    // a daily / 4h level of min_strength above the price, closer than within (ratio of the price)
+    BelowMajorResistance {
+        within: Decimal,
+        min_strength: Decimal,
+    },
+    // output of an indicator of the catalogue (closed 1m candles) under min or over max,
+    // {"rule": "indicator", "indicator": {"name": "rsi", "period": 14}, "max": 70}
+    Indicator {
+        indicator: NamedIndicator,
+        #[serde(default)]
+        output: IndicatorOutput,
+        min: Option<Decimal>,
+        max: Option<Decimal>,
+    },
+}
+
+// Rules of the configs, the outputs must be ones of their indicator
+pub fn deserialize_rules<'de, D>(deserializer: D) -> Result<Vec<Rule>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let rules = Vec::<Rule>::deserialize(deserializer)?;
+    for rule in rules.iter() {
+        if let Rule::Indicator {
+            indicator, output, ..
+        } = rule
+        {
+            if !indicator.outputs().contains(output) {
+                return Err(serde::de::Error::custom(format!(
+                    "{output} is not an output of {indicator:?}, available : {:?}",
+                    indicator.outputs()
+                )));
+            }
+        }
+    }
+    Ok(rules)
+}
+
+// Values of the indicators read by the rules, over the closed candles (latest first).
+// Computed once by closed candle, the rules read them from the price stats.
+pub fn indicator_values<'a>(
+    rules: impl IntoIterator<Item = &'a Rule>,
+    closed: impl Iterator<Item = &'a MarketplaceCandle> + Clone,
+) -> Vec<(NamedIndicator, IndicatorValue)> {
+    let mut values: Vec<(NamedIndicator, IndicatorValue)> = Vec::new();
+    for rule in rules {
+        if let Rule::Indicator { indicator, .. } = rule {
+            if values.iter().any(|(named, _)| named == indicator) {
+                continue;
+            }
+            if let Some(value) = indicator.compute(closed.clone()) {
+                values.push((indicator.clone(), value));
+            }
+        }
+    }
+    values
+}
+
+impl Rule {
+    // Rules reading the trends, supports, resistances, VWAPs or levels of the price stats
+    pub fn needs_price_stats(&self) -> bool {
+        !matches!(
+            self,
+            Rule::MaxSpread { .. } | Rule::MinVolume { .. } | Rule::Indicator { .. }
+        )
+    }
+
+    pub fn check(&self, ctx: &RuleContext) -> RuleResult {
+        let stats = ctx.stats;
+        match self {
+            Rule::Downtrend => {
+                if matches!(
+                    stats.long_trend,
+                    Some(PriceTrend::Crash) | Some(PriceTrend::Down)
+                ) && !matches!(stats.short_trend, Some(PriceTrend::Bull))
+                {
+                    return RuleResult::ignore("price down", None);
+                }
+            }
+            Rule::HoldBull => {
+                if matches!(stats.short_trend, Some(PriceTrend::Bull)) {
+                    return RuleResult::ignore("hold bull", None);
+                }
+            }
+            Rule::BelowLongSupport => {
+                if let Some(support) = stats.long_support.filter(|support| *support > ctx.price) {
+                    return RuleResult::ignore("price < long support", Some(support.to_string()));
+                }
+            }
+            Rule::BelowShortResistance => {
+                if let Some(resistance) = stats
+                    .short_resistance
+                    .filter(|resistance| *resistance > ctx.price)
+                {
+                    return RuleResult::ignore(
+                        "price < short resistance",
+                        Some(resistance.to_string()),
+                    );
+                }
+            }
+            Rule::BelowLongResistance => {
+                if let Some(resistance) = stats
+                    .long_resistance
+                    .filter(|resistance| *resistance > ctx.price)
+                {
+                    return RuleResult::ignore(
+                        "price < long resistance",
+                        Some(resistance.to_string()),
+                    );
+                }
+            }
+            Rule::MaxSpread { max } => {
+                if let (Some(bid), Some(ask)) = (ctx.book.buy_price(), ctx.book.sell_price()) {
+                    let spread = if bid > dec!(0) {
+                        (ask - bid) / bid
+                    } else {
+                        Decimal::MAX
+                    };
+                    if spread > *max {
+                        return RuleResult::ignore("spread", Some(spread.round_dp(6).to_string()));
+                    }
+                }
+            }
+            Rule::MinVolume { min, candles } => {
+                let volume: Decimal = ctx
+                    .candles
+                    .iter()
+                    .take(*candles)
+                    .map(|candle| candle.volume)
+                    .sum();
+                if volume < *min {
+                    return RuleResult::ignore("low volume", Some(volume.to_string()));
+                }
+            }
+            Rule::AboveVwap { std_devs, anchored } => {
+                if let Some((_, upper)) = stats
+                    .vwap(*anchored)
+                    .and_then(|vwap| vwap.bands(*std_devs))
+                    .filter(|(_, upper)| ctx.price > *upper)
+                {
+                    return RuleResult::ignore("price > vwap", Some(upper.round_dp(8).to_string()));
+                }
+            }
+            Rule::BelowVwap { std_devs, anchored } => {
+                if let Some((lower, _)) = stats
+                    .vwap(*anchored)
+                    .and_then(|vwap| vwap.bands(*std_devs))
+                    .filter(|(lower, _)| ctx.price < *lower)
+                {
+                    return RuleResult::ignore("price < vwap", Some(lower.round_dp(8).to_string()));
+                }
+            }
+            Rule::BelowMajorResistance {
+                within,
+                min_strength,
+            } => {
+                if let Some(level) = stats.levels.iter().find(|level| {
+                    level.price > ctx.price
+                        && level.price <= ctx.price * (dec!(1) + within)
+                        && level.strength(ctx.book.time) >= *min_strength
+                }) {
+                    return RuleResult::ignore(
+                        "price < major resistance",
+                        Some(format!(
+                            "{} ({})",
+                            level.price.round_dp(8),
+                            level.strength(ctx.book.time).round_dp(2)
+                        )),
+                    );
+                }
+            }
+            Rule::Indicator {
+                indicator,
+                output,
+                min,
+                max,
+            } => {
+                if let Some(value) = stats
+                    .indicators
+                    .iter()
+                    .find(|(named, _)| named == indicator)
+                    .and_then(|(_, value)| value.get(*output, ctx.price))
+                {
+                    if min.is_some_and(|min| value < min) || max.is_some_and(|max| value > max) {
+                        return RuleResult::ignore(
+                            &format!(
+                                "{output} {}",
+                                serde_json::to_string(indicator).unwrap_or_default()
+                            ),
+                            Some(value.round_dp(4).to_string()),
+                        );
+                    }
+                }
+            }
+        }
+        RuleResult::Pass
+    }
+}
+
+// Rules of each phase of the scalping strategy, the defaults are its original checks.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PhaseRules {
+    #[serde(deserialize_with = "deserialize_rules")]
+    pub entry: Vec<Rule>,
+    #[serde(deserialize_with = "deserialize_rules")]
+    pub reentry: Vec<Rule>,
+    #[serde(deserialize_with = "deserialize_rules")]
+    pub exit: Vec<Rule>,
+}
+
+impl Default for PhaseRules {
+    fn default() -> Self {
+        Self {
+            entry: vec![Rule::Downtrend, Rule::BelowLongSupport],
+            reentry: vec![Rule::BelowShortResistance, Rule::BelowLongResistance],
+            exit: vec![Rule::HoldBull],
+        }
+    }
+}
+
+impl PhaseRules {
+    pub fn get(&self, phase: Phase) -> &[Rule] {
+        match phase {
+            Phase::Entry => &self.entry,
+            Phase::Reentry => &self.reentry,
+            Phase::Exit => &self.exit,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Rule> {
+        self.entry.iter().chain(&self.reentry).chain(&self.exit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::Vwap;
+
+    #[test]
+    fn test_rules() {
+        let ticker = Ticker::new("BTC", "USDC");
+        let state = State::new();
+        let book = MarketplaceBook {
+            ticker: ticker.clone(),
+            first_update_id: 0,
+            final_update_id: 0,
+            time: 0,
+            bids: vec![(dec!(100), dec!(1))],
+            asks: vec![(dec!(100.5), dec!(1))],
+        };
+        let mut session_vwap = Vwap::new(0);
+        session_vwap.add(dec!(98), dec!(1));
+        session_vwap.add(dec!(100), dec!(1));
+        let stats = PriceStats {
+            long_trend: Some(PriceTrend::Down),
+            long_support: Some(dec!(99)),
+            session_vwap: Some(session_vwap),
+            ..Default::default()
+        };
+        let candles = VecDeque::new();
+        let ctx = RuleContext {
+            ticker: &ticker,
+            stats: &stats,
+            state: &state,
+            price: dec!(100),
+            book: &book,
+            candles: &candles,
+        };
+
+        assert!(matches!(
+            Rule::Downtrend.check(&ctx),
+            RuleResult::Ignore { .. }
+        ));
+        assert_eq!(Rule::BelowLongSupport.check(&ctx), RuleResult::Pass);
+        assert_eq!(
+            Rule::MaxSpread { max: dec!(0.01) }.check(&ctx),
+            RuleResult::Pass
+        );
+        assert!(matches!(
+            Rule::MaxSpread { max: dec!(0.001) }.check(&ctx),
+            RuleResult::Ignore { .. }
+        ));
+        assert!(matches!(
+            Rule::MinVolume {
+                min: dec!(1),
+                candles: 5
+            }
+            .check(&ctx),
+            RuleResult::Ignore { .. }
+        ));
+        assert!(matches!(
+            Rule::AboveVwap {
+                std_devs: dec!(0),
+                anchored: false
+            }
+            .check(&ctx),
+            RuleResult::Ignore { .. }
+        ));
+        assert_eq!(
+            Rule::AboveVwap {
+                std_devs: dec!(1),
+                anchored: false
+            }
+            .check(&ctx),
+            RuleResult::Pass
+        );
+        // no anchored vwap yet
+        assert_eq!(
+            Rule::BelowVwap {
+                std_devs: dec!(0),
+                anchored: true
+            }
+            .check(&ctx),
+            RuleResult::Pass
+        );
+
+        // no closed candles to compute it yet
+        let rsi: Rule = serde_json::from_str(
+            r#"{"rule": "indicator", "indicator": {"name": "rsi", "period": 14}, "max": 70}"#,
+        )
+        .unwrap();
+        assert_eq!(rsi.check(&ctx), RuleResult::Pass);
+        let Rule::Indicator { indicator, .. } = &rsi else {
+            unreachable!()
+        };
+        let stats = PriceStats {
+            indicators: vec![(indicator.clone(), IndicatorValue::Rsi(dec!(75)))],
+            ..Default::default()
+        };
+        assert!(matches!(
+            rsi.check(&RuleContext {
+                stats: &stats,
+                ..ctx
+            }),
+            RuleResult::Ignore { .. }
+        ));
+
+        let rules: PhaseRules = serde_json::from_str(
+            r#"{"entry": [{"rule": "downtrend"}, {"rule": "max_spread", "max": 0.001}]}"#,
+        )
+        .unwrap();
+        assert_eq!(rules.entry[1], Rule::MaxSpread { max: dec!(0.001) });
+        assert_eq!(rules.exit, PhaseRules::default().exit);
+
+        // the outputs are checked against their indicator
+        let rules = |output: &str| {
+            serde_json::from_str::<PhaseRules>(&format!(
+                r#"{{"entry": [{{"rule": "indicator", "indicator": {{"name": "macd", "fast": 12, "slow": 26, "signal": 9}}, "output": "{output}", "min": 0}}]}}"#
+            ))
+        };
+        assert!(rules("histogram").is_ok());
+        assert!(rules("plus_di").is_err());
+        assert!(rules("unknown").is_err());
+    }
+}
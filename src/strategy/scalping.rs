@@ -1,59 +1,188 @@
 use super::StrategyAction;
+use crate::indicators::catalogue::{IndicatorValue, NamedIndicator};
+use crate::indicators::{IndicatorEngine, IndicatorKind};
+use crate::marketplace::candle::CandleInterval;
+use crate::marketplace::store::fetch_range;
 use crate::marketplace::{
     Marketplace, MarketplaceBook, MarketplaceCandle, MarketplaceDataApi, MarketplaceEvent,
-    MarketplaceSettingsApi, MarketplaceTrade,
+    MarketplaceSettingsApi, MarketplaceTrade, StreamStatus,
 };
-use crate::order::{Order, OrderSide, OrderStatus};
+use crate::order::{ExitReason, Order, OrderSide, OrderStatus, OrderType};
 use crate::state::{OrderListFilters, OrderListSort, OrderListSortBy, State};
-use crate::strategy::{Strategy, StrategyEvent};
+use crate::strategy::levels::{find_levels, Level, LevelParams};
+use crate::strategy::rules::{indicator_values, Phase, PhaseRules, RuleContext, RuleResult};
+use crate::strategy::{Strategy, StrategyCommand, StrategyEvent, StrategyStatus};
 use crate::ticker::Ticker;
-use crate::utils::{atr, find_price_clusters, sma, wsma};
+use crate::utils::{deserialize_secs, find_price_clusters, serialize_secs, Vwap};
 use crate::AppEvent;
 use anyhow::{Context, Result};
+use chrono::Utc;
 use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::broadcast::Sender;
 use tokio::sync::RwLock;
 use tracing::{debug, error, info};
 
+const WSMA_5: IndicatorKind = IndicatorKind::Wsma { period: 5 };
+const WSMA_14: IndicatorKind = IndicatorKind::Wsma { period: 14 };
+const WSMA_120: IndicatorKind = IndicatorKind::Wsma { period: 120 };
+const ATR_14: IndicatorKind = IndicatorKind::Atr { period: 14 };
+const ATR_120: IndicatorKind = IndicatorKind::Atr { period: 120 };
+const INDICATORS: [IndicatorKind; 5] = [WSMA_5, WSMA_14, WSMA_120, ATR_14, ATR_120];
+// gap between two wsmas of a bull or crash trend, the Wilder averages lag about 1.6 times
+// more than the windowed ones the former 1% was set for
+const TREND_GAP: Decimal = dec!(0.016);
+
 #[derive(Clone, Debug)]
 pub struct ScalpingStrategy<M> {
     marketplace: M,
     ticker: Ticker,
     state: Arc<RwLock<State>>,
     trade_event_history: Arc<RwLock<VecDeque<MarketplaceTrade>>>,
-    candle_event_history: Arc<RwLock<VecDeque<MarketplaceCandle>>>,
+    candle_event_history: Arc<RwLock<HashMap<CandleInterval, VecDeque<MarketplaceCandle>>>>,
     price_stats: Arc<RwLock<PriceStats>>,
+    // 1m indicators, warmed up with the loaded history
+    indicators: IndicatorEngine,
+    // highest price since the entry of the open positions, by buy order id
+    highest_prices: Arc<RwLock<HashMap<String, Decimal>>>,
     initialized: bool,
+    // false while the market data stream is reconnecting or backfilling
+    stream_ready: bool,
+    status: StrategyStatus,
     params: ScalpingParams,
+    // vwap of the current UTC day, and since params.vwap.anchor
+    session_vwap: Vwap,
+    anchored_vwap: Option<Vwap>,
+    // entry cancelled to be placed again at the best bid
+    repricing: Option<Order>,
+    reprices: u32,
 }
 
-#[derive(Clone, Debug)]
+// Durations in seconds
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ScalpingParams {
     pub target_profit: Decimal,
     pub quote_amount: Decimal,
+    #[serde(
+        serialize_with = "serialize_secs",
+        deserialize_with = "deserialize_secs"
+    )]
     pub entry_delay: Duration,
+    #[serde(
+        serialize_with = "serialize_secs",
+        deserialize_with = "deserialize_secs"
+    )]
     pub reentry_delay: Duration,
     pub session_count: u8,
+    #[serde(
+        serialize_with = "serialize_secs",
+        deserialize_with = "deserialize_secs"
+    )]
     pub session_profit_lifetime: Duration,
+    // checks before the orders of each phase
+    pub rules: PhaseRules,
+    pub stops: StopParams,
+    // Market, Limit or LimitMaker, the stops are always market sells
+    pub entry_order: OrderType,
+    pub exit_order: OrderType,
+    // limit entries above the best bid, ratio of the bid
+    pub entry_improve: Decimal,
+    // seconds before an unfilled limit entry follows the best bid (0 to keep it),
+    // it is abandoned after max_reprices
+    pub reprice_after: u64,
+    pub max_reprices: u32,
+    pub vwap: VwapParams,
+    // daily and 4h support / resistance levels
+    pub levels: LevelParams,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum VwapSource {
+    // typical price and volume of the 1m candles
+    #[default]
+    Candles,
+    // trade prints, the trade or aggTrade streams must be enabled
+    Trades,
+}
+
+// VWAPs used by the above_vwap / below_vwap rules
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VwapParams {
+    pub source: VwapSource,
+    // start of the anchored VWAP, timestamp in ms
+    pub anchor: Option<u64>,
+}
+
+// Loss exits of a position, none by default. Ratios of the entry price (0.05 for 5%).
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StopParams {
+    pub stop_loss: Option<Decimal>,
+    // multiple of the 14 x 1m ATR under the entry price
+    pub atr_stop: Option<Decimal>,
+    // ratio under the highest price since the entry
+    pub trailing_stop: Option<Decimal>,
+    // seconds
+    pub max_hold: Option<u64>,
+}
+
+impl Default for ScalpingParams {
+    fn default() -> Self {
+        Self {
+            target_profit: dec!(1),
+            quote_amount: dec!(100),
+            entry_delay: Duration::from_secs(3600 * 24),
+            reentry_delay: Duration::from_secs(60 * 15),
+            session_count: 2,
+            session_profit_lifetime: Duration::from_secs(3600),
+            rules: PhaseRules::default(),
+            stops: StopParams::default(),
+            entry_order: OrderType::Market,
+            exit_order: OrderType::Market,
+            entry_improve: dec!(0),
+            reprice_after: 60,
+            max_reprices: 3,
+            vwap: VwapParams::default(),
+            levels: LevelParams::default(),
+        }
+    }
 }
 
 #[derive(Clone, Debug, Default)]
-struct PriceStats {
+pub struct PriceStats {
     pub short_trend: Option<PriceTrend>,
     pub long_trend: Option<PriceTrend>,
     pub short_support: Option<Decimal>,
     pub long_support: Option<Decimal>,
     pub short_resistance: Option<Decimal>,
     pub long_resistance: Option<Decimal>,
+    pub session_vwap: Option<Vwap>,
+    pub anchored_vwap: Option<Vwap>,
+    // daily and 4h levels, sorted by price
+    pub levels: Vec<Level>,
+    // values of the indicator rules, see rules::indicator_values
+    pub indicators: Vec<(NamedIndicator, IndicatorValue)>,
+}
+
+impl PriceStats {
+    pub fn vwap(&self, anchored: bool) -> Option<&Vwap> {
+        if anchored {
+            self.anchored_vwap.as_ref()
+        } else {
+            self.session_vwap.as_ref()
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
-enum PriceTrend {
+pub enum PriceTrend {
     Up,
     Down,
     Bull,
@@ -69,41 +198,223 @@ impl<M: Marketplace + MarketplaceSettingsApi + MarketplaceDataApi> ScalpingStrat
     ) -> Self {
         Self {
             state,
-            ticker,
+            ticker: ticker.clone(),
             trade_event_history: Arc::from(RwLock::from(VecDeque::new())),
-            candle_event_history: Arc::from(RwLock::from(VecDeque::new())),
+            candle_event_history: Arc::from(RwLock::from(HashMap::new())),
             price_stats: Arc::from(RwLock::from(PriceStats::default())),
+            indicators: IndicatorEngine::new().with_indicators(
+                &ticker,
+                CandleInterval::M1,
+                &INDICATORS,
+            ),
+            highest_prices: Arc::default(),
             params,
             marketplace,
             initialized: false,
+            stream_ready: true,
+            status: StrategyStatus::New,
+            session_vwap: Vwap::default(),
+            anchored_vwap: None,
+            repricing: None,
+            reprices: 0,
         }
     }
 
-    pub async fn init(&mut self, start_time: Option<u64>) -> Result<()> {
+    fn add_vwap(&mut self, time: u64, price: Decimal, volume: Decimal) {
+        let day = time - time % (24 * 3600 * 1000);
+        if self.session_vwap.anchor != day {
+            self.session_vwap = Vwap::new(day);
+        }
+        self.session_vwap.add(price, volume);
+
+        if let Some(anchor) = self.params.vwap.anchor.filter(|anchor| time >= *anchor) {
+            self.anchored_vwap
+                .get_or_insert_with(|| Vwap::new(anchor))
+                .add(price, volume);
+        }
+    }
+
+    fn add_vwap_candle(&mut self, candle: &MarketplaceCandle) {
+        if self.params.vwap.source == VwapSource::Candles {
+            let typical_price =
+                (candle.high_price + candle.low_price + candle.close_price) / dec!(3);
+            self.add_vwap(candle.start_time, typical_price, candle.volume);
+        }
+    }
+
+    // Values of the warm engine for the tui, which has no history to compute them
+    fn send_indicators(&self, tx_app: &Sender<AppEvent>) {
+        let values = INDICATORS
+            .iter()
+            .filter_map(|kind| {
+                let value = self
+                    .indicators
+                    .peek(&self.ticker, CandleInterval::M1, *kind)?;
+                Some((*kind, value))
+            })
+            .collect();
+        let _ = tx_app.send(AppEvent::Strategy(StrategyEvent::Indicators {
+            ticker: self.ticker.clone(),
+            interval: CandleInterval::M1,
+            values,
+        }));
+    }
+
+    // Indicators of the rules over the closed 1m candles, the first one is still open
+    async fn update_indicator_stats(&self) {
+        let indicators = {
+            let history = self.candle_event_history.read().await;
+            let Some(history) = history.get(&CandleInterval::M1) else {
+                return;
+            };
+            indicator_values(self.params.rules.iter(), history.iter().skip(1))
+        };
+        self.price_stats.write().await.indicators = indicators;
+    }
+
+    // Levels around the last 1m close
+    async fn update_level_stats(&self) {
+        let levels = {
+            let history = self.candle_event_history.read().await;
+            let Some(last) = history
+                .get(&CandleInterval::M1)
+                .and_then(|history| history.front())
+            else {
+                return;
+            };
+            let candles = |interval| -> Vec<MarketplaceCandle> {
+                history
+                    .get(&interval)
+                    .map(|history| history.iter().cloned().collect())
+                    .unwrap_or_default()
+            };
+            find_levels(
+                &candles(CandleInterval::D1),
+                &candles(CandleInterval::H4),
+                last.close_price,
+                last.start_time,
+                &self.params.levels,
+            )
+        };
+        self.price_stats.write().await.levels = levels;
+    }
+
+    async fn update_vwap_stats(&self) {
+        let mut stats = self.price_stats.write().await;
+        stats.session_vwap = Some(self.session_vwap.clone());
+        stats.anchored_vwap = self.anchored_vwap.clone();
+    }
+
+    fn is_limit(order_type: OrderType) -> bool {
+        matches!(order_type, OrderType::Limit | OrderType::LimitMaker)
+    }
+
+    // Buy at the market, or rest at (or just above) the best bid
+    fn new_entry_order(
+        &self,
+        amount: Decimal,
+        book: &MarketplaceBook,
+        sell_order: Option<&Order>,
+    ) -> Option<Order> {
+        let bid = book.buy_price()?;
+        let mut order = Order::new_buy(
+            self.ticker.clone(),
+            amount,
+            bid,
+            amount * bid,
+            book.time,
+            sell_order,
+        );
+        if Self::is_limit(self.params.entry_order) {
+            let mut price = bid * (dec!(1) + self.params.entry_improve);
+            // a limit maker crossing the ask would be rejected
+            if self.params.entry_order == OrderType::LimitMaker
+                && book.sell_price().is_some_and(|ask| price >= ask)
+            {
+                price = bid;
+            }
+            order.order_type = self.params.entry_order;
+            order.price = price;
+            order.quote_amount = amount * price;
+        }
+        Some(order)
+    }
+
+    async fn load_history(&mut self, start_time: Option<u64>) -> Result<()> {
         if self.initialized {
             return Ok(());
         }
 
-        let mut candles = self
-            .marketplace
-            .get_candles(&self.ticker, "1m", None, start_time)
-            .await?;
-        let mut history = self.candle_event_history.write().await;
-        candles.reverse();
-        info!(
-            "Loaded {} candles for {}. Start={:?} End={:?}",
-            candles.len(),
-            self.ticker,
-            candles.first().map(|candle| candle.start_time),
-            candles.last().map(|candle| candle.start_time),
-        );
-        *history = VecDeque::from(candles);
+        for interval in self.intervals() {
+            let mut candles = self
+                .marketplace
+                .get_candles(&self.ticker, interval, None, start_time)
+                .await?;
+            let mut history = self.candle_event_history.write().await;
+            candles.reverse();
+            info!(
+                "Loaded {} {} candles for {}. Start={:?} End={:?}",
+                candles.len(),
+                interval,
+                self.ticker,
+                candles.first().map(|candle| candle.start_time),
+                candles.last().map(|candle| candle.start_time),
+            );
+            history.insert(interval, VecDeque::from(candles));
+        }
+
+        // the indicators restart from the loaded candles, the current one is still open,
+        // the vwaps from their day or anchor
+        self.session_vwap = Vwap::default();
+        self.anchored_vwap = None;
+        self.indicators.reset(&self.ticker);
+        let candles: Vec<MarketplaceCandle> = self
+            .candle_event_history
+            .read()
+            .await
+            .get(&CandleInterval::M1)
+            .map(|history| history.iter().skip(1).rev().cloned().collect())
+            .unwrap_or_default();
+        for candle in candles.iter() {
+            self.indicators.update(candle);
+        }
+        if self.params.vwap.source == VwapSource::Candles {
+            for candle in self.load_vwap_candles(start_time).await? {
+                self.add_vwap_candle(&candle);
+            }
+        }
+        self.update_vwap_stats().await;
+        self.update_level_stats().await;
+        self.update_indicator_stats().await;
 
         self.initialized = true;
 
         Ok(())
     }
 
+    // Closed 1m candles of the VWAPs, since the start of the day or the anchor when earlier
+    async fn load_vwap_candles(&self, start_time: Option<u64>) -> Result<Vec<MarketplaceCandle>> {
+        let end = start_time.unwrap_or_else(|| Utc::now().timestamp_millis() as u64);
+        let day = end - end % (24 * 3600 * 1000);
+        let from = self
+            .params
+            .vwap
+            .anchor
+            .map_or(day, |anchor| anchor.min(day));
+        let candles = fetch_range(
+            &self.marketplace,
+            &self.ticker,
+            CandleInterval::M1,
+            from,
+            end,
+        )
+        .await?;
+        Ok(candles
+            .into_iter()
+            .filter(|candle| candle.close_time < end)
+            .collect())
+    }
+
     async fn add_trade_event_history(&mut self, event: MarketplaceTrade) {
         let mut history = self.trade_event_history.write().await;
 
@@ -113,19 +424,24 @@ impl<M: Marketplace + MarketplaceSettingsApi + MarketplaceDataApi> ScalpingStrat
         }
     }
 
-    fn get_sma(&self, history: &[MarketplaceCandle], n: usize) -> Option<Decimal> {
-        let price_history: Vec<Decimal> = history.iter().map(|event| event.close_price).collect();
-
-        sma(&price_history, n)
-    }
-
     async fn add_candle_event_history(&mut self, event: MarketplaceCandle) {
         let mut update_stats = false;
+        let mut closed = None;
         {
             let mut history = self.candle_event_history.write().await;
+            let history = history.entry(event.interval).or_default();
+
+            // backfilled candles may overlap the history
+            if history
+                .front()
+                .is_some_and(|last| event.start_time < last.start_time)
+            {
+                return;
+            }
 
             if let Some(last) = history.pop_front() {
                 if last.start_time != event.start_time {
+                    closed = Some(last.clone());
                     history.push_front(last);
                     update_stats = true;
                 }
@@ -137,35 +453,31 @@ impl<M: Marketplace + MarketplaceSettingsApi + MarketplaceDataApi> ScalpingStrat
             }
         }
 
-        if update_stats {
-            self.update_stats(event.close_price, event.close_price)
-                .await;
-        }
-    }
+        self.indicators.update(&event);
 
-    fn get_atr(&self, history: &[MarketplaceCandle], n: usize) -> Option<Decimal> {
-        let price_history: Vec<(Decimal, Decimal, Decimal)> = history
-            .windows(2)
-            .map(|candles| {
-                (
-                    candles[0].high_price,
-                    candles[0].low_price,
-                    candles[1].close_price,
-                )
-            })
-            .collect();
+        if let Some(closed) = closed.filter(|_| event.interval == CandleInterval::M1) {
+            self.add_vwap_candle(&closed);
+            self.update_vwap_stats().await;
+            self.update_level_stats().await;
+            self.update_indicator_stats().await;
+        }
 
-        atr(&price_history, n)
+        if update_stats && event.interval == CandleInterval::M1 {
+            self.refresh_price_stats(event.close_price).await;
+        }
     }
 
-    fn get_wsma(&self, history: &[MarketplaceCandle], n: usize) -> Option<Decimal> {
-        let price_history: Vec<Decimal> = history.iter().map(|event| event.close_price).collect();
-        wsma(&price_history, n)
+    // Trends, supports and resistances read by the rules (downtrend, hold_bull, below_*),
+    // kept as they were until the indicators are warmed up
+    async fn refresh_price_stats(&self, price: Decimal) {
+        if let Some(stats) = self.update_stats(price, price).await {
+            *self.price_stats.write().await = stats;
+        }
     }
 
     fn get_support(
         &self,
-        history: &[MarketplaceCandle],
+        history: &VecDeque<MarketplaceCandle>,
         n: usize,
         tolerance: Decimal,
         price: Decimal,
@@ -187,14 +499,14 @@ impl<M: Marketplace + MarketplaceSettingsApi + MarketplaceDataApi> ScalpingStrat
 
     fn get_resistance(
         &self,
-        history: &[MarketplaceCandle],
+        history: &VecDeque<MarketplaceCandle>,
         n: usize,
         tolerance: Decimal,
         price: Decimal,
     ) -> Option<Decimal> {
         let prices: Vec<Decimal> = history
             .iter()
-            .map(|event| event.low_price)
+            .map(|event| event.high_price)
             .take(n)
             .collect();
 
@@ -212,35 +524,34 @@ impl<M: Marketplace + MarketplaceSettingsApi + MarketplaceDataApi> ScalpingStrat
 
     async fn update_stats(&self, buy_price: Decimal, sell_price: Decimal) -> Option<PriceStats> {
         let history = self.candle_event_history.read().await;
-        let history: Vec<MarketplaceCandle> = history.iter().cloned().collect();
-
-        let wsma_120 = self.get_wsma(&history, 120)?;
-        let wsma_14 = self.get_wsma(&history, 14)?;
-        let wsma_5 = self.get_wsma(&history, 5)?;
+        let history = history.get(&CandleInterval::M1)?;
+        let value = |kind| {
+            self.indicators
+                .value(&self.ticker, CandleInterval::M1, kind)
+        };
 
-        let sma_120 = self.get_sma(&history, 120)?;
-        let sma_14 = self.get_sma(&history, 14)?;
-        let sma_5 = self.get_sma(&history, 5)?;
+        let wsma_120 = value(WSMA_120)?;
+        let wsma_14 = value(WSMA_14)?;
+        let wsma_5 = value(WSMA_5)?;
 
-        let atr_120 = self.get_atr(&history, 120)?;
-        let atr_14 = self.get_atr(&history, 14)?;
-        let atr_5 = self.get_atr(&history, 5)?;
+        let atr_120 = value(ATR_120)?;
+        let atr_14 = value(ATR_14)?;
 
-        let support_120 = self.get_support(&history, 120, atr_120 * dec!(0.5), buy_price);
-        let support_14 = self.get_support(&history, 14, atr_14 * dec!(0.5), buy_price);
+        let support_120 = self.get_support(history, 120, atr_120 * dec!(0.5), buy_price);
+        let support_14 = self.get_support(history, 14, atr_14 * dec!(0.5), buy_price);
 
-        let resistance_120 = self.get_resistance(&history, 120, atr_120 * dec!(0.5), buy_price);
-        let resistance_14 = self.get_resistance(&history, 14, atr_14 * dec!(0.5), buy_price);
+        let resistance_120 = self.get_resistance(history, 120, atr_120 * dec!(0.5), buy_price);
+        let resistance_14 = self.get_resistance(history, 14, atr_14 * dec!(0.5), buy_price);
 
         let mut short_trend = None;
         if wsma_5 > wsma_14 {
-            if wsma_14 > dec!(0) && (wsma_5 - wsma_14) / wsma_14 > dec!(0.01) {
+            if wsma_14 > dec!(0) && (wsma_5 - wsma_14) / wsma_14 > TREND_GAP {
                 short_trend = Some(PriceTrend::Bull)
             } else {
                 short_trend = Some(PriceTrend::Up)
             }
         } else if wsma_5 < wsma_14 {
-            if wsma_14 > dec!(0) && (wsma_14 - wsma_5) / wsma_14 > dec!(0.01) {
+            if wsma_14 > dec!(0) && (wsma_14 - wsma_5) / wsma_14 > TREND_GAP {
                 short_trend = Some(PriceTrend::Crash)
             } else {
                 short_trend = Some(PriceTrend::Down)
@@ -249,13 +560,13 @@ impl<M: Marketplace + MarketplaceSettingsApi + MarketplaceDataApi> ScalpingStrat
 
         let mut long_trend = None;
         if wsma_14 > wsma_120 {
-            if wsma_120 > dec!(0) && (wsma_14 - wsma_120) / wsma_120 > dec!(0.01) {
+            if wsma_120 > dec!(0) && (wsma_14 - wsma_120) / wsma_120 > TREND_GAP {
                 long_trend = Some(PriceTrend::Bull)
             } else {
                 long_trend = Some(PriceTrend::Up)
             }
         } else if wsma_14 < wsma_120 {
-            if wsma_120 > dec!(0) && (wsma_120 - wsma_14) / wsma_120 > dec!(0.01) {
+            if wsma_120 > dec!(0) && (wsma_120 - wsma_14) / wsma_120 > TREND_GAP {
                 long_trend = Some(PriceTrend::Crash)
             } else {
                 long_trend = Some(PriceTrend::Down)
@@ -269,14 +580,108 @@ impl<M: Marketplace + MarketplaceSettingsApi + MarketplaceDataApi> ScalpingStrat
             long_support: support_120,
             short_resistance: resistance_14,
             long_resistance: resistance_120,
+            session_vwap: Some(self.session_vwap.clone()),
+            anchored_vwap: self.anchored_vwap.clone(),
+            levels: self.price_stats.read().await.levels.clone(),
+            indicators: self.price_stats.read().await.indicators.clone(),
         })
     }
 
+    // Ignore actions of the rules of a phase which did not pass
+    async fn check_rules(
+        &self,
+        phase: Phase,
+        state: &State,
+        price: Decimal,
+        book: &MarketplaceBook,
+    ) -> Vec<StrategyAction> {
+        let rules = self.params.rules.get(phase);
+        if rules.is_empty() {
+            return vec![];
+        }
+
+        let stats = self.price_stats.read().await;
+        let history = self.candle_event_history.read().await;
+        let no_candles = VecDeque::new();
+        let ctx = RuleContext {
+            ticker: &self.ticker,
+            stats: &stats,
+            state,
+            price,
+            book,
+            candles: history.get(&CandleInterval::M1).unwrap_or(&no_candles),
+        };
+
+        rules
+            .iter()
+            .filter_map(|rule| match rule.check(&ctx) {
+                RuleResult::Pass => None,
+                RuleResult::Ignore { reason, details } => Some(StrategyAction::Ignore {
+                    ticker: self.ticker.clone(),
+                    reason: format!("{} {}", phase, reason),
+                    details,
+                }),
+            })
+            .collect()
+    }
+
+    // Stop reached by an open position, checked before its profit
+    async fn stop_exit(&self, buy_order: &Order, price: Decimal, time: u64) -> Option<ExitReason> {
+        let stops = &self.params.stops;
+        let entry_price = buy_order.get_order_base_price();
+
+        let highest = {
+            let mut highest_prices = self.highest_prices.write().await;
+            let highest = highest_prices
+                .entry(buy_order.id.clone())
+                .or_insert(entry_price);
+            *highest = (*highest).max(price);
+            *highest
+        };
+
+        if stops
+            .stop_loss
+            .is_some_and(|stop_loss| price <= entry_price * (dec!(1) - stop_loss))
+        {
+            return Some(ExitReason::StopLoss);
+        }
+
+        if let Some(multiple) = stops.atr_stop {
+            if self
+                .indicators
+                .peek(&self.ticker, CandleInterval::M1, ATR_14)
+                .is_some_and(|atr| price <= entry_price - atr * multiple)
+            {
+                return Some(ExitReason::AtrStop);
+            }
+        }
+
+        if stops
+            .trailing_stop
+            .is_some_and(|trailing_stop| price <= highest * (dec!(1) - trailing_stop))
+        {
+            return Some(ExitReason::TrailingStop);
+        }
+
+        let entry_time = buy_order
+            .get_last_trade_time()
+            .unwrap_or(buy_order.creation_time);
+        if stops
+            .max_hold
+            .is_some_and(|max_hold| time.saturating_sub(entry_time) > max_hold * 1000)
+        {
+            return Some(ExitReason::MaxHold);
+        }
+
+        None
+    }
+
     async fn process_sell(
         &self,
         current_sell_price: Decimal,
-        current_time: u64,
+        book: &MarketplaceBook,
     ) -> Vec<StrategyAction> {
+        let current_time = book.time;
         let state = self.state.read().await;
 
         let last_buy_orders = state.find_by(
@@ -293,11 +698,22 @@ impl<M: Marketplace + MarketplaceSettingsApi + MarketplaceDataApi> ScalpingStrat
             },
         );
 
+        self.highest_prices
+            .write()
+            .await
+            .retain(|id, _| last_buy_orders.iter().any(|order| &order.id == id));
+
         let mut actions: Vec<StrategyAction> = Vec::new();
 
         for buy_order in last_buy_orders.iter() {
             let fees = self.marketplace.get_fees().await;
-            let amount = buy_order.filled_amount * (dec!(1) - fees);
+            // what a cancelled exit sold is no longer held, nor left to recover
+            let (sold, received) = state.get_exit_fills(&buy_order.id);
+            let amount = buy_order.get_net_base_amount(fees) - sold;
+            let cost = buy_order.get_net_quote_amount() - received;
+            if amount <= dec!(0) {
+                continue;
+            }
             if !state.portfolio.check_funds(&self.ticker.base, amount) {
                 actions.push(StrategyAction::Ignore {
                     ticker: self.ticker.clone(),
@@ -316,21 +732,66 @@ impl<M: Marketplace + MarketplaceSettingsApi + MarketplaceDataApi> ScalpingStrat
                 continue;
             }
             let price = current_sell_price;
-            let order = Order::new_sell(
+            let mut order = Order::new_sell(
                 self.ticker.clone(),
                 amount,
                 price,
                 current_time,
                 Some(buy_order),
             );
+            order.buy_order_price = Some(cost);
             let receive = amount * price * (dec!(1) - fees);
-            let take_profit = receive - buy_order.cumulative_quote_amount;
+            let take_profit = receive - cost;
+
+            // stops are not subject to the exit rules
+            if let Some(reason) = self.stop_exit(buy_order, price, current_time).await {
+                info!(
+                    "{} {} at {}, profit {}",
+                    self.ticker, reason, price, take_profit
+                );
+                order.exit_reason = Some(reason);
+                actions.push(StrategyAction::PlaceOrder { order });
+                break;
+            }
+
+            // the take profit rests at its target, the exit rules do not apply
+            if Self::is_limit(self.params.exit_order) {
+                // the amount is floored to the step, the dust left can not be sold
+                let amount = self
+                    .marketplace
+                    .floor_amount(&self.ticker, amount)
+                    .await
+                    .unwrap_or(amount);
+                if amount <= dec!(0) {
+                    continue;
+                }
+                let maker_fees = self.marketplace.get_maker_fees().await;
+                let mut price =
+                    (cost + self.params.target_profit) / (amount * (dec!(1) - maker_fees));
+                if self.params.exit_order == OrderType::LimitMaker {
+                    price = price.max(current_sell_price);
+                }
+                // only the price is rounded, adjusting would round the amount up
+                let mut adjusted = order.clone();
+                adjusted.order_type = self.params.exit_order;
+                adjusted.price = price;
+                if self
+                    .marketplace
+                    .adjust_order_price_and_amount(&mut adjusted)
+                    .await
+                    .is_ok()
+                {
+                    price = adjusted.price;
+                }
+                order.order_type = self.params.exit_order;
+                order.amount = amount;
+                order.price = price;
+                order.exit_reason = Some(ExitReason::TakeProfit);
+                actions.push(StrategyAction::PlaceOrder { order });
+                break;
+            }
 
             if take_profit < self.params.target_profit {
-                //if take_profit < self.params.target_profit * -dec!(0.5) {
-                //    actions.push(StrategyAction::PlaceOrder { order });
-                //    break;
-                //}
                 actions.push(StrategyAction::Ignore {
                     ticker: self.ticker.clone(),
                     reason: "No profit".to_string(),
@@ -339,18 +800,9 @@ impl<M: Marketplace + MarketplaceSettingsApi + MarketplaceDataApi> ScalpingStrat
                 continue;
             }
 
-            let stats = self.price_stats.read().await;
-
-            let mut ignores: Vec<StrategyAction> = vec![];
-            if matches!(stats.short_trend, Some(PriceTrend::Bull)) {
-                ignores.push(StrategyAction::Ignore {
-                    ticker: self.ticker.clone(),
-                    reason: "Hold bull".to_string(),
-                    details: None,
-                });
-            }
-
+            let mut ignores = self.check_rules(Phase::Exit, &state, price, book).await;
             if ignores.is_empty() {
+                order.exit_reason = Some(ExitReason::TakeProfit);
                 actions.push(StrategyAction::PlaceOrder { order });
                 break;
             }
@@ -364,8 +816,9 @@ impl<M: Marketplace + MarketplaceSettingsApi + MarketplaceDataApi> ScalpingStrat
     async fn process_reentry(
         &self,
         current_buy_price: Decimal,
-        current_time: u64,
+        book: &MarketplaceBook,
     ) -> Vec<StrategyAction> {
+        let current_time = book.time;
         if current_buy_price <= dec!(0) {
             error!("Invalid current buy price for {}", self.ticker);
             return vec![];
@@ -378,12 +831,8 @@ impl<M: Marketplace + MarketplaceSettingsApi + MarketplaceDataApi> ScalpingStrat
                 ticker: Some(self.ticker.clone()),
                 side: Some(OrderSide::Sell),
                 has_child: Some(false),
-                status: vec![
-                    OrderStatus::Executed,
-                    OrderStatus::Active,
-                    OrderStatus::Sent,
-                    OrderStatus::Draft,
-                ],
+                // a resting take profit is not an exit yet
+                status: vec![OrderStatus::Executed],
                 ..Default::default()
             },
             OrderListSort {
@@ -394,6 +843,15 @@ impl<M: Marketplace + MarketplaceSettingsApi + MarketplaceDataApi> ScalpingStrat
 
         let mut actions: Vec<StrategyAction> = Vec::new();
         for sell_order in last_sell_orders.iter() {
+            // a stop ends its session
+            if sell_order
+                .exit_reason
+                .as_ref()
+                .is_some_and(ExitReason::is_stop)
+            {
+                continue;
+            }
+
             let amount = self.params.quote_amount / current_buy_price;
             let price = current_buy_price;
 
@@ -446,47 +904,19 @@ impl<M: Marketplace + MarketplaceSettingsApi + MarketplaceDataApi> ScalpingStrat
                 }
             }
 
-            let stats = self.price_stats.read().await;
-            let mut ignores: Vec<StrategyAction> = vec![];
-
-            if stats
-                .short_resistance
-                .is_some_and(|resistance| resistance > current_buy_price)
-            {
-                ignores.push(StrategyAction::Ignore {
-                    ticker: self.ticker.clone(),
-                    reason: "Reentry long resistance < price".to_string(),
-                    details: None,
-                });
-            }
-            if stats
-                .long_resistance
-                .is_some_and(|resistance| resistance > current_buy_price)
-            {
-                ignores.push(StrategyAction::Ignore {
-                    ticker: self.ticker.clone(),
-                    reason: "Reentry long resistance < price".to_string(),
-                    details: None,
-                });
-            }
+            let mut ignores = self.check_rules(Phase::Reentry, &state, price, book).await;
 
             if ignores.is_empty() {
-                let mut order = Order::new_buy(
-                    self.ticker.clone(),
-                    amount,
-                    price,
-                    amount * price,
-                    current_time,
-                    Some(sell_order),
-                );
-                if self
-                    .marketplace
-                    .adjust_order_price_and_amount(&mut order)
-                    .await
-                    .is_ok()
-                {
-                    actions.push(StrategyAction::PlaceOrder { order });
-                    break;
+                if let Some(mut order) = self.new_entry_order(amount, book, Some(sell_order)) {
+                    if self
+                        .marketplace
+                        .adjust_order_price_and_amount(&mut order)
+                        .await
+                        .is_ok()
+                    {
+                        actions.push(StrategyAction::PlaceOrder { order });
+                        break;
+                    }
                 }
             }
 
@@ -496,11 +926,14 @@ impl<M: Marketplace + MarketplaceSettingsApi + MarketplaceDataApi> ScalpingStrat
         actions
     }
 
+    // `reprice` is the cancelled entry placed again, it keeps its session
     async fn process_entry(
         &self,
         current_buy_price: Decimal,
-        current_time: u64,
+        book: &MarketplaceBook,
+        reprice: Option<&Order>,
     ) -> Vec<StrategyAction> {
+        let current_time = book.time;
         if current_buy_price <= dec!(0) {
             error!("Invalid current buy price for {}", self.ticker);
             return vec![];
@@ -523,10 +956,13 @@ impl<M: Marketplace + MarketplaceSettingsApi + MarketplaceDataApi> ScalpingStrat
             return vec![];
         }
 
-        if let Some(last_order_time) = state.get_last_executed_order_time(OrderListFilters {
-            ticker: Some(self.ticker.clone()),
-            ..Default::default()
-        }) {
+        if let Some(last_order_time) = state
+            .get_last_executed_order_time(OrderListFilters {
+                ticker: Some(self.ticker.clone()),
+                ..Default::default()
+            })
+            .filter(|_| reprice.is_none())
+        {
             if self.params.entry_delay
                 > Duration::from_millis(current_time.saturating_sub(last_order_time))
             {
@@ -558,8 +994,9 @@ impl<M: Marketplace + MarketplaceSettingsApi + MarketplaceDataApi> ScalpingStrat
             }];
         }
 
-        if state.get_active_sessions(&self.ticker, current_time, &Duration::from_secs(3600))
-            >= self.params.session_count.into()
+        if reprice.is_none()
+            && state.get_active_sessions(&self.ticker, current_time, &Duration::from_secs(3600))
+                >= self.params.session_count.into()
         {
             return vec![StrategyAction::Ignore {
                 ticker: self.ticker.clone(),
@@ -568,41 +1005,17 @@ impl<M: Marketplace + MarketplaceSettingsApi + MarketplaceDataApi> ScalpingStrat
             }];
         }
 
-        let stats = self.price_stats.read().await;
-
-        let mut ignores: Vec<StrategyAction> = vec![];
-        if matches!(
-            stats.long_trend,
-            Some(PriceTrend::Crash) | Some(PriceTrend::Down)
-        ) && !matches!(stats.short_trend, Some(PriceTrend::Bull))
-        {
-            ignores.push(StrategyAction::Ignore {
-                ticker: self.ticker.clone(),
-                reason: "Entry price down".to_string(),
-                details: None,
-            });
-        }
-
-        if stats.long_support.is_some_and(|v| v > current_buy_price) {
-            ignores.push(StrategyAction::Ignore {
-                ticker: self.ticker.clone(),
-                reason: "Entry price > support".to_string(),
-                details: None,
-            });
-        }
-
+        let ignores = self.check_rules(Phase::Entry, &state, price, book).await;
         if !ignores.is_empty() {
             return ignores;
         }
 
-        let mut order = Order::new_buy(
-            self.ticker.clone(),
-            amount,
-            price,
-            amount * price,
-            current_time,
-            None,
-        );
+        let Some(mut order) = self.new_entry_order(amount, book, None) else {
+            return vec![];
+        };
+        if let Some(reprice) = reprice {
+            order.session_id = reprice.session_id.clone();
+        }
 
         match self
             .marketplace
@@ -617,10 +1030,100 @@ impl<M: Marketplace + MarketplaceSettingsApi + MarketplaceDataApi> ScalpingStrat
         }
     }
 
+    // Cancels of the resting limit orders : unfilled entries left behind by the
+    // price, take profits of a position which reached a stop.
+    // None when no order of the ticker is pending.
+    // The bool tells an order is on its way : the other positions wait for it, except for
+    // the resting take profits whose positions are left out by process_sell.
+    async fn process_open_orders(&mut self, book: &MarketplaceBook) -> (Vec<StrategyAction>, bool) {
+        let state = self.state.clone();
+        let state = state.read().await;
+        let open_orders = state.find_by(
+            OrderListFilters {
+                ticker: Some(self.ticker.clone()),
+                status: vec![
+                    OrderStatus::Draft,
+                    OrderStatus::Sent,
+                    OrderStatus::Active,
+                    OrderStatus::PendingCancel,
+                ],
+                ..Default::default()
+            },
+            OrderListSort {
+                by: OrderListSortBy::Date,
+                asc: true,
+            },
+        );
+        let waiting = open_orders.iter().any(|order| {
+            !(order.side == OrderSide::Sell
+                && Self::is_limit(order.order_type)
+                && matches!(
+                    order.status,
+                    OrderStatus::Active | OrderStatus::PendingCancel
+                ))
+        });
+
+        let mut actions = vec![];
+        for order in open_orders
+            .iter()
+            .filter(|order| order.status == OrderStatus::Active && Self::is_limit(order.order_type))
+        {
+            match order.side {
+                OrderSide::Buy => {
+                    let bid = book.buy_price().unwrap_or(order.price);
+                    if self.params.reprice_after == 0
+                        || order.filled_amount > dec!(0)
+                        || bid <= order.price
+                        || Duration::from_millis(book.time.saturating_sub(order.creation_time))
+                            < Duration::from_secs(self.params.reprice_after)
+                    {
+                        continue;
+                    }
+                    let reason = if self.reprices >= self.params.max_reprices {
+                        self.reprices = 0;
+                        "Abandon entry"
+                    } else {
+                        self.reprices += 1;
+                        // reentries are placed again by their own rules
+                        if order.prev_order_id.is_none() {
+                            self.repricing = Some((*order).clone());
+                        }
+                        "Reprice entry"
+                    };
+                    actions.push(StrategyAction::Cancel {
+                        order_id: order.id.clone(),
+                        reason: reason.to_string(),
+                        details: Some(format!("{} / {}", order.price, bid)),
+                    });
+                }
+                OrderSide::Sell => {
+                    let Some(buy_order) = order
+                        .prev_order_id
+                        .as_ref()
+                        .and_then(|id| state.orders.iter().find(|o| &o.id == id))
+                    else {
+                        continue;
+                    };
+                    let price = book.sell_price().unwrap_or(order.price);
+                    // the position is sold at the market once the take profit is cancelled
+                    if let Some(reason) = self.stop_exit(buy_order, price, book.time).await {
+                        actions.push(StrategyAction::Cancel {
+                            order_id: order.id.clone(),
+                            reason: format!("{}", reason),
+                            details: Some(format!("{}", price)),
+                        });
+                    }
+                }
+            }
+        }
+
+        (actions, waiting)
+    }
+
     async fn on_depth_event(
         &mut self,
         event: &MarketplaceBook,
-        tx_app: Sender<AppEvent>,
+        tx_app: &Sender<AppEvent>,
     ) -> Result<()> {
         if self.ticker != event.ticker {
             return Ok(());
@@ -634,37 +1137,53 @@ impl<M: Marketplace + MarketplaceSettingsApi + MarketplaceDataApi> ScalpingStrat
             .sell_price()
             .context(format!("Current sell price missing for {}.", event.ticker))?;
 
-        {
-            let state = self.state.read().await;
-            // if there is a pending order, wait for it to be processed
-            if !state
-                .find_by(
-                    OrderListFilters {
-                        ticker: Some(self.ticker.clone()),
-                        status: vec![OrderStatus::Draft, OrderStatus::Sent, OrderStatus::Active],
-                        ..Default::default()
-                    },
-                    OrderListSort {
-                        by: OrderListSortBy::Date,
-                        asc: true,
-                    },
-                )
-                .is_empty()
-            {
-                return Ok(());
+        // if there is a pending order, wait for it to be processed
+        let (actions, waiting) = self.process_open_orders(event).await;
+        for action in actions {
+            tx_app.send(AppEvent::Strategy(StrategyEvent::Action(Box::new(action))))?;
+        }
+        if waiting {
+            return Ok(());
+        }
+
+        // the cancelled entry is placed again unless it got filled meanwhile
+        let reprice = match self.repricing.take() {
+            Some(order) => {
+                let state = self.state.read().await;
+                state
+                    .orders
+                    .iter()
+                    .find(|o| o.id == order.id)
+                    .filter(|o| o.status == OrderStatus::Cancelled && o.filled_amount == dec!(0))
+                    .cloned()
             }
+            None => None,
+        };
+        if reprice.is_none() {
+            self.reprices = 0;
         }
 
         let mut actions: Vec<StrategyAction> = vec![];
 
-        actions.append(&mut self.process_sell(current_sell_price, event.time).await);
+        actions.append(&mut self.process_sell(current_sell_price, event).await);
 
-        actions.append(&mut self.process_reentry(current_buy_price, event.time).await);
+        actions.append(&mut self.process_reentry(current_buy_price, event).await);
 
-        actions.append(&mut self.process_entry(current_buy_price, event.time).await);
+        actions.append(
+            &mut self
+                .process_entry(current_buy_price, event, reprice.as_ref())
+                .await,
+        );
+
+        let fees = self.marketplace.get_fees().await;
+        for action in actions.iter_mut() {
+            if let StrategyAction::PlaceOrder { order } = action {
+                order.fees = fees;
+            }
+        }
 
         for action in actions {
-            tx_app.send(AppEvent::Strategy(StrategyEvent::Action(action)))?;
+            tx_app.send(AppEvent::Strategy(StrategyEvent::Action(Box::new(action))))?;
         }
 
         Ok(())
@@ -675,25 +1194,92 @@ impl<M> Strategy for ScalpingStrategy<M>
 where
     M: Marketplace + MarketplaceSettingsApi + MarketplaceDataApi,
 {
-    async fn start(&mut self, tx_app: Sender<AppEvent>) {
-        let mut rx_app = tx_app.subscribe();
-        loop {
-            if let Ok(event) = rx_app.recv().await {
-                match event {
-                    AppEvent::MarketPlace(MarketplaceEvent::Candle(event)) => {
-                        if self.ticker == event.ticker {
-                            self.add_candle_event_history(event.clone()).await;
-                        }
-                    }
-                    AppEvent::MarketPlace(MarketplaceEvent::Book(event)) => {
-                        if self.ticker == event.ticker {
-                            let _ = self.on_depth_event(&event, tx_app.clone()).await;
-                        }
-                    }
-                    _ => {}
+    fn intervals(&self) -> Vec<CandleInterval> {
+        // daily and 4h candles for the support/resistance levels
+        vec![CandleInterval::M1, CandleInterval::H4, CandleInterval::D1]
+    }
+
+    fn name(&self) -> &'static str {
+        "scalping"
+    }
+
+    fn ticker(&self) -> &Ticker {
+        &self.ticker
+    }
+
+    async fn init(&mut self, start_time: Option<u64>) -> Result<()> {
+        self.status = StrategyStatus::Initializing;
+        self.load_history(start_time).await?;
+        self.status = StrategyStatus::Running;
+        Ok(())
+    }
+
+    async fn on_event(&mut self, event: &AppEvent, tx_app: &Sender<AppEvent>) -> Result<()> {
+        match event {
+            AppEvent::MarketPlace(MarketplaceEvent::Candle(event))
+                if self.ticker == event.ticker =>
+            {
+                self.add_candle_event_history(event.clone()).await;
+                if event.interval == CandleInterval::M1 {
+                    self.send_indicators(tx_app);
+                }
+            }
+            AppEvent::MarketPlace(MarketplaceEvent::Trade(event))
+                if self.ticker == event.ticker =>
+            {
+                if self.params.vwap.source == VwapSource::Trades {
+                    self.add_vwap(event.trade_time, event.price, event.quantity);
+                    self.update_vwap_stats().await;
+                }
+                self.add_trade_event_history(event.clone()).await;
+            }
+            AppEvent::MarketPlace(MarketplaceEvent::AggTrade(event))
+                if self.ticker == event.ticker && self.params.vwap.source == VwapSource::Trades =>
+            {
+                self.add_vwap(event.trade_time, event.price, event.quantity);
+                self.update_vwap_stats().await;
+            }
+            AppEvent::MarketPlace(MarketplaceEvent::StreamStatus(event)) => match event.status {
+                StreamStatus::Reconnecting { .. } => self.stream_ready = false,
+                StreamStatus::Backfilled { ref ticker, .. } if *ticker == self.ticker => {
+                    self.stream_ready = true
+                }
+                StreamStatus::Backfilled { .. } => {}
+                StreamStatus::Connected => {}
+                StreamStatus::Seeked { to, .. } => {
+                    // the history is reloaded up to the new replay time
+                    self.initialized = false;
+                    self.trade_event_history.write().await.clear();
+                    self.load_history(Some(to))
+                        .await
+                        .with_context(|| format!("Could not reload {} history", self.ticker))?;
                 }
+            },
+            AppEvent::MarketPlace(MarketplaceEvent::Book(event))
+                if self.ticker == event.ticker && self.stream_ready =>
+            {
+                self.on_depth_event(event, tx_app).await?;
             }
+            _ => {}
         }
+        Ok(())
+    }
+
+    async fn on_command(&mut self, command: StrategyCommand) {
+        self.status = match (command, self.status) {
+            (StrategyCommand::Stop, _) => StrategyStatus::Stopped,
+            (StrategyCommand::Pause, StrategyStatus::Running) => StrategyStatus::Paused,
+            (StrategyCommand::Resume, StrategyStatus::Paused) => StrategyStatus::Running,
+            (_, status) => status,
+        };
+    }
+
+    fn status(&self) -> StrategyStatus {
+        self.status
+    }
+
+    fn params(&self) -> serde_json::Value {
+        serde_json::to_value(&self.params).unwrap_or_default()
     }
 }
 
@@ -713,4 +1299,341 @@ mod tests {
         let v2 = VecDeque::from(vec![3, 2, 1]);
         assert_eq!(v, v2);
     }
+
+    #[tokio::test]
+    async fn test_stop_exit() {
+        let ticker = Ticker::new("BTC", "USDC");
+        let mut strategy = ScalpingStrategy::new(
+            Arc::new(RwLock::new(State::new())),
+            crate::marketplace::binance::Binance::default(),
+            ticker.clone(),
+            ScalpingParams::default(),
+        );
+        let buy_order = Order::new_buy(ticker, dec!(1), dec!(100), dec!(100), 0, None);
+        assert_eq!(strategy.stop_exit(&buy_order, dec!(50), 0).await, None);
+
+        strategy.params.stops = StopParams {
+            stop_loss: Some(dec!(0.05)),
+            trailing_stop: Some(dec!(0.02)),
+            max_hold: Some(60),
+            ..Default::default()
+        };
+        assert_eq!(strategy.stop_exit(&buy_order, dec!(99), 1000).await, None);
+        assert_eq!(strategy.stop_exit(&buy_order, dec!(104), 2000).await, None);
+        assert_eq!(
+            strategy.stop_exit(&buy_order, dec!(101.9), 3000).await,
+            Some(ExitReason::TrailingStop)
+        );
+        assert_eq!(
+            strategy.stop_exit(&buy_order, dec!(94), 4000).await,
+            Some(ExitReason::StopLoss)
+        );
+        assert_eq!(
+            strategy.stop_exit(&buy_order, dec!(103), 61_000).await,
+            Some(ExitReason::MaxHold)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_price_stats_on_closed_candles() {
+        let ticker = Ticker::new("BTC", "USDC");
+        let mut strategy = ScalpingStrategy::new(
+            Arc::new(RwLock::new(State::new())),
+            crate::marketplace::binance::Binance::default(),
+            ticker.clone(),
+            ScalpingParams::default(),
+        );
+        // falling 1m candles, enough to warm up the 120 periods indicators
+        for i in 0..130u64 {
+            let close = dec!(1000) - Decimal::from(i);
+            strategy
+                .add_candle_event_history(MarketplaceCandle {
+                    ticker: ticker.clone(),
+                    interval: CandleInterval::M1,
+                    open_price: close + dec!(1),
+                    close_price: close,
+                    high_price: close + dec!(2),
+                    low_price: close - dec!(1),
+                    trade_count: 1,
+                    start_time: 1_735_689_600_000 + i * 60_000,
+                    close_time: 1_735_689_600_000 + i * 60_000 + 59_999,
+                    volume: dec!(1),
+                    closed: false,
+                })
+                .await;
+        }
+
+        let stats = strategy.price_stats.read().await;
+        assert!(matches!(
+            stats.long_trend,
+            Some(PriceTrend::Down) | Some(PriceTrend::Crash)
+        ));
+        assert!(matches!(
+            stats.short_trend,
+            Some(PriceTrend::Down) | Some(PriceTrend::Crash)
+        ));
+
+        // so the rules reading the trends do block the entries
+        let state = State::new();
+        let book = MarketplaceBook {
+            ticker: ticker.clone(),
+            first_update_id: 0,
+            final_update_id: 0,
+            time: 0,
+            bids: vec![(dec!(871), dec!(1))],
+            asks: vec![(dec!(871.1), dec!(1))],
+        };
+        let candles = VecDeque::new();
+        let ctx = RuleContext {
+            ticker: &ticker,
+            stats: &stats,
+            state: &state,
+            price: dec!(871.1),
+            book: &book,
+            candles: &candles,
+        };
+        assert!(matches!(
+            crate::strategy::rules::Rule::Downtrend.check(&ctx),
+            RuleResult::Ignore { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_resting_take_profit() {
+        let ticker = Ticker::new("BTC", "USDC");
+        let state = Arc::new(RwLock::new(State::new()));
+        let mut strategy = ScalpingStrategy::new(
+            state.clone(),
+            crate::marketplace::binance::Binance::default(),
+            ticker.clone(),
+            ScalpingParams::default(),
+        );
+        let book = MarketplaceBook {
+            ticker: ticker.clone(),
+            first_update_id: 0,
+            final_update_id: 0,
+            time: 0,
+            bids: vec![(dec!(100), dec!(1))],
+            asks: vec![(dec!(100.05), dec!(1))],
+        };
+
+        // a take profit resting on the book lets the other positions go on
+        let mut take_profit = Order::new_sell(ticker.clone(), dec!(1), dec!(101), 0, None);
+        take_profit.order_type = OrderType::LimitMaker;
+        take_profit.status = OrderStatus::Active;
+        state.write().await.orders.push(take_profit);
+        let (actions, waiting) = strategy.process_open_orders(&book).await;
+        assert!(actions.is_empty());
+        assert!(!waiting);
+
+        // an order on its way holds them
+        let entry = Order::new_buy(ticker, dec!(1), dec!(100), dec!(100), 0, None);
+        state.write().await.orders.push(entry);
+        assert!(strategy.process_open_orders(&book).await.1);
+    }
+
+    #[tokio::test]
+    async fn test_partly_filled_exit() {
+        let ticker = Ticker::new("BTC", "USDC");
+        let state = Arc::new(RwLock::new(State::new()));
+        let mut strategy = ScalpingStrategy::new(
+            state.clone(),
+            crate::marketplace::binance::Binance::default(),
+            ticker.clone(),
+            ScalpingParams::default(),
+        );
+        strategy.params.exit_order = OrderType::Limit;
+        let book = MarketplaceBook {
+            ticker: ticker.clone(),
+            first_update_id: 0,
+            final_update_id: 0,
+            time: 0,
+            bids: vec![(dec!(100), dec!(1))],
+            asks: vec![(dec!(100.05), dec!(1))],
+        };
+        let trade = |id: &str, amount, price| crate::order::OrderTrade {
+            id: id.to_string(),
+            trade_time: 0,
+            amount,
+            price,
+            fee: dec!(0),
+            fee_asset: Some("BNB".to_string()),
+        };
+
+        let mut buy = Order::new_buy(ticker.clone(), dec!(1), dec!(100), dec!(100), 0, None);
+        buy.status = OrderStatus::Executed;
+        buy.filled_amount = dec!(1);
+        buy.trades.push(trade("1", dec!(1), dec!(100)));
+        // the take profit sold 0.4 before being cancelled
+        let mut sell = Order::new_sell(ticker.clone(), dec!(1), dec!(101), 0, Some(&buy));
+        sell.status = OrderStatus::Cancelled;
+        sell.filled_amount = dec!(0.4);
+        sell.trades.push(trade("2", dec!(0.4), dec!(101)));
+        buy.next_order_id = Some(sell.id.clone());
+        {
+            let mut state = state.write().await;
+            state.portfolio.update_asset(crate::portfolio::Asset {
+                symbol: "BTC".to_string(),
+                amount: dec!(0.6),
+                locked: dec!(0),
+                value: None,
+            });
+            state.orders.push(buy);
+            let sell_id = sell.id.clone();
+            state.orders.push(sell);
+            state.detach_unfilled(&sell_id);
+        }
+
+        // only the remainder is sold, for what is left to recover
+        let actions = strategy.process_sell(dec!(100), &book).await;
+        let Some(StrategyAction::PlaceOrder { order }) = actions.first() else {
+            panic!("no exit for the remainder: {:?}", actions);
+        };
+        assert_eq!(order.amount, dec!(0.6));
+        assert_eq!(order.buy_order_price, Some(dec!(59.6)));
+    }
+
+    #[tokio::test]
+    async fn test_take_profit_step() {
+        let ticker = Ticker::new("BTC", "USDC");
+        let state = Arc::new(RwLock::new(State::new()));
+        let exchange_info = serde_json::from_value(serde_json::json!({
+            "symbols": [{
+                "symbol": "BTCUSDC",
+                "baseAsset": "BTC",
+                "quoteAsset": "USDC",
+                "baseAssetPrecision": 8,
+                "quoteAssetPrecision": 8,
+                "filters": [
+                    {"filterType": "PRICE_FILTER", "minPrice": "0.01", "maxPrice": "1000000", "tickSize": "0.01"},
+                    {"filterType": "LOT_SIZE", "minQty": "0.001", "maxQty": "9000", "stepSize": "0.001"},
+                    {"filterType": "NOTIONAL", "minNotional": "5"}
+                ]
+            }]
+        }))
+        .unwrap();
+        let mut strategy = ScalpingStrategy::new(
+            state.clone(),
+            crate::marketplace::binance::Binance::default().with_exchange_info(exchange_info),
+            ticker.clone(),
+            ScalpingParams::default(),
+        );
+        strategy.params.exit_order = OrderType::Limit;
+        let book = MarketplaceBook {
+            ticker: ticker.clone(),
+            first_update_id: 0,
+            final_update_id: 0,
+            time: 0,
+            bids: vec![(dec!(100), dec!(1))],
+            asks: vec![(dec!(100.05), dec!(1))],
+        };
+
+        let mut buy = Order::new_buy(
+            ticker.clone(),
+            dec!(0.12345),
+            dec!(100),
+            dec!(12.345),
+            0,
+            None,
+        );
+        buy.status = OrderStatus::Executed;
+        buy.filled_amount = dec!(0.12345);
+        buy.trades.push(crate::order::OrderTrade {
+            id: "1".to_string(),
+            trade_time: 0,
+            amount: dec!(0.12345),
+            price: dec!(100),
+            fee: dec!(0),
+            fee_asset: Some("BNB".to_string()),
+        });
+        {
+            let mut state = state.write().await;
+            state.portfolio.update_asset(crate::portfolio::Asset {
+                symbol: "BTC".to_string(),
+                amount: dec!(0.12345),
+                locked: dec!(0),
+                value: None,
+            });
+            state.orders.push(buy);
+        }
+
+        // the held amount is not on the step, the take profit sells what the step allows
+        let actions = strategy.process_sell(dec!(100), &book).await;
+        let Some(StrategyAction::PlaceOrder { order }) = actions.first() else {
+            panic!("no take profit: {:?}", actions);
+        };
+        assert_eq!(order.amount, dec!(0.123));
+        assert_eq!(order.price, order.price.round_dp(2));
+    }
+
+    #[tokio::test]
+    async fn test_no_reentry_after_stop() {
+        let ticker = Ticker::new("BTC", "USDC");
+        let state = Arc::new(RwLock::new(State::new()));
+        let strategy = ScalpingStrategy::new(
+            state.clone(),
+            crate::marketplace::binance::Binance::default(),
+            ticker.clone(),
+            ScalpingParams::default(),
+        );
+        let book = MarketplaceBook {
+            ticker: ticker.clone(),
+            first_update_id: 0,
+            final_update_id: 0,
+            time: 3_600_000,
+            bids: vec![(dec!(100), dec!(1))],
+            asks: vec![(dec!(100.05), dec!(1))],
+        };
+
+        let mut sell = Order::new_sell(ticker, dec!(1), dec!(95), 0, None);
+        sell.status = OrderStatus::Executed;
+        sell.exit_reason = Some(ExitReason::StopLoss);
+        state.write().await.orders.push(sell);
+        assert!(strategy
+            .process_reentry(dec!(100.05), &book)
+            .await
+            .is_empty());
+
+        // a take profit does reenter, here without the funds to
+        state.write().await.orders[0].exit_reason = Some(ExitReason::TakeProfit);
+        let actions = strategy.process_reentry(dec!(100.05), &book).await;
+        assert!(matches!(
+            actions.first(),
+            Some(StrategyAction::Ignore { reason, .. }) if reason == "Reentry no funds"
+        ));
+    }
+
+    #[test]
+    fn test_limit_entry_order() {
+        let ticker = Ticker::new("BTC", "USDC");
+        let mut strategy = ScalpingStrategy::new(
+            Arc::new(RwLock::new(State::new())),
+            crate::marketplace::binance::Binance::default(),
+            ticker.clone(),
+            ScalpingParams::default(),
+        );
+        let book = MarketplaceBook {
+            ticker,
+            first_update_id: 0,
+            final_update_id: 0,
+            time: 0,
+            bids: vec![(dec!(100), dec!(1))],
+            asks: vec![(dec!(100.05), dec!(1))],
+        };
+
+        let order = strategy.new_entry_order(dec!(1), &book, None).unwrap();
+        assert_eq!(order.order_type, OrderType::Market);
+
+        strategy.params.entry_order = OrderType::Limit;
+        strategy.params.entry_improve = dec!(0.0001);
+        let order = strategy.new_entry_order(dec!(1), &book, None).unwrap();
+        assert_eq!(order.order_type, OrderType::Limit);
+        assert_eq!(order.price, dec!(100.01));
+
+        // a maker entry never crosses the ask
+        strategy.params.entry_order = OrderType::LimitMaker;
+        strategy.params.entry_improve = dec!(0.001);
+        let order = strategy.new_entry_order(dec!(1), &book, None).unwrap();
+        assert_eq!(order.price, dec!(100));
+    }
 }
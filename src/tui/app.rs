@@ -24,18 +24,27 @@ use std::{
 };
 use tokio::sync::mpsc::{self, Receiver};
 
+use crate::indicators::IndicatorKind;
 use crate::strategy::StrategyAction;
 use crate::{
-    marketplace::{MarketplaceCandle, MarketplaceEvent, MarketplaceTrade},
-    order::{Order, OrderSide, OrderTrade},
+    marketplace::{
+        candle::CandleInterval, replay::ReplayCommand, MarketplaceCandle, MarketplaceEvent,
+        MarketplaceTrade,
+    },
+    order::{get_cost_basis, Order, OrderSide, OrderStatus, OrderTrade},
     portfolio::{Asset, Portfolio},
     state::StateEvent,
     strategy::StrategyEvent,
     ticker::Ticker,
-    utils::avg,
+    utils::{avg, parse_time},
     AppCommandEvent, AppEvent,
 };
 
+const TUI_INDICATORS: [IndicatorKind; 2] = [
+    IndicatorKind::Wsma { period: 14 },
+    IndicatorKind::Atr { period: 14 },
+];
+
 enum Window {
     None,
     Portfolio,
@@ -52,12 +61,20 @@ pub struct App {
     tickers: Vec<Ticker>,
     last_strategy_events: HashMap<Ticker, HashMap<String, (u64, String)>>,
     candles: HashMap<Ticker, VecDeque<MarketplaceCandle>>,
+    // 1m indicators shown over the candles, as computed by the strategies
+    indicators: HashMap<Ticker, Vec<(IndicatorKind, Decimal)>>,
     trades: HashMap<Ticker, VecDeque<MarketplaceTrade>>,
     selected_window: Window,
     selected_asset: Option<String>,
     order_table_state: TableState,
     orders_scroll_state: ScrollbarState,
     orders_scroll: usize,
+    // base asset typed for a new ticker
+    ticker_input: Option<String>,
+    // replay time typed for a seek
+    seek_input: Option<String>,
+    // replay speed multiplier sent with (+) and (-)
+    replay_speed: f64,
 }
 
 impl App {
@@ -75,6 +92,7 @@ impl App {
             tickers,
             last_strategy_events: HashMap::new(),
             candles: HashMap::new(),
+            indicators: HashMap::new(),
             trades: HashMap::new(),
             portfolio: Portfolio::new(),
             orders: Vec::new(),
@@ -83,6 +101,9 @@ impl App {
             order_table_state: TableState::default(),
             orders_scroll_state: ScrollbarState::new(0),
             orders_scroll: 0,
+            ticker_input: None,
+            seek_input: None,
+            replay_speed: 1.0,
         }
     }
 
@@ -121,23 +142,35 @@ impl App {
                 self.orders = orders;
                 self.update_orders_scroll();
             }
-            AppEvent::Strategy(StrategyEvent::Action(StrategyAction::Ignore {
+            AppEvent::Strategy(StrategyEvent::Action(action)) => {
+                if let StrategyAction::Ignore {
+                    ticker,
+                    reason,
+                    details,
+                } = *action
+                {
+                    let entry = self.last_strategy_events.entry(ticker).or_default();
+                    entry
+                        .entry(reason)
+                        .and_modify(|e| {
+                            e.0 += 1;
+                            if let Some(details) = details.clone() {
+                                e.1 = details;
+                            }
+                        })
+                        .or_insert((0, details.unwrap_or(String::new())));
+                }
+            }
+            AppEvent::Strategy(StrategyEvent::Indicators {
                 ticker,
-                reason,
-                details,
-            })) => {
-                let entry = self.last_strategy_events.entry(ticker).or_default();
-                entry
-                    .entry(reason)
-                    .and_modify(|e| {
-                        e.0 += 1;
-                        if let Some(details) = details.clone() {
-                            e.1 = details;
-                        }
-                    })
-                    .or_insert((0, details.unwrap_or(String::new())));
+                interval: CandleInterval::M1,
+                values,
+            }) => {
+                self.indicators.insert(ticker, values);
             }
-            AppEvent::MarketPlace(MarketplaceEvent::Candle(candle)) => {
+            AppEvent::MarketPlace(MarketplaceEvent::Candle(candle))
+                if candle.interval == CandleInterval::M1 =>
+            {
                 let candles = self.candles.entry(candle.ticker.clone()).or_default();
                 if let Some(last) = candles.pop_back() {
                     if last.start_time != candle.start_time {
@@ -163,8 +196,65 @@ impl App {
         }
     }
 
+    async fn handle_ticker_input(&mut self, code: KeyCode) {
+        let Some(input) = self.ticker_input.as_mut() else {
+            return;
+        };
+        match code {
+            KeyCode::Esc => self.ticker_input = None,
+            KeyCode::Backspace => {
+                input.pop();
+            }
+            KeyCode::Char(c) if c.is_ascii_alphanumeric() => input.push(c.to_ascii_uppercase()),
+            KeyCode::Enter => {
+                if !input.is_empty() {
+                    let ticker = Ticker::new(input, &self.quote);
+                    if !self.tickers.contains(&ticker) {
+                        self.tickers.push(ticker.clone());
+                    }
+                    let _ = self.tx.send(AppCommandEvent::AddTicker(ticker)).await;
+                }
+                self.ticker_input = None;
+            }
+            _ => {}
+        }
+    }
+
+    async fn handle_seek_input(&mut self, code: KeyCode) {
+        let Some(input) = self.seek_input.as_mut() else {
+            return;
+        };
+        match code {
+            KeyCode::Esc => self.seek_input = None,
+            KeyCode::Backspace => {
+                input.pop();
+            }
+            KeyCode::Char(c) if c.is_ascii_alphanumeric() || "-:.+".contains(c) => input.push(c),
+            KeyCode::Enter => {
+                // an invalid time keeps the prompt open
+                if let Ok(time) = parse_time(input) {
+                    self.replay_command(ReplayCommand::Seek(time)).await;
+                    self.seek_input = None;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    async fn replay_command(&mut self, cmd: ReplayCommand) {
+        let _ = self.tx.send(AppCommandEvent::Replay(cmd)).await;
+    }
+
     async fn handle_events(&mut self, event: Event) {
         if let Some(key) = event.as_key_press_event() {
+            if self.ticker_input.is_some() {
+                self.handle_ticker_input(key.code).await;
+                return;
+            }
+            if self.seek_input.is_some() {
+                self.handle_seek_input(key.code).await;
+                return;
+            }
             match key.code {
                 KeyCode::Esc => match self.selected_window {
                     Window::None => self.should_quit = true,
@@ -220,6 +310,32 @@ impl App {
                 KeyCode::Char('p') => {
                     let _ = self.tx.send(AppCommandEvent::Pause).await;
                 }
+                KeyCode::Char('a') => {
+                    self.ticker_input = Some(String::new());
+                }
+                KeyCode::Char('+') => {
+                    self.replay_speed = (self.replay_speed * 2.0).min(1024.0);
+                    self.replay_command(ReplayCommand::Speed(self.replay_speed))
+                        .await;
+                }
+                KeyCode::Char('-') => {
+                    self.replay_speed = (self.replay_speed / 2.0).max(1.0 / 64.0);
+                    self.replay_command(ReplayCommand::Speed(self.replay_speed))
+                        .await;
+                }
+                KeyCode::Char('.') => self.replay_command(ReplayCommand::Step(1)).await,
+                KeyCode::Char('b') => self.replay_command(ReplayCommand::StepBook).await,
+                KeyCode::Char('s') => {
+                    self.seek_input = Some(String::new());
+                }
+                KeyCode::Char('R') => self.replay_command(ReplayCommand::Restart).await,
+                KeyCode::Char('r') => {
+                    if let Some(symbol) = self.selected_asset.take() {
+                        let ticker = Ticker::new(&symbol, &self.quote);
+                        self.tickers.retain(|t| t != &ticker);
+                        let _ = self.tx.send(AppCommandEvent::RemoveTicker(ticker)).await;
+                    }
+                }
                 _ => {}
             }
         }
@@ -249,6 +365,33 @@ impl App {
         //    .with_offset(self.orders_scroll);
     }
 
+    // Base amount and average price of a session, with the fee ratio its strategy placed
+    // the orders with when the marketplace did not report the commissions
+    fn get_session_cost_basis(&self, session_id: &String) -> Option<(Decimal, Decimal)> {
+        let orders: Vec<&Order> = self
+            .orders
+            .iter()
+            .filter(|order| order.session_id.as_ref() == Some(session_id))
+            .collect();
+        let fees = orders.first().map(|order| order.fees)?;
+        get_cost_basis(orders, fees)
+    }
+
+    // Cost basis of the open DCA cycle of a ticker
+    fn get_dca_cost_basis(&self, ticker: &Ticker) -> Option<(Decimal, Decimal)> {
+        self.orders
+            .iter()
+            .filter(|order| {
+                &order.ticker == ticker
+                    && order.strategy.as_deref() == Some("dca")
+                    && order.status == OrderStatus::Executed
+            })
+            .max_by_key(|order| order.creation_time)
+            .filter(|order| order.side == OrderSide::Buy)
+            .and_then(|order| order.session_id.as_ref())
+            .and_then(|session_id| self.get_session_cost_basis(session_id))
+    }
+
     fn get_orders(&self) -> Vec<&Order> {
         self.orders
             .iter()
@@ -359,6 +502,7 @@ impl App {
             "Ticker",
             "Type",
             "Status",
+            "Exit",
             "Value",
             "Fulfilled",
             "Price",
@@ -394,6 +538,15 @@ impl App {
                         },
                     )),
                     Cell::from(format!("{}", order.status)),
+                    match order.exit_reason {
+                        Some(reason) => Cell::from(reason.to_string()).style(Style::new().fg(
+                            match reason.is_stop() {
+                                true => tailwind::ORANGE.c500,
+                                false => tailwind::GREEN.c500,
+                            },
+                        )),
+                        None => Cell::from(""),
+                    },
                     Cell::from(format!("{}", order.cumulative_quote_amount)),
                     Cell::from(format!(
                         "{}%",
@@ -422,6 +575,7 @@ impl App {
                 Constraint::Min(6),
                 Constraint::Min(6),
                 Constraint::Fill(1),
+                Constraint::Min(13),
                 Constraint::Fill(1),
                 Constraint::Fill(1),
                 Constraint::Fill(1),
@@ -470,6 +624,17 @@ impl App {
                 "n: {}",
                 order.next_order_id.clone().unwrap_or("?".to_string())
             )));
+            if let Some((amount, price)) = order
+                .session_id
+                .as_ref()
+                .and_then(|session_id| self.get_session_cost_basis(session_id))
+            {
+                items.push(ListItem::from(format!(
+                    "c: {} @ {}",
+                    amount.round_dp(8),
+                    price.round_dp(8)
+                )));
+            }
             let mut trade_items: Vec<ListItem> = order.trades.iter().map(ListItem::from).collect();
             items.append(&mut trade_items);
             let list = List::new(items).block(block);
@@ -543,10 +708,31 @@ impl App {
             .and_then(|k| self.portfolio.assets.get(&k))
         {
             Some(asset) => {
+                let ticker = Ticker::new(asset.symbol.as_str(), &self.quote);
+                let indicators: Vec<String> = TUI_INDICATORS
+                    .iter()
+                    .filter_map(|kind| {
+                        let (_, value) = self
+                            .indicators
+                            .get(&ticker)?
+                            .iter()
+                            .find(|(k, _)| k == kind)?;
+                        Some(match kind {
+                            IndicatorKind::Sma { period } => {
+                                format!("SMA{period} {}", value.round_dp(4))
+                            }
+                            IndicatorKind::Wsma { period } => {
+                                format!("WSMA{period} {}", value.round_dp(4))
+                            }
+                            IndicatorKind::Atr { period } => {
+                                format!("ATR{period} {}", value.round_dp(4))
+                            }
+                        })
+                    })
+                    .collect();
                 let block = Block::default()
-                    .title(format!("{} candles", asset.symbol))
+                    .title(format!("{} candles {}", asset.symbol, indicators.join(" ")))
                     .borders(Borders::ALL);
-                let ticker = Ticker::new(asset.symbol.as_str(), &self.quote);
                 if let Some(candles) = self.candles.get_mut(&ticker) {
                     let data_candles: Vec<(f64, f64)> = candles
                         .make_contiguous()
@@ -712,7 +898,15 @@ impl App {
                 match self.last_strategy_events.get(&ticker) {
                     Some(events) => {
                         let block = Block::default()
-                            .title(format!("{} strategy", ticker.base))
+                            .title(match self.get_dca_cost_basis(&ticker) {
+                                Some((amount, price)) => format!(
+                                    "{} strategy, cost {} @ {}",
+                                    ticker.base,
+                                    amount.round_dp(8),
+                                    price.round_dp(8)
+                                ),
+                                None => format!("{} strategy", ticker.base),
+                            })
                             .borders(Borders::ALL);
                         let mut items: Vec<ListItem> = vec![];
                         for (reason, (count, details)) in events.iter() {
@@ -748,14 +942,34 @@ impl App {
 
     fn render_footer(&self, frame: &mut Frame, area: Rect) {
         let block = Block::default().borders(Borders::ALL);
+        if let Some(input) = &self.ticker_input {
+            let prompt = Paragraph::new(Text::from(format!(
+                "Add ticker: {}{} | (Enter) confirm | (Esc) cancel",
+                input, self.quote
+            )))
+            .block(block);
+            frame.render_widget(prompt, area);
+            return;
+        }
+        if let Some(input) = &self.seek_input {
+            let prompt = Paragraph::new(Text::from(format!(
+                "Seek replay to (ms, 2025-01-31 or 2025-01-31T14:00:00Z): {} | (Enter) confirm | (Esc) cancel",
+                input
+            )))
+            .block(block);
+            frame.render_widget(prompt, area);
+            return;
+        }
         let keys = match self.selected_window {
             Window::Portfolio => {
-                "(Esc) back | (p) Pause/Resume | (1) Portfolio | (2) Orders | (↑) previous asset | (↓) next asset"
+                "(Esc) back | (p) Pause/Resume | (a) Add ticker | (r) Remove ticker | (1) Portfolio | (2) Orders | (↑) previous asset | (↓) next asset"
             }
             Window::Orders => {
                 "(Esc) back | (p) Pause/Resume | (1) Portfolio | (2) Orders | (↑) previous order | (↓) next order"
             }
-            Window::None => "(Esc) quit | (p) Pause/Resume | (1) Portfolio | (2) Orders",
+            Window::None => {
+                "(Esc) quit | (p) Pause/Resume | (a) Add ticker | (1) Portfolio | (2) Orders | replay: (+/-) speed | (.) step | (b) next book | (s) seek | (R) restart"
+            }
         };
 
         let keys = Paragraph::new(Text::from(keys)).block(block);
@@ -794,6 +1008,12 @@ impl From<&OrderTrade> for ListItem<'_> {
             Span::raw(format!("{}", value.amount)),
             Span::raw(" - "),
             Span::raw(format!("{}", value.price)),
+            Span::raw(" - fee "),
+            Span::raw(format!(
+                "{} {}",
+                value.fee,
+                value.fee_asset.clone().unwrap_or_default()
+            )),
         ]);
         ListItem::new(vec![top_line, bot_line])
     }
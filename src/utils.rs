@@ -152,6 +152,69 @@ pub fn find_price_clusters(
     clustered_levels
 }
 
+// Volume Weighted Average Price since an anchor time (ms), fed with trades or candles
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Vwap {
+    pub anchor: u64,
+    volume: Decimal,
+    price_volume: Decimal,
+    square_price_volume: Decimal,
+}
+
+impl Vwap {
+    pub fn new(anchor: u64) -> Self {
+        Self {
+            anchor,
+            ..Default::default()
+        }
+    }
+
+    pub fn add(&mut self, price: Decimal, volume: Decimal) {
+        self.volume += volume;
+        self.price_volume += price * volume;
+        self.square_price_volume += price * price * volume;
+    }
+
+    pub fn value(&self) -> Option<Decimal> {
+        if self.volume > dec!(0) {
+            Some(self.price_volume / self.volume)
+        } else {
+            None
+        }
+    }
+
+    // Volume weighted standard deviation of the prices
+    pub fn std_dev(&self) -> Option<Decimal> {
+        let vwap = self.value()?;
+        let variance = (self.square_price_volume / self.volume - vwap * vwap).max(dec!(0));
+        Decimal::from_f64_retain(variance.to_f64()?.sqrt())
+    }
+
+    // (vwap - std_devs x std dev, vwap + std_devs x std dev)
+    pub fn bands(&self, std_devs: Decimal) -> Option<(Decimal, Decimal)> {
+        let vwap = self.value()?;
+        let width = self.std_dev()? * std_devs;
+        Some((vwap - width, vwap + width))
+    }
+}
+
+// Date (2025-01-31), RFC 3339 datetime or timestamp in ms
+pub fn parse_time(value: &str) -> Result<u64, String> {
+    if let Ok(time) = value.parse::<u64>() {
+        return Ok(time);
+    }
+    if let Ok(time) = chrono::DateTime::parse_from_rfc3339(value) {
+        return Ok(time.timestamp_millis() as u64);
+    }
+    chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map(|date| {
+            date.and_time(chrono::NaiveTime::MIN)
+                .and_utc()
+                .timestamp_millis() as u64
+        })
+        .map_err(|err| format!("Invalid time {value} : {err}"))
+}
+
 pub fn deserialize_decimal_pairs<'de, D>(
     deserializer: D,
 ) -> Result<Vec<(Decimal, Decimal)>, D::Error>
@@ -187,6 +250,23 @@ where
     formatted.serialize(serializer)
 }
 
+// Durations of the strategies params, in seconds
+pub fn deserialize_secs<'de, D>(deserializer: D) -> Result<std::time::Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(std::time::Duration::from_secs(u64::deserialize(
+        deserializer,
+    )?))
+}
+
+pub fn serialize_secs<S>(duration: &std::time::Duration, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    duration.as_secs().serialize(serializer)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -241,6 +321,21 @@ mod tests {
         assert_eq!(dec!(2), sma(&v, 3).unwrap().round_dp(1));
     }
 
+    #[test]
+    fn test_vwap() {
+        let mut vwap = Vwap::new(0);
+        assert_eq!(None, vwap.value());
+
+        vwap.add(dec!(100), dec!(1));
+        vwap.add(dec!(110), dec!(3));
+        assert_eq!(Some(dec!(107.5)), vwap.value());
+        assert_eq!(dec!(4.33), vwap.std_dev().unwrap().round_dp(2));
+
+        let (lower, upper) = vwap.bands(dec!(2)).unwrap();
+        assert_eq!(dec!(98.84), lower.round_dp(2));
+        assert_eq!(dec!(116.16), upper.round_dp(2));
+    }
+
     #[test]
     fn test_wsma() {
         let v = vec![dec!(1)];